@@ -0,0 +1,79 @@
+//! Measures throughput of the heaptrack-trim binary on a synthetic profile.
+//!
+//! Run with `cargo bench`. Every configuration is run once against the same input file, reading
+//! from a file on stdin and writing to /dev/null.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+/// Roughly 100MiB of profile, mostly "+" and "-" lines like real profiles.
+fn generate_profile(path: &std::path::Path) {
+    let mut file = BufWriter::new(File::create(path).unwrap());
+    writeln!(file, "v 10400 3").unwrap();
+    writeln!(file, "X ./bench").unwrap();
+
+    let mut allocation_infos = 0u64;
+    for timestamp in 0..10_000u64 {
+        writeln!(file, "c {:x}", timestamp).unwrap();
+        for i in 0..500u64 {
+            if i % 50 == 0 {
+                writeln!(file, "a {:x} 1", 16 + i).unwrap();
+                writeln!(file, "+ {:x}", allocation_infos).unwrap();
+                allocation_infos += 1;
+            } else {
+                writeln!(file, "+ {:x}", (timestamp * 7 + i) % allocation_infos).unwrap();
+                writeln!(file, "- {:x}", (timestamp * 13 + i) % allocation_infos).unwrap();
+            }
+        }
+    }
+}
+
+fn main() {
+    let path = std::env::temp_dir().join(format!("heaptrack-trim-bench-{}", std::process::id()));
+    generate_profile(&path);
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    // every size in the profile, so that the output is the same as without --exact-size, and only
+    // the cost of parsing "a" lines is measured
+    let mut all_sizes = vec!["--skip-seconds", "0", "--null-output"];
+    let sizes: Vec<String> = (0..500).step_by(50).map(|i| (16 + i).to_string()).collect();
+    for size in &sizes {
+        all_sizes.extend(["--exact-size", size]);
+    }
+
+    let configurations: &[&[&str]] = &[
+        &["--skip-seconds", "0"],
+        &["--skip-seconds", "0", "--null-output"],
+        &["--skip-seconds", "5"],
+        &["--skip-seconds", "5", "--null-output"],
+        &all_sizes,
+    ];
+
+    for args in configurations {
+        let start = Instant::now();
+        let status = Command::new(env!("CARGO_BIN_EXE_heaptrack-trim"))
+            .args(*args)
+            .stdin(File::open(&path).unwrap())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let elapsed = start.elapsed();
+        let name = if args.len() > 6 {
+            "--skip-seconds 0 --null-output --exact-size ...".to_owned()
+        } else {
+            args.join(" ")
+        };
+        println!(
+            "{:<48} {:>8.1} MiB/s",
+            name,
+            size as f64 / elapsed.as_secs_f64() / (1 << 20) as f64
+        );
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,95 @@
+//! Refusing stale profiles, for --max-age.
+//!
+//! heaptrack does not record when a profile was captured, neither in the header nor anywhere
+//! else, so the age is taken from the modification time of the file instead, which is when
+//! heaptrack last wrote to it, at the end of the capture.
+
+use std::io;
+use std::time::{Duration, SystemTime};
+
+/// Parse durations like "90s", "30m", "12h" or "7d". A number without unit is in seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let seconds_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid duration {:?}, expected a unit of s, m, h or d",
+                value
+            ))
+        }
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|e| format!("invalid duration {:?}: {}", value, e))?;
+    number
+        .checked_mul(seconds_per_unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration {:?} is too long", value))
+}
+
+/// Fail if a profile last modified at `modified` is older than `max_age` at `now`, or if its
+/// modification time cannot be read, as on filesystems without one. Modification times in the
+/// future count as new.
+pub fn check_age(
+    modified: io::Result<SystemTime>,
+    now: SystemTime,
+    max_age: Duration,
+) -> Result<(), String> {
+    let modified =
+        modified.map_err(|e| format!("--max-age: cannot read the modification time: {}", e))?;
+    let age = now.duration_since(modified).unwrap_or(Duration::ZERO);
+    if age > max_age {
+        return Err(format!(
+            "the profile was last written {}s ago, which is more than --max-age {}s",
+            age.as_secs(),
+            max_age.as_secs()
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+    assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+    assert_eq!(
+        parse_duration("7d"),
+        Ok(Duration::from_secs(7 * 24 * 60 * 60))
+    );
+    assert!(parse_duration("7w").is_err());
+    assert!(parse_duration("d").is_err());
+    assert!(parse_duration("-1s").is_err());
+    assert!(parse_duration("99999999999999999999d").is_err());
+}
+
+#[test]
+fn test_check_age() {
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100 * 24 * 60 * 60);
+    let day = Duration::from_secs(24 * 60 * 60);
+
+    assert_eq!(check_age(Ok(now - day), now, 2 * day), Ok(()));
+    assert_eq!(check_age(Ok(now - 2 * day), now, 2 * day), Ok(()));
+    assert_eq!(
+        check_age(Ok(now - 3 * day), now, 2 * day),
+        Err(
+            "the profile was last written 259200s ago, which is more than --max-age 172800s".into()
+        )
+    );
+    // clock skew between machines
+    assert_eq!(check_age(Ok(now + day), now, Duration::ZERO), Ok(()));
+
+    let unsupported = io::Error::new(io::ErrorKind::Unsupported, "no modification times");
+    assert_eq!(
+        check_age(Err(unsupported), now, day),
+        Err("--max-age: cannot read the modification time: no modification times".into())
+    );
+}
@@ -0,0 +1,131 @@
+//! Keeping only the allocations of sites that a baseline profile does not have, for
+//! --diff-against.
+//!
+//! A site is an allocation info identified like with --stable-ids: by its size and the module,
+//! function, file and line of every frame of its backtrace, so the same site has the same
+//! identity in profiles of different builds of the program and with different instruction
+//! pointers. Two sites that only differ in size are different sites.
+//!
+//! The baseline is read completely first to collect its sites, then the current profile gets a
+//! first pass of its own to find out which allocations belong to new sites.
+
+use std::collections::HashSet;
+use std::io::{self, BufRead};
+
+use crate::lifetime::AllocationFilter;
+use crate::parse_hex;
+use crate::stable_id::StableIds;
+
+/// The sites of all allocation infos in `input`. `on_alloc` is called for every "+" line with its
+/// allocation index and the sites so far.
+fn read_sites(
+    mut input: impl BufRead,
+    mut on_alloc: impl FnMut(&StableIds, u64),
+) -> io::Result<StableIds> {
+    let mut ids = StableIds::default();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(ids);
+        }
+        match line[0] {
+            b's' | b'i' | b't' | b'a' => ids.add(&line),
+            b'+' => {
+                let arg = line.trim_ascii_end().get(2..).unwrap_or_default();
+                if let Ok(allocation_index) = parse_hex(arg) {
+                    on_alloc(&ids, allocation_index);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// All sites of the baseline.
+pub fn baseline_sites(input: impl BufRead) -> io::Result<HashSet<u64>> {
+    let ids = read_sites(input, |_, _| {})?;
+    Ok(ids.allocation_info_ids().iter().copied().collect())
+}
+
+/// The first pass over the current profile: keep the allocations of sites that are not in
+/// `baseline`. Frees follow their allocation. Also returns how many of the sites of the profile
+/// are new, and how many sites it has.
+pub fn new_sites_filter(
+    input: impl BufRead,
+    baseline: &HashSet<u64>,
+) -> io::Result<(AllocationFilter, usize, usize)> {
+    // allocations are numbered by their "+" lines, like in `Pairing`
+    let mut keep = Vec::new();
+    let ids = read_sites(input, |ids, allocation_index| {
+        keep.push(!baseline.contains(&ids.id(allocation_index)));
+    })?;
+
+    let sites: HashSet<_> = ids.allocation_info_ids().iter().collect();
+    let new_sites = sites.iter().filter(|x| !baseline.contains(x)).count();
+    Ok((AllocationFilter::new(keep), new_sites, sites.len()))
+}
+
+#[test]
+fn test_new_sites_filter() {
+    use crate::lifetime::Pairing;
+
+    let baseline = baseline_sites(
+        &b"\
+v 10400 3
+s a.out
+s main
+s parse
+i 10 1 2 0 0
+i 20 1 3 0 0
+t 1 0
+t 2 1
+c 1
+a 8 2
++ 0
+a 10 1
+"[..],
+    )
+    .unwrap();
+
+    // a new build: main() itself still allocates 16 bytes, parse() now allocates 32 instead of
+    // 8, and run() is new
+    let current = b"\
+v 10400 3
+s run
+s a.out
+s parse
+s main
+i 15 2 4 0 0
+i 25 2 3 0 0
+i 35 2 1 0 0
+t 1 0
+t 2 1
+t 3 1
+c 1
+a 10 1
+a 20 2
+a 8 3
++ 0
++ 1
++ 2
+- 0
+- 1
++ 1
+";
+    let (filter, new_sites, sites) = new_sites_filter(&current[..], &baseline).unwrap();
+    assert_eq!((new_sites, sites), (2, 3));
+    let mut pairing = Pairing::default();
+    let kept: Vec<_> = [
+        (b'+', 0),
+        (b'+', 1),
+        (b'+', 2),
+        (b'-', 0),
+        (b'-', 1),
+        (b'+', 1),
+    ]
+    .into_iter()
+    .map(|(instruction, index)| filter.keep(&mut pairing, instruction, index))
+    .collect();
+    assert_eq!(kept, vec![false, true, true, false, true, true]);
+}
@@ -0,0 +1,100 @@
+//! Choosing the skip timestamp so that the output has a given size, for --max-output-percent.
+
+use std::io::{self, BufRead};
+
+use crate::parse_hex;
+
+/// How many bytes trimming removes, sampled at every "c" line.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SizeModel {
+    pub input_bytes: u64,
+    /// `(timestamp, bytes)`, where bytes is the size of all "+", "-" and "a" lines before the
+    /// timestamp. Trimming drops exactly those lines from before the first kept timestamp.
+    pub samples: Vec<(u64, u64)>,
+    /// the size of all "+", "-" and "a" lines in the profile
+    pub removable_bytes: u64,
+}
+
+impl SizeModel {
+    /// The predicted output size if the first kept timestamp is the sample with the given index,
+    /// or nothing is kept at all.
+    fn output_bytes(&self, first: usize) -> u64 {
+        let removed = self
+            .samples
+            .get(first)
+            .map_or(self.removable_bytes, |(_, bytes)| *bytes);
+        self.input_bytes - removed
+    }
+
+    /// Find the smallest skip timestamp for which the output is predicted to be at most
+    /// `max_output_bytes`. Candidates are the timestamps in the profile, like in
+    /// `LiveSizes::hotspot`. If even skipping everything is too large, the last timestamp.
+    pub fn skip_timestamp(&self, max_output_bytes: u64) -> u64 {
+        // index of the first sample after the candidate, where trimming starts
+        let mut first = 0;
+        for (timestamp, _) in &self.samples {
+            while first < self.samples.len() && self.samples[first].0 <= *timestamp {
+                first += 1;
+            }
+            if self.output_bytes(first) <= max_output_bytes {
+                return *timestamp;
+            }
+        }
+        self.samples.last().map_or(0, |(timestamp, _)| *timestamp)
+    }
+}
+
+pub fn size_model(mut input: impl BufRead) -> io::Result<SizeModel> {
+    let mut model = SizeModel::default();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read_bytes = input.read_until(b'\n', &mut line)? as u64;
+        if read_bytes == 0 {
+            return Ok(model);
+        }
+        model.input_bytes += read_bytes;
+        match line[0] {
+            b'+' | b'-' | b'a' => model.removable_bytes += read_bytes,
+            b'c' => {
+                if let Ok(timestamp) = parse_hex(line[1..].trim_ascii()) {
+                    model.samples.push((timestamp, model.removable_bytes));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_skip_timestamp() {
+    // 100 bytes of header, then every second 10 lines of 10 bytes each
+    let mut profile = format!("X {}\n", "x".repeat(97));
+    for second in 0..10 {
+        profile.push_str(&format!("c {:07x}\n", second * 1000));
+        for i in 0..10 {
+            profile.push_str(&format!("+ {:07x}\n", second * 10 + i));
+        }
+    }
+    let model = size_model(profile.as_bytes()).unwrap();
+    assert_eq!(model.input_bytes, 1200);
+    assert_eq!(model.removable_bytes, 1000);
+
+    // everything
+    assert_eq!(model.skip_timestamp(1200), 0);
+    // after skipping to timestamp 0, the events of second 0 are still removed
+    assert_eq!(model.skip_timestamp(1100), 0);
+    // half of the input: the header, all timestamps and the 40 events after timestamp 5000
+    assert_eq!(model.skip_timestamp(600), 5000);
+    assert_eq!(model.skip_timestamp(599), 6000);
+    // nothing fits, so keep as little as possible
+    assert_eq!(model.skip_timestamp(0), 9000);
+
+    // rewritten indices and timestamps only ever get shorter
+    let options = crate::TrimOptions {
+        skip_timestamp: model.skip_timestamp(model.input_bytes / 2),
+        ..crate::TrimOptions::default()
+    };
+    let stats = crate::run_main(&options, profile.as_bytes(), io::sink()).unwrap();
+    assert!(stats.output_bytes <= 600, "{}", stats.output_bytes);
+}
@@ -0,0 +1,99 @@
+//! Time, for throttling periodic output. Abstracted so tests don't have to sleep.
+
+use std::time::Instant;
+
+pub trait Clock {
+    fn now_ms(&self) -> u64;
+}
+
+pub struct SystemClock(Instant);
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock(Instant::now())
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        self.0.elapsed().as_millis() as u64
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockClock(std::rc::Rc<std::cell::Cell<u64>>);
+
+#[cfg(test)]
+impl MockClock {
+    pub fn advance(&self, ms: u64) {
+        self.0.set(self.0.get() + ms);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+/// Allows something to happen at most once per interval.
+pub struct Throttle {
+    clock: Box<dyn Clock>,
+    interval_ms: u64,
+    last_ms: Option<u64>,
+}
+
+impl Throttle {
+    pub fn new(clock: Box<dyn Clock>, interval_ms: u64) -> Self {
+        Throttle {
+            clock,
+            interval_ms,
+            last_ms: None,
+        }
+    }
+
+    /// True on the first call, and then whenever at least the interval has passed since the last
+    /// time it was true.
+    pub fn ready(&mut self) -> bool {
+        let now = self.clock.now_ms();
+        if self
+            .last_ms
+            .is_some_and(|last| now.saturating_sub(last) < self.interval_ms)
+        {
+            return false;
+        }
+        self.last_ms = Some(now);
+        true
+    }
+}
+
+#[test]
+fn test_throttle() {
+    let clock = MockClock::default();
+    let mut throttle = Throttle::new(Box::new(clock.clone()), 1000);
+
+    assert!(throttle.ready());
+    assert!(!throttle.ready());
+    clock.advance(999);
+    assert!(!throttle.ready());
+    clock.advance(1);
+    assert!(throttle.ready());
+    clock.advance(1500);
+    assert!(throttle.ready());
+    // the interval counts from the last time it was ready, not from a fixed grid
+    clock.advance(600);
+    assert!(!throttle.ready());
+    clock.advance(400);
+    assert!(throttle.ready());
+}
+
+#[test]
+fn test_throttle_zero() {
+    let clock = MockClock::default();
+    let mut throttle = Throttle::new(Box::new(clock), 0);
+    assert!(throttle.ready());
+    assert!(throttle.ready());
+}
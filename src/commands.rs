@@ -0,0 +1,119 @@
+//! Trim commands read from stdin, for --commands-from-stdin.
+//!
+//! Every line is one command, written as options like on the command line, separated by spaces:
+//!
+//! ```text
+//! --skip-seconds 10
+//! --start-from-index 1f --exact-size 16 --exact-size 32
+//! --skip-seconds 5 --min-trace-depth 3 --rss-band 0:1000000 --preserve-time
+//! ```
+//!
+//! A command trims with either --skip-seconds or --start-from-index, and optionally the filters
+//! --exact-size, --min-trace-depth and --rss-band, and --preserve-time. Filters given in a command
+//! replace the same filter from the command line, the other options of the command line apply to
+//! every command. Empty lines and lines starting with "#" are ignored.
+
+use crate::{parse_hex_arg, rss};
+
+/// the options of commands that take a value
+const OPTIONS: [&str; 5] = [
+    "--skip-seconds",
+    "--start-from-index",
+    "--exact-size",
+    "--min-trace-depth",
+    "--rss-band",
+];
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TrimCommand {
+    pub skip_seconds: u64,
+    pub start_from_index: Option<u64>,
+    pub exact_sizes: Vec<u64>,
+    pub min_trace_depth: Option<u64>,
+    pub rss_band: Option<(u64, u64)>,
+    pub preserve_time: bool,
+}
+
+/// Parse a line of input, None if it is empty or a comment.
+pub fn parse_command(line: &str) -> Result<Option<TrimCommand>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut command = TrimCommand::default();
+    let mut skip_seconds = None;
+    let mut args = line.split_whitespace();
+    while let Some(option) = args.next() {
+        if option == "--preserve-time" {
+            command.preserve_time = true;
+            continue;
+        }
+        if !OPTIONS.contains(&option) {
+            return Err(format!("unknown option {:?}", option));
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", option))?;
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid number {:?} for {}", value, option))
+        };
+        match option {
+            "--skip-seconds" => skip_seconds = Some(number()?),
+            "--start-from-index" => command.start_from_index = Some(parse_hex_arg(value)?),
+            "--exact-size" => command.exact_sizes.push(number()?),
+            "--min-trace-depth" => command.min_trace_depth = Some(number()?),
+            "--rss-band" => command.rss_band = Some(rss::parse_rss_band(value)?),
+            _ => unreachable!(),
+        }
+    }
+
+    match (skip_seconds, command.start_from_index) {
+        (Some(skip_seconds), None) => command.skip_seconds = skip_seconds,
+        (None, Some(_)) => {}
+        _ => return Err("exactly one of --skip-seconds or --start-from-index is required".into()),
+    }
+    Ok(Some(command))
+}
+
+#[test]
+fn test_parse_command() {
+    assert_eq!(parse_command("  \n"), Ok(None));
+    assert_eq!(parse_command("# --skip-seconds 1"), Ok(None));
+    assert_eq!(
+        parse_command("--skip-seconds 10"),
+        Ok(Some(TrimCommand {
+            skip_seconds: 10,
+            ..TrimCommand::default()
+        }))
+    );
+    assert_eq!(
+        parse_command("--start-from-index 1f --exact-size 16 --exact-size 32 --preserve-time\n"),
+        Ok(Some(TrimCommand {
+            start_from_index: Some(0x1f),
+            exact_sizes: vec![16, 32],
+            preserve_time: true,
+            ..TrimCommand::default()
+        }))
+    );
+    assert_eq!(
+        parse_command("--skip-seconds 1 --rss-band 1:2 --min-trace-depth 3"),
+        Ok(Some(TrimCommand {
+            skip_seconds: 1,
+            rss_band: Some((1, 2)),
+            min_trace_depth: Some(3),
+            ..TrimCommand::default()
+        }))
+    );
+
+    assert!(parse_command("--exact-size 16").is_err());
+    assert!(parse_command("--skip-seconds 1 --start-from-index 0").is_err());
+    assert!(parse_command("--skip-seconds").is_err());
+    assert!(parse_command("--skip-seconds x").is_err());
+    assert_eq!(
+        parse_command("--skip-seconds 1 --watch"),
+        Err("unknown option \"--watch\"".to_owned())
+    );
+}
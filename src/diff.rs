@@ -0,0 +1,83 @@
+//! Recording which lines of the input were removed, for --diff-out.
+//!
+//! The format borrows the deletion commands of `diff`'s normal output, without the deleted lines
+//! themselves: one line per range of consecutive removed input lines, "3,7d" for lines 3 to 7 and
+//! "3d" for only line 3. Lines are numbered from 1. "12,$d" means that everything from line 12 to
+//! the end of the input was removed without reading it.
+//!
+//! Lines that were kept but rewritten, like timestamps and rebased allocation indices, are not
+//! recorded, so this is not a patch that turns the input into the output.
+
+use std::io::{self, Write};
+
+pub struct RemovedLines<W> {
+    output: W,
+    /// the first line of the current range of removed lines
+    start: Option<u64>,
+    /// the last line passed to `line`
+    lineno: u64,
+}
+
+impl<W: Write> RemovedLines<W> {
+    pub fn new(output: W) -> Self {
+        RemovedLines {
+            output,
+            start: None,
+            lineno: 0,
+        }
+    }
+
+    /// Has to be called for every input line, in order.
+    pub fn line(&mut self, lineno: u64, is_kept: bool) -> io::Result<()> {
+        self.lineno = lineno;
+        match (self.start, is_kept) {
+            (None, false) => self.start = Some(lineno),
+            (Some(start), true) => {
+                self.start = None;
+                self.write_range(start, lineno - 1)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn write_range(&mut self, start: u64, end: u64) -> io::Result<()> {
+        if start == end {
+            writeln!(self.output, "{}d", start)
+        } else {
+            writeln!(self.output, "{},{}d", start, end)
+        }
+    }
+
+    /// `is_truncated` means that the input after the last line passed to `line` was not read and
+    /// is removed too.
+    pub fn finish(mut self, is_truncated: bool) -> io::Result<W> {
+        match (self.start, is_truncated) {
+            (Some(start), true) => writeln!(self.output, "{},$d", start)?,
+            (None, true) => writeln!(self.output, "{},$d", self.lineno + 1)?,
+            (Some(start), false) => self.write_range(start, self.lineno)?,
+            (None, false) => {}
+        }
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+#[test]
+fn test_removed_lines() {
+    let diff = |kept: &[bool], is_truncated| {
+        let mut removed = RemovedLines::new(Vec::new());
+        for (i, is_kept) in kept.iter().enumerate() {
+            removed.line(i as u64 + 1, *is_kept).unwrap();
+        }
+        String::from_utf8(removed.finish(is_truncated).unwrap()).unwrap()
+    };
+
+    assert_eq!(diff(&[true, true], false), "");
+    assert_eq!(
+        diff(&[false, true, false, false, true, false], false),
+        "1d\n3,4d\n6d\n"
+    );
+    assert_eq!(diff(&[true, false, false], true), "2,$d\n");
+    assert_eq!(diff(&[true, true], true), "3,$d\n");
+}
@@ -0,0 +1,197 @@
+//! Guessing the size of a trim from a small part of the input.
+//!
+//! Only the first and the last `sample_size` bytes are read. The head and tail together tell how
+//! many bytes are "+", "-" and "a" lines, which are the only lines trimming removes, and the tail
+//! tells when the profile ends. Assuming that allocations and frees are spread evenly over the
+//! whole profile, the share of them after the skipped part is then the share of the profile's
+//! duration after it. Since the kept ones are rebased to start at 0, their lines are assumed to be
+//! as long as the ones in the head.
+//!
+//! This is off when the profile is not evenly busy, for example when most allocations happen
+//! during startup.
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::parse_hex;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Estimate {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// the duration of the trimmed profile, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = if self.input_bytes == 0 {
+            0.0
+        } else {
+            self.output_bytes as f64 * 100.0 / self.input_bytes as f64
+        };
+        write!(
+            f,
+            "estimated output: {} bytes ({:.1}%), {}ms long",
+            self.output_bytes, percent, self.duration_ms
+        )
+    }
+}
+
+#[derive(Default)]
+struct Sample {
+    bytes: u64,
+    event_bytes: u64,
+    events: u64,
+    first_timestamp: Option<u64>,
+    last_timestamp: Option<u64>,
+}
+
+impl Sample {
+    /// Count the complete lines in `data`. If `partial_start`, the first line is cut off and
+    /// ignored.
+    fn add(&mut self, data: &[u8], partial_start: bool) {
+        let mut lines = data.split_inclusive(|x| *x == b'\n');
+        if partial_start {
+            lines.next();
+        }
+        for line in lines {
+            if !line.ends_with(b"\n") {
+                break;
+            }
+            self.bytes += line.len() as u64;
+            match line[0] {
+                b'+' | b'-' | b'a' => {
+                    self.events += 1;
+                    self.event_bytes += line.len() as u64;
+                }
+                b'c' => {
+                    if let Ok(timestamp) = parse_hex(line[1..].trim_ascii()) {
+                        self.first_timestamp.get_or_insert(timestamp);
+                        self.last_timestamp = Some(timestamp);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn estimate(
+    mut input: impl Read + Seek,
+    skip_timestamp: u64,
+    sample_size: u64,
+) -> io::Result<Estimate> {
+    let input_bytes = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(0))?;
+
+    let mut head = Sample::default();
+    let mut tail = Sample::default();
+    let mut data = Vec::new();
+    (&mut input).take(sample_size).read_to_end(&mut data)?;
+    let tail_start = input_bytes.saturating_sub(sample_size);
+    if tail_start <= sample_size {
+        // the head and tail touch or overlap, read everything as the head, so that no line is
+        // split between them
+        input.read_to_end(&mut data)?;
+        head.add(&data, false);
+    } else {
+        head.add(&data, false);
+        // from the byte before the tail, which tells whether it starts in the middle of a line
+        input.seek(SeekFrom::Start(tail_start - 1))?;
+        data.clear();
+        input.read_to_end(&mut data)?;
+        tail.add(&data[1..], data[0] != b'\n');
+    }
+
+    let first_timestamp = head.first_timestamp.unwrap_or(0);
+    let last_timestamp = tail.last_timestamp.or(head.last_timestamp).unwrap_or(0);
+    let kept_share = if last_timestamp <= first_timestamp {
+        1.0
+    } else {
+        let kept = last_timestamp.saturating_sub(skip_timestamp.max(first_timestamp));
+        kept as f64 / (last_timestamp - first_timestamp) as f64
+    };
+    let ratio = |part: u64, total: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            part as f64 / total as f64
+        }
+    };
+
+    if head.events + tail.events == 0 {
+        // nothing that trimming removes, only timestamps are rewritten
+        return Ok(Estimate {
+            input_bytes,
+            output_bytes: input_bytes,
+            duration_ms: last_timestamp.saturating_sub(skip_timestamp),
+        });
+    }
+
+    let event_bytes =
+        input_bytes as f64 * ratio(head.event_bytes + tail.event_bytes, head.bytes + tail.bytes);
+    let events = event_bytes
+        / ratio(
+            head.event_bytes + tail.event_bytes,
+            head.events + tail.events,
+        );
+    // rebased allocation indices are as short as the ones at the start of the input
+    let kept_event_bytes = events * kept_share * ratio(head.event_bytes, head.events);
+    let output_bytes = input_bytes as f64 - event_bytes + kept_event_bytes;
+    Ok(Estimate {
+        input_bytes,
+        output_bytes: output_bytes as u64,
+        duration_ms: last_timestamp.saturating_sub(skip_timestamp),
+    })
+}
+
+#[test]
+fn test_estimate() {
+    use std::io::Cursor;
+
+    // 5 allocations and frees every millisecond, for 10 seconds
+    let mut profile = b"v 10400 3\nX ./a.out\ns main\ni 1 1\nt 1 0\n".to_vec();
+    for timestamp in 0..10_000u64 {
+        profile.extend_from_slice(format!("c {:x}\na 8 1\n", timestamp).as_bytes());
+        for _ in 0..5 {
+            profile.extend_from_slice(format!("+ {:x}\n- {:x}\n", timestamp, timestamp).as_bytes());
+        }
+    }
+
+    let options = crate::TrimOptions {
+        skip_timestamp: 4_000,
+        ..crate::TrimOptions::default()
+    };
+    let output = crate::run_main(&options, &profile[..], io::sink()).unwrap();
+    let guess = estimate(Cursor::new(&profile), 4_000, 1 << 14).unwrap();
+    assert_eq!(guess.input_bytes, profile.len() as u64);
+    assert_eq!(guess.duration_ms, 5_999);
+    let error = guess.output_bytes as f64 / output.output_bytes as f64 - 1.0;
+    assert!(error.abs() < 0.1, "{:?} vs {:?}", guess, output);
+
+    // small inputs are read entirely, the head and tail must not overlap
+    let small = b"c 0\n+ 0\nc a\n- 0\n";
+    let guess = estimate(Cursor::new(&small), 5, 1 << 14).unwrap();
+    assert_eq!(guess.duration_ms, 5);
+    assert_eq!(guess.output_bytes, 8 + 8 / 2);
+
+    // the head ends in the middle of the "s" line, whose rest must not count as a "+" line
+    let split = b"c 0\n+ 0\ns aaaaa+ 1\nc a\n- 0\n";
+    assert_eq!(
+        estimate(Cursor::new(&split), 5, 16).unwrap(),
+        estimate(Cursor::new(&split), 5, 1 << 14).unwrap()
+    );
+    // a line longer than the head and tail, and a tail that starts right after a newline
+    let mut long = b"c 0\n+ 0\ns ".to_vec();
+    long.extend_from_slice(&[b'a'; 100]);
+    long.extend_from_slice(b"\n- 0\nc a\n");
+    let guess = estimate(Cursor::new(&long), 5, 10).unwrap();
+    assert_eq!(guess.duration_ms, 5);
+
+    // without allocations, frees and allocation infos, everything is kept
+    let no_events = b"v 10400 3\ns main\nc 0\nc a\n";
+    let guess = estimate(Cursor::new(&no_events), 5, 1 << 14).unwrap();
+    assert_eq!(guess.output_bytes, no_events.len() as u64);
+    assert_eq!(guess.duration_ms, 5);
+}
@@ -0,0 +1,596 @@
+//! Writing the trimmed profile in formats other than heaptrack's own, for --export.
+//!
+//! Trimming always produces heaptrack's text format. `Export` parses that output again and hands
+//! every command to an `OutputFormat`, so adding a format only means implementing that trait and
+//! adding it to `ExportFormat`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+
+use crate::reader::{parse_command, Command};
+use crate::stats::write_json_string;
+use crate::{parse_hex, write_hex};
+
+pub trait OutputFormat {
+    /// Called for every line of the trimmed profile, in order.
+    fn write_command(&mut self, output: &mut dyn Write, command: &Command) -> io::Result<()>;
+
+    /// Called once after the last line.
+    fn finish(&mut self, _output: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// heaptrack's text format, the same as not exporting at all.
+pub struct HeaptrackText;
+
+impl OutputFormat for HeaptrackText {
+    fn write_command(&mut self, mut output: &mut dyn Write, command: &Command) -> io::Result<()> {
+        match command {
+            Command::Version(version) => {
+                output.write_all(b"v ")?;
+                write_hex(&mut output, version.heaptrack)?;
+                output.write_all(b" ")?;
+                write_hex(&mut output, version.file_format)?;
+            }
+            Command::Timestamp(timestamp) => {
+                output.write_all(b"c ")?;
+                write_hex(&mut output, *timestamp)?;
+            }
+            Command::AllocationInfo { size, trace } => {
+                output.write_all(b"a ")?;
+                write_hex(&mut output, *size)?;
+                output.write_all(b" ")?;
+                write_hex(&mut output, *trace)?;
+            }
+            Command::Alloc(index) => {
+                output.write_all(b"+ ")?;
+                write_hex(&mut output, *index)?;
+            }
+            Command::Free(index) => {
+                output.write_all(b"- ")?;
+                write_hex(&mut output, *index)?;
+            }
+            Command::Other(line) => output.write_all(line)?,
+        }
+        output.write_all(b"\n")
+    }
+}
+
+/// One JSON object per line and command, with a "type" and the arguments in decimal.
+pub struct Ndjson;
+
+impl OutputFormat for Ndjson {
+    fn write_command(&mut self, output: &mut dyn Write, command: &Command) -> io::Result<()> {
+        match command {
+            Command::Version(version) => write!(
+                output,
+                "{{\"type\":\"version\",\"heaptrack\":{},\"file_format\":{}}}",
+                version.heaptrack, version.file_format
+            )?,
+            Command::Timestamp(timestamp) => {
+                write!(output, "{{\"type\":\"timestamp\",\"ms\":{}}}", timestamp)?
+            }
+            Command::AllocationInfo { size, trace } => write!(
+                output,
+                "{{\"type\":\"allocation_info\",\"size\":{},\"trace\":{}}}",
+                size, trace
+            )?,
+            Command::Alloc(index) => write!(output, "{{\"type\":\"alloc\",\"index\":{}}}", index)?,
+            Command::Free(index) => write!(output, "{{\"type\":\"free\",\"index\":{}}}", index)?,
+            Command::Other(line) => {
+                output.write_all(b"{\"type\":\"other\",\"line\":")?;
+                write_json_string(&mut *output, &String::from_utf8_lossy(line))?;
+                output.write_all(b"}")?;
+            }
+        }
+        output.write_all(b"\n")
+    }
+}
+
+/// heaptrack's text format, except that runs of consecutive "+" and "-" lines are packed into
+/// one line each. heaptrack-gui cannot open this, use `unpack` to get a normal profile back.
+///
+/// A packed line is "p " followed by the events of the run without any separator, every event
+/// being its "+" or "-" and the allocation index in hex:
+///
+/// ```text
+/// + 0
+/// + 1
+/// - 0        =>  p +0+1-0
+/// c 3e8          c 3e8
+/// - 1            p -1
+/// ```
+///
+/// A run ends at any other line, or after `events_per_line` events.
+pub struct Packed {
+    events_per_line: usize,
+    /// events written to the current "p" line so far
+    events_in_line: usize,
+}
+
+impl Packed {
+    pub fn new(events_per_line: usize) -> Self {
+        Packed {
+            events_per_line,
+            events_in_line: 0,
+        }
+    }
+
+    fn end_line(&mut self, output: &mut dyn Write) -> io::Result<()> {
+        if self.events_in_line > 0 {
+            self.events_in_line = 0;
+            output.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl OutputFormat for Packed {
+    fn write_command(&mut self, mut output: &mut dyn Write, command: &Command) -> io::Result<()> {
+        let (instruction, index) = match command {
+            Command::Alloc(index) => (b'+', *index),
+            Command::Free(index) => (b'-', *index),
+            _ => {
+                self.end_line(output)?;
+                return HeaptrackText.write_command(output, command);
+            }
+        };
+
+        if self.events_in_line == 0 {
+            output.write_all(b"p ")?;
+        }
+        output.write_all(&[instruction])?;
+        write_hex(&mut output, index)?;
+        self.events_in_line += 1;
+        if self.events_in_line == self.events_per_line {
+            self.end_line(output)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut dyn Write) -> io::Result<()> {
+        self.end_line(output)
+    }
+}
+
+/// Turn a profile written by `Packed` back into heaptrack's text format, by expanding every "p"
+/// line into one line per event. Other lines are copied as they are.
+pub fn unpack(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        let Some(events) = line.strip_prefix(b"p ") else {
+            output.write_all(&line)?;
+            continue;
+        };
+
+        let events = events.trim_ascii_end();
+        let malformed = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "malformed packed line {:?}",
+                    String::from_utf8_lossy(line.trim_ascii_end())
+                ),
+            )
+        };
+        if !events.starts_with(b"+") && !events.starts_with(b"-") {
+            return Err(malformed());
+        }
+        // every event starts with its sign, so splitting before each sign yields one event each
+        let mut start = 0;
+        while start < events.len() {
+            let end = events[start + 1..]
+                .iter()
+                .position(|x| *x == b'+' || *x == b'-')
+                .map_or(events.len(), |x| start + 1 + x);
+            let digits = &events[start + 1..end];
+            if digits.is_empty() {
+                return Err(malformed());
+            }
+            let index = parse_hex(digits).map_err(|()| malformed())?;
+            output.write_all(&[events[start], b' '])?;
+            write_hex(&mut output, index)?;
+            output.write_all(b"\n")?;
+            start = end;
+        }
+    }
+}
+
+/// heaptrack's text format with only "a", "+" and "-" lines, and the "c" lines before them.
+/// heaptrack-gui cannot open this, it lacks the header and all strings and traces.
+///
+/// A "c" line is only written if an event follows it before the next "c" line, so every event
+/// still has the timestamp it happened at.
+#[derive(Default)]
+pub struct AllocationsOnly {
+    /// the last timestamp, if it was not written yet
+    timestamp: Option<u64>,
+}
+
+impl OutputFormat for AllocationsOnly {
+    fn write_command(&mut self, output: &mut dyn Write, command: &Command) -> io::Result<()> {
+        match command {
+            Command::Timestamp(timestamp) => {
+                self.timestamp = Some(*timestamp);
+                Ok(())
+            }
+            Command::AllocationInfo { .. } | Command::Alloc(_) | Command::Free(_) => {
+                if let Some(timestamp) = self.timestamp.take() {
+                    HeaptrackText.write_command(output, &Command::Timestamp(timestamp))?;
+                }
+                HeaptrackText.write_command(output, command)
+            }
+            Command::Version(_) | Command::Other(_) => Ok(()),
+        }
+    }
+}
+
+/// Folded stacks, the input of flamegraph tools like inferno or flamegraph.pl: one line per
+/// distinct backtrace, its frames from the outermost to the innermost separated by ";", followed
+/// by the sum of the sizes of all its allocations in the output:
+///
+/// ```text
+/// main;parse_args;malloc 4096
+/// ```
+///
+/// Frees are ignored, so this is how much every backtrace allocated, not how much it still holds.
+/// A frame is named by the first function of its "i" line, functions inlined into it are not
+/// shown, and by its module if it has no function. Traces that end up with the same names are
+/// summed into one line. Since heaptrack defines strings, instruction pointers and traces before
+/// using them, symbols are resolved while reading, but all of them are kept in memory and lines
+/// are only written at the end, once every allocation has been counted.
+#[derive(Default)]
+pub struct Folded {
+    /// the text of every "s" line
+    strings: Vec<Vec<u8>>,
+    /// the string naming every instruction pointer
+    frames: Vec<u64>,
+    /// the instruction pointer and parent of every trace
+    traces: Vec<(u64, u64)>,
+    /// the size and trace of every allocation info
+    allocation_infos: Vec<(u64, u64)>,
+    /// bytes allocated per trace
+    bytes: HashMap<u64, u64>,
+}
+
+impl Folded {
+    fn string(&self, index: u64) -> &[u8] {
+        // numbered from 1
+        (index as usize)
+            .checked_sub(1)
+            .and_then(|x| self.strings.get(x))
+            .map_or(b"[unknown]", |x| &x[..])
+    }
+
+    /// `trace` from the root, with ";" between frames.
+    fn stack(&self, mut trace: u64) -> Vec<u8> {
+        let mut frames = Vec::new();
+        // traces can only reference earlier ones, which bounds the loop even in broken profiles
+        while let Some(&(ip, parent)) = (trace as usize)
+            .checked_sub(1)
+            .and_then(|x| self.traces.get(x))
+            .filter(|_| frames.len() < self.traces.len())
+        {
+            let name = (ip as usize)
+                .checked_sub(1)
+                .and_then(|x| self.frames.get(x))
+                .map_or(&b"[unknown]"[..], |x| self.string(*x));
+            frames.push(name);
+            trace = parent;
+        }
+        let mut stack = Vec::new();
+        for (i, frame) in frames.iter().rev().enumerate() {
+            if i > 0 {
+                stack.push(b';');
+            }
+            // ";" separates frames, and a newline would end the line
+            stack.extend(frame.iter().map(|x| match x {
+                b';' => b':',
+                b'\n' => b' ',
+                x => *x,
+            }));
+        }
+        stack
+    }
+}
+
+impl OutputFormat for Folded {
+    fn write_command(&mut self, _output: &mut dyn Write, command: &Command) -> io::Result<()> {
+        match command {
+            Command::AllocationInfo { size, trace } => self.allocation_infos.push((*size, *trace)),
+            Command::Alloc(index) => {
+                if let Some(&(size, trace)) = self.allocation_infos.get(*index as usize) {
+                    *self.bytes.entry(trace).or_default() += size;
+                }
+            }
+            Command::Other(line) => {
+                let line = line.trim_ascii_end();
+                let mut args = line
+                    .split(|x| *x == b' ')
+                    .skip(1)
+                    .map(|x| parse_hex(x).unwrap_or(0));
+                match line.first() {
+                    Some(b's') => self
+                        .strings
+                        .push(line.get(2..).unwrap_or_default().to_vec()),
+                    Some(b'i') => {
+                        // "i <ip> <module> [<function> <file> <line>]..."
+                        let _ip = args.next();
+                        let module = args.next().unwrap_or(0);
+                        let function = args.next().filter(|x| *x != 0);
+                        self.frames.push(function.unwrap_or(module));
+                    }
+                    Some(b't') => {
+                        let ip = args.next().unwrap_or(0);
+                        let parent = args.next().unwrap_or(0);
+                        self.traces.push((ip, parent));
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut dyn Write) -> io::Result<()> {
+        let mut stacks = BTreeMap::<Vec<u8>, u64>::new();
+        for (trace, bytes) in &self.bytes {
+            *stacks.entry(self.stack(*trace)).or_default() += bytes;
+        }
+        for (stack, bytes) in stacks {
+            // allocations without a backtrace
+            if stack.is_empty() {
+                output.write_all(b"[unknown]")?;
+            }
+            output.write_all(&stack)?;
+            writeln!(output, " {}", bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// The formats --export accepts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Heaptrack,
+    Ndjson,
+    /// with how many events per line at most
+    Packed(usize),
+    AllocationsOnly,
+    Folded,
+}
+
+impl ExportFormat {
+    /// How many events per line --export packed writes, unless --allocations-per-line is given.
+    pub const DEFAULT_EVENTS_PER_LINE: usize = 64;
+
+    /// The name it is given as on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Heaptrack => "heaptrack",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Packed(_) => "packed",
+            ExportFormat::AllocationsOnly => "allocations-only",
+            ExportFormat::Folded => "folded",
+        }
+    }
+
+    pub fn output_format(self) -> Box<dyn OutputFormat> {
+        match self {
+            ExportFormat::Heaptrack => Box::new(HeaptrackText),
+            ExportFormat::Ndjson => Box::new(Ndjson),
+            ExportFormat::Packed(events_per_line) => Box::new(Packed::new(events_per_line)),
+            ExportFormat::AllocationsOnly => Box::new(AllocationsOnly::default()),
+            ExportFormat::Folded => Box::new(Folded::default()),
+        }
+    }
+}
+
+pub fn parse_export_format(value: &str) -> Result<ExportFormat, String> {
+    match value {
+        "heaptrack" => Ok(ExportFormat::Heaptrack),
+        "ndjson" => Ok(ExportFormat::Ndjson),
+        "packed" => Ok(ExportFormat::Packed(ExportFormat::DEFAULT_EVENTS_PER_LINE)),
+        "allocations-only" => Ok(ExportFormat::AllocationsOnly),
+        "folded" => Ok(ExportFormat::Folded),
+        _ => Err(format!(
+            "unknown --export format {:?}, expected heaptrack, ndjson, packed, allocations-only \
+             or folded",
+            value
+        )),
+    }
+}
+
+/// Parses the heaptrack text written to it, and writes it to `inner` in another format. Call
+/// `finish` after the last write.
+pub struct Export<W> {
+    inner: W,
+    format: Box<dyn OutputFormat>,
+    line: Vec<u8>,
+}
+
+impl<W: Write> Export<W> {
+    pub fn new(inner: W, format: Box<dyn OutputFormat>) -> Self {
+        Export {
+            inner,
+            format,
+            line: Vec::new(),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.format.finish(&mut self.inner)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Export<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.split_inclusive(|x| *x == b'\n') {
+            self.line.extend_from_slice(chunk);
+            if !self.line.ends_with(b"\n") {
+                continue;
+            }
+
+            let command = parse_command(&self.line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "cannot export malformed line {:?}",
+                        String::from_utf8_lossy(self.line.trim_ascii_end())
+                    ),
+                )
+            })?;
+            self.format.write_command(&mut self.inner, &command)?;
+            self.line.clear();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_export() {
+    let profile = b"v 10400 3\nX ./a.out\nc 3e8\na 10 1\n+ 0\n- 0\n";
+    let export = |format: &str| {
+        let format = parse_export_format(format).unwrap().output_format();
+        let mut export = Export::new(Vec::new(), format);
+        // in pieces, like a BufWriter would
+        export.write_all(&profile[..7]).unwrap();
+        export.write_all(&profile[7..]).unwrap();
+        String::from_utf8(export.finish().unwrap()).unwrap()
+    };
+
+    assert_eq!(export("heaptrack").as_bytes(), profile);
+    assert_eq!(
+        export("ndjson"),
+        "\
+{\"type\":\"version\",\"heaptrack\":66560,\"file_format\":3}
+{\"type\":\"other\",\"line\":\"X ./a.out\"}
+{\"type\":\"timestamp\",\"ms\":1000}
+{\"type\":\"allocation_info\",\"size\":16,\"trace\":1}
+{\"type\":\"alloc\",\"index\":0}
+{\"type\":\"free\",\"index\":0}
+"
+    );
+    assert!(parse_export_format("chrome-trace").is_err());
+}
+
+#[test]
+fn test_packed() {
+    let profile = b"v 10400 3\nc 1\na 10 1\n+ 0\n+ 1\n- 0\n+ 1a\nc 3e8\n- 1\n- 1a\n";
+    let pack = |events_per_line| {
+        let mut export = Export::new(Vec::new(), Box::new(Packed::new(events_per_line)));
+        export.write_all(profile).unwrap();
+        export.finish().unwrap()
+    };
+
+    let packed = pack(3);
+    assert_eq!(
+        String::from_utf8(packed.clone()).unwrap(),
+        "v 10400 3\nc 1\na 10 1\np +0+1-0\np +1a\nc 3e8\np -1-1a\n"
+    );
+    let mut unpacked = Vec::new();
+    unpack(&packed[..], &mut unpacked).unwrap();
+    assert_eq!(unpacked, profile);
+
+    for events_per_line in [1, 2, 64] {
+        let mut unpacked = Vec::new();
+        unpack(&pack(events_per_line)[..], &mut unpacked).unwrap();
+        assert_eq!(unpacked, profile);
+    }
+
+    assert!(unpack(&b"p 0+1\n"[..], io::sink()).is_err());
+    assert!(unpack(&b"p +0-\n"[..], io::sink()).is_err());
+    assert!(unpack(&b"p \n"[..], io::sink()).is_err());
+}
+
+#[test]
+fn test_allocations_only() {
+    let profile = b"\
+v 10400 3
+X ./a.out
+I 1000 10
+s main
+i 1234 1
+t 1 0
+c 1
+R 1000
+c 2
+a 10 1
++ 0
+# comment
+c 3
+c 4
+- 0
+";
+    let mut export = Export::new(Vec::new(), Box::new(AllocationsOnly::default()));
+    export.write_all(profile).unwrap();
+    let output = String::from_utf8(export.finish().unwrap()).unwrap();
+    assert_eq!(output, "c 2\na 10 1\n+ 0\nc 4\n- 0\n");
+    assert!(output
+        .lines()
+        .all(|line| line.starts_with(['a', '+', '-', 'c'])));
+}
+
+#[test]
+fn test_folded() {
+    // main calls parse (with an inlined helper) and run, both allocate, and so does a function
+    // without a name in libc.so
+    let profile = b"\
+v 10400 3
+s libfoo.so
+s main
+s foo.c
+s parse;v2
+s helper
+s run
+s libc.so
+i 10 1 2 3 1
+i 20 1 4 3 5 5 3 6
+i 30 1 6 3 7
+i 40 7
+t 1 0
+t 2 1
+t 3 1
+t 4 3
+c 1
+a 10 2
+a 8 3
+a 20 2
+a 4 4
+a 1 0
++ 0
++ 1
++ 2
+- 0
++ 0
++ 1
++ 3
++ 4
++ 9
+";
+    let mut export = Export::new(Vec::new(), Box::new(Folded::default()));
+    export.write_all(profile).unwrap();
+    let output = String::from_utf8(export.finish().unwrap()).unwrap();
+    // trace 2 allocated 0x10 twice and 0x20 once, the undefined allocation info is ignored
+    assert_eq!(
+        output,
+        "\
+[unknown] 1
+main;parse:v2 64
+main;run 16
+main;run;libc.so 4
+"
+    );
+}
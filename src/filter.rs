@@ -0,0 +1,286 @@
+//! The filters that drop "+" and "-" lines, applied in sequence, and how much each of them
+//! dropped, for --filter-stats.
+//!
+//! The chain always starts with the trimming itself, which drops the events before the kept part
+//! and those referring to allocations from before it. After that come the filters given on the
+//! command line, always in this order, no matter the order of the options:
+//!
+//! 1. --lifetime-percentile
+//! 2. --min-size-percentile
+//! 3. --min-alloc-rate
+//! 4. --max-traces
+//! 5. --min-realloc-growth
+//! 6. --diff-against
+//! 7. --rss-band
+//! 8. --exact-size
+//! 9. --min-trace-depth
+//!
+//! An event is kept only if every stage keeps it. Every stage still sees every event, since most
+//! of them have to follow the allocations and frees of the whole profile, but a dropped event
+//! only counts for the first stage that dropped it.
+
+use std::io::{self, Write};
+
+use crate::lifetime::{AllocationFilter, Pairing};
+use crate::stats::write_json_string;
+
+pub trait EventFilter {
+    /// Whether to keep a "+" or "-" line. Has to be called for every one of them, in order.
+    fn keep(&mut self, instruction: u8, allocation_index: u64) -> bool;
+
+    /// Called for every "R" line with the resident set size, if any filter needs it.
+    fn rss(&mut self, _rss: u64) {}
+
+    /// Called for every "a" line, if any filter needs them.
+    fn allocation_info(&mut self, _size: u64, _trace: u64) {}
+
+    /// Called for every "t" line with its parent trace, if any filter needs them.
+    fn trace(&mut self, _parent: u64) {}
+}
+
+/// Follows the allocations of the second pass for a filter from a first pass.
+pub struct FirstPassFilter<'a> {
+    filter: &'a AllocationFilter,
+    pairing: Pairing,
+}
+
+impl<'a> FirstPassFilter<'a> {
+    pub fn new(filter: &'a AllocationFilter) -> Self {
+        FirstPassFilter {
+            filter,
+            pairing: Pairing::default(),
+        }
+    }
+}
+
+impl EventFilter for FirstPassFilter<'_> {
+    fn keep(&mut self, instruction: u8, allocation_index: u64) -> bool {
+        self.filter
+            .keep(&mut self.pairing, instruction, allocation_index)
+    }
+}
+
+/// Keeps the events of allocation infos with one of the given sizes.
+pub struct ExactSizes<'a> {
+    sizes: &'a [u64],
+    /// by allocation index
+    matches: Vec<bool>,
+}
+
+impl<'a> ExactSizes<'a> {
+    pub fn new(sizes: &'a [u64]) -> Self {
+        ExactSizes {
+            sizes,
+            matches: Vec::new(),
+        }
+    }
+}
+
+impl EventFilter for ExactSizes<'_> {
+    fn keep(&mut self, _instruction: u8, allocation_index: u64) -> bool {
+        self.matches
+            .get(allocation_index as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    fn allocation_info(&mut self, size: u64, _trace: u64) {
+        self.matches.push(self.sizes.contains(&size));
+    }
+}
+
+/// Keeps the events of allocation infos whose trace is at least `min_depth` deep.
+pub struct MinTraceDepth {
+    min_depth: u64,
+    /// by trace, trace 0 is the root
+    depths: Vec<u64>,
+    /// by allocation index
+    matches: Vec<bool>,
+}
+
+impl MinTraceDepth {
+    pub fn new(min_depth: u64) -> Self {
+        MinTraceDepth {
+            min_depth,
+            depths: vec![0],
+            matches: Vec::new(),
+        }
+    }
+}
+
+impl EventFilter for MinTraceDepth {
+    fn keep(&mut self, _instruction: u8, allocation_index: u64) -> bool {
+        self.matches
+            .get(allocation_index as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    fn allocation_info(&mut self, _size: u64, trace: u64) {
+        let depth = self.depths.get(trace as usize).copied().unwrap_or(0);
+        self.matches.push(depth >= self.min_depth);
+    }
+
+    fn trace(&mut self, parent: u64) {
+        let depth = self.depths.get(parent as usize).copied().unwrap_or(0) + 1;
+        self.depths.push(depth);
+    }
+}
+
+struct Stage<'a> {
+    name: &'static str,
+    filter: Box<dyn EventFilter + 'a>,
+    dropped: u64,
+}
+
+#[derive(Default)]
+pub struct FilterChain<'a> {
+    stages: Vec<Stage<'a>>,
+    events: u64,
+    /// by the trimming itself
+    trimmed: u64,
+}
+
+impl<'a> FilterChain<'a> {
+    pub fn push(&mut self, name: &'static str, filter: Box<dyn EventFilter + 'a>) {
+        self.stages.push(Stage {
+            name,
+            filter,
+            dropped: 0,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// `is_trimmed` is whether the trimming itself drops the event.
+    pub fn keep(&mut self, is_trimmed: bool, instruction: u8, allocation_index: u64) -> bool {
+        self.events += 1;
+        let mut is_kept = !is_trimmed;
+        if is_trimmed {
+            self.trimmed += 1;
+        }
+        for stage in &mut self.stages {
+            if !stage.filter.keep(instruction, allocation_index) && is_kept {
+                stage.dropped += 1;
+                is_kept = false;
+            }
+        }
+        is_kept
+    }
+
+    pub fn rss(&mut self, rss: u64) {
+        for stage in &mut self.stages {
+            stage.filter.rss(rss);
+        }
+    }
+
+    pub fn allocation_info(&mut self, size: u64, trace: u64) {
+        for stage in &mut self.stages {
+            stage.filter.allocation_info(size, trace);
+        }
+    }
+
+    pub fn trace(&mut self, parent: u64) {
+        for stage in &mut self.stages {
+            stage.filter.trace(parent);
+        }
+    }
+
+    /// `(name, events that reached it, dropped)` of every stage, starting with the trimming.
+    pub fn stage_stats(&self) -> Vec<(&'static str, u64, u64)> {
+        let mut events = self.events;
+        let trimming = [("trimming", self.trimmed)];
+        let stages = self.stages.iter().map(|stage| (stage.name, stage.dropped));
+        trimming
+            .into_iter()
+            .chain(stages)
+            .map(|(name, dropped)| {
+                let reached = events;
+                events -= dropped;
+                (name, reached, dropped)
+            })
+            .collect()
+    }
+
+    /// One line per stage, with how many events reached it and how many it dropped.
+    pub fn write_stats(&self, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "{:<22} {:>12} {:>12}", "stage", "events", "dropped")?;
+        let stats = self.stage_stats();
+        for (name, events, dropped) in &stats {
+            writeln!(output, "{:<22} {:>12} {:>12}", name, events, dropped)?;
+        }
+        let kept = stats
+            .last()
+            .map_or(0, |(_, events, dropped)| events - dropped);
+        writeln!(output, "kept: {} of {} events", kept, self.events)
+    }
+}
+
+/// The `stage_stats` of a chain as a JSON array, without a trailing newline.
+pub fn write_stages_json(
+    stages: &[(&'static str, u64, u64)],
+    mut output: impl Write,
+) -> io::Result<()> {
+    output.write_all(b"[")?;
+    for (i, (name, events, dropped)) in stages.iter().enumerate() {
+        if i > 0 {
+            output.write_all(b",")?;
+        }
+        output.write_all(b"{\"name\":")?;
+        write_json_string(&mut output, name)?;
+        write!(output, ",\"events\":{},\"dropped\":{}}}", events, dropped)?;
+    }
+    output.write_all(b"]")
+}
+
+#[test]
+fn test_filter_chain() {
+    let sizes = [8];
+    let mut chain = FilterChain::default();
+    chain.push("--exact-size", Box::new(ExactSizes::new(&sizes)));
+    chain.push("--min-trace-depth", Box::new(MinTraceDepth::new(2)));
+    assert!(!chain.is_empty());
+
+    // trace 2 is 2 deep
+    chain.trace(0);
+    chain.trace(1);
+    chain.allocation_info(8, 1);
+    chain.allocation_info(8, 2);
+    chain.allocation_info(16, 2);
+    chain.allocation_info(16, 1);
+
+    let kept: Vec<_> = [
+        (true, b'+', 1),
+        (false, b'+', 0),
+        (false, b'+', 1),
+        (false, b'+', 1),
+        (false, b'+', 2),
+        (false, b'+', 3),
+        (false, b'-', 1),
+        // undefined
+        (false, b'+', 9),
+    ]
+    .into_iter()
+    .map(|(is_trimmed, instruction, index)| chain.keep(is_trimmed, instruction, index))
+    .collect();
+    assert_eq!(
+        kept,
+        vec![false, false, true, true, false, false, true, false]
+    );
+
+    let mut stats = Vec::new();
+    chain.write_stats(&mut stats).unwrap();
+    // index 3 fails both, but only counts for --exact-size
+    assert_eq!(
+        String::from_utf8(stats).unwrap(),
+        "\
+stage                        events      dropped
+trimming                          8            1
+--exact-size                      7            3
+--min-trace-depth                 4            1
+kept: 3 of 8 events
+"
+    );
+}
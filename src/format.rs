@@ -0,0 +1,289 @@
+//! Detection of the container format of a profile and its heaptrack version, without parsing the
+//! body.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::parse_hex;
+use crate::stats::HashingWriter;
+use crate::text::BYTE_ORDER_MARK;
+
+/// The compression formats heaptrack can write profiles in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// How many bytes `detect` needs to see to tell all formats apart.
+    pub const MAGIC_LEN: usize = 6;
+
+    pub fn detect(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if magic.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+
+    /// heaptrack-trim does not link against any compression libraries, so decompressing shells
+    /// out to the usual command line tools. All of them understand `-dc`.
+    fn decompressor(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+            Compression::Bzip2 => Some("bzip2"),
+            Compression::Xz => Some("xz"),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "uncompressed",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+            Compression::Xz => "xz",
+        })
+    }
+}
+
+/// The first line of every profile, "v <heaptrack version> <file format version>", both in hex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Version {
+    /// `(major << 16) | (minor << 8) | patch`
+    pub heaptrack: u64,
+    pub file_format: u64,
+}
+
+impl Version {
+    pub fn parse(line: &[u8]) -> Option<Version> {
+        let mut args = line.trim_ascii_end().split(|x| *x == b' ');
+        if args.next()? != b"v" {
+            return None;
+        }
+        let heaptrack = parse_hex(args.next()?).ok()?;
+        let file_format = parse_hex(args.next()?).ok()?;
+        Some(Version {
+            heaptrack,
+            file_format,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "heaptrack {}.{}.{}, file format {}",
+            self.heaptrack >> 16,
+            (self.heaptrack >> 8) & 0xff,
+            self.heaptrack & 0xff,
+            self.file_format
+        )
+    }
+}
+
+/// Read the version line of an uncompressed profile.
+pub fn read_version(mut input: impl BufRead) -> io::Result<Version> {
+    let mut line = Vec::new();
+    input.read_until(b'\n', &mut line)?;
+    let line = line.strip_prefix(BYTE_ORDER_MARK).unwrap_or(&line);
+    Version::parse(line).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a heaptrack profile, first line is not a version line",
+        )
+    })
+}
+
+/// Figure out whether `path` is a heaptrack profile, only looking at the first line after
+/// decompression.
+pub fn probe(path: &Path) -> io::Result<(Compression, Version)> {
+    let mut file = File::open(path)?;
+    let mut magic = Vec::with_capacity(Compression::MAGIC_LEN);
+    (&mut file)
+        .take(Compression::MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+    file.rewind()?;
+
+    let compression = Compression::detect(&magic);
+    let version = match compression.decompressor() {
+        None => read_version(BufReader::new(file))?,
+        Some(program) => {
+            let mut child = Command::new(program)
+                .arg("-dc")
+                .stdin(file)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| {
+                    io::Error::new(e.kind(), format!("failed to run {}: {}", program, e))
+                })?;
+            let version = read_version(BufReader::new(child.stdout.take().unwrap()));
+            // we only wanted the first line
+            let _ = child.kill();
+            let _ = child.wait();
+            version?
+        }
+    };
+
+    Ok((compression, version))
+}
+
+/// Decompress all of `path` and check that it is exactly what a `HashingWriter` saw being
+/// written to the compressor, given its `bytes` and `hash`.
+pub fn verify_compressed(path: &Path, bytes: u64, hash: u64) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut magic = Vec::with_capacity(Compression::MAGIC_LEN);
+    (&mut file)
+        .take(Compression::MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+    file.rewind()?;
+
+    let mut decompressed = HashingWriter::new(io::sink());
+    let compression = Compression::detect(&magic);
+    match compression.decompressor() {
+        None => {
+            io::copy(&mut file, &mut decompressed)?;
+        }
+        Some(program) => {
+            let mut child = Command::new(program)
+                .arg("-dc")
+                .stdin(file)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| {
+                    io::Error::new(e.kind(), format!("failed to run {}: {}", program, e))
+                })?;
+            io::copy(&mut child.stdout.take().unwrap(), &mut decompressed)?;
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} failed to decompress it: {}", program, status),
+                ));
+            }
+        }
+    }
+
+    if (decompressed.bytes, decompressed.hash) != (bytes, hash) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} bytes were written, but it decompresses to {} different bytes",
+                bytes, decompressed.bytes
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_detect_compression() {
+    assert_eq!(Compression::detect(b"\x1f\x8b\x08\x00"), Compression::Gzip);
+    assert_eq!(
+        Compression::detect(b"\x28\xb5\x2f\xfd\x00"),
+        Compression::Zstd
+    );
+    assert_eq!(Compression::detect(b"BZh91AY"), Compression::Bzip2);
+    assert_eq!(Compression::detect(b"\xfd7zXZ\x00"), Compression::Xz);
+    assert_eq!(Compression::detect(b"v 10400 3\n"), Compression::None);
+    assert_eq!(Compression::detect(b""), Compression::None);
+}
+
+#[test]
+fn test_read_version() {
+    let version = read_version(&b"v 10400 3\nX ./a.out\n"[..]).unwrap();
+    assert_eq!(
+        version,
+        Version {
+            heaptrack: 0x10400,
+            file_format: 3
+        }
+    );
+    assert_eq!(version.to_string(), "heaptrack 1.4.0, file format 3");
+
+    assert!(read_version(&b"X ./a.out\n"[..]).is_err());
+    assert!(read_version(&b"v\n"[..]).is_err());
+    assert!(read_version(&b""[..]).is_err());
+    assert!(read_version(&b"\x7fELF"[..]).is_err());
+}
+
+#[test]
+fn test_probe_gzip() {
+    let dir = std::env::temp_dir().join(format!("heaptrack-trim-probe-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("profile");
+    std::fs::write(&path, b"v 10400 3\nX ./a.out\n").unwrap();
+
+    assert_eq!(
+        probe(&path).unwrap(),
+        (
+            Compression::None,
+            Version {
+                heaptrack: 0x10400,
+                file_format: 3
+            }
+        )
+    );
+
+    let status = Command::new("gzip").arg(&path).status();
+    if status.is_ok_and(|s| s.success()) {
+        let (compression, version) = probe(&dir.join("profile.gz")).unwrap();
+        assert_eq!(compression, Compression::Gzip);
+        assert_eq!(version.file_format, 3);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_verify_compressed() {
+    use std::io::Write;
+
+    let dir = std::env::temp_dir().join(format!("heaptrack-trim-verify-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("profile");
+    let profile = "v 10400 3\nX ./a.out\nc 1\n+ 0\n".repeat(100);
+    std::fs::write(&path, &profile).unwrap();
+
+    let mut written = HashingWriter::new(io::sink());
+    written.write_all(profile.as_bytes()).unwrap();
+    verify_compressed(&path, written.bytes, written.hash).unwrap();
+
+    let status = Command::new("gzip").arg(&path).status();
+    if status.is_ok_and(|s| s.success()) {
+        let path = dir.join("profile.gz");
+        verify_compressed(&path, written.bytes, written.hash).unwrap();
+
+        let mut other = HashingWriter::new(io::sink());
+        other.write_all(b"v 10400 3\n").unwrap();
+        assert!(verify_compressed(&path, other.bytes, other.hash).is_err());
+
+        let mut compressed = std::fs::read(&path).unwrap();
+        let middle = compressed.len() / 2;
+        compressed[middle] ^= 0xff;
+        std::fs::write(&path, &compressed).unwrap();
+        assert!(verify_compressed(&path, written.bytes, written.hash).is_err());
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
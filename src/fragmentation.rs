@@ -0,0 +1,230 @@
+//! Comparing the live heap to the resident set size, for --fragmentation-report.
+//!
+//! At every "R" line, the live heap, replayed from the "+" and "-" lines like in `replay`, is
+//! what the program asked for, and the resident set size is what it got. The difference is an
+//! estimate of the overhead of the allocator, which grows when freed memory cannot be reused or
+//! returned to the system.
+//!
+//! This is only an estimate. The resident set size includes more than the heap: code, stacks,
+//! memory mapped files and anything allocated without malloc, so there is always some overhead,
+//! even without any fragmentation, and what matters is how it changes over time. It also leaves
+//! out memory that was swapped out, and pages of large allocations that were never touched, so
+//! the overhead can be negative. Allocations in "a" lines are the sizes the program asked for,
+//! not what the allocator rounded them up to.
+
+use std::io::{self, BufRead, Write};
+
+use crate::parse_hex;
+use crate::reader::{Command, HeaptrackReader};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub timestamp: u64,
+    /// bytes allocated and not freed yet
+    pub live: i64,
+    /// resident set size in bytes
+    pub rss: u64,
+}
+
+impl Sample {
+    /// Resident bytes that are not part of the live heap.
+    pub fn overhead(&self) -> i64 {
+        self.rss as i64 - self.live
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+pub fn parse_report_format(value: &str) -> Result<ReportFormat, String> {
+    match value {
+        "text" => Ok(ReportFormat::Text),
+        "csv" => Ok(ReportFormat::Csv),
+        "json" => Ok(ReportFormat::Json),
+        _ => Err(format!(
+            "unknown report format {:?}, expected text, csv or json",
+            value
+        )),
+    }
+}
+
+/// One sample per "R" line. Malformed "R" lines are left out.
+pub fn samples(input: impl BufRead) -> io::Result<Vec<Sample>> {
+    let mut allocation_sizes = Vec::new();
+    let mut live = 0;
+    let mut samples = Vec::new();
+    let size = |sizes: &[i64], index: u64| sizes.get(index as usize).copied().unwrap_or(0);
+
+    for command in HeaptrackReader::new(input).timed() {
+        match command? {
+            (_, Command::AllocationInfo { size, .. }) => allocation_sizes.push(size as i64),
+            (_, Command::Alloc(index)) => live += size(&allocation_sizes, index),
+            (_, Command::Free(index)) => live -= size(&allocation_sizes, index),
+            (timestamp, Command::Other(line)) if line.starts_with(b"R ") => {
+                let rss = line[2..].split(|x| *x == b' ').next().map(parse_hex);
+                if let Some(Ok(rss)) = rss {
+                    samples.push(Sample {
+                        timestamp,
+                        live,
+                        rss,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(samples)
+}
+
+pub fn write_report(
+    samples: &[Sample],
+    format: ReportFormat,
+    mut output: impl Write,
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Text => {
+            writeln!(
+                output,
+                "{:>14} {:>14} {:>14} {:>14}",
+                "timestamp_ms", "live_bytes", "rss_bytes", "overhead"
+            )?;
+            for sample in samples {
+                writeln!(
+                    output,
+                    "{:>14} {:>14} {:>14} {:>14}",
+                    sample.timestamp,
+                    sample.live,
+                    sample.rss,
+                    sample.overhead()
+                )?;
+            }
+            match samples
+                .iter()
+                .max_by_key(|x| (x.overhead(), u64::MAX - x.timestamp))
+            {
+                Some(largest) => writeln!(
+                    output,
+                    "largest overhead: {} bytes at {}ms, {:.1}% of the resident set size",
+                    largest.overhead(),
+                    largest.timestamp,
+                    largest.overhead() as f64 * 100.0 / largest.rss.max(1) as f64
+                ),
+                None => writeln!(output, "no resident set sizes in the profile"),
+            }
+        }
+        ReportFormat::Csv => {
+            writeln!(output, "timestamp_ms,live_bytes,rss_bytes,overhead_bytes")?;
+            for sample in samples {
+                writeln!(
+                    output,
+                    "{},{},{},{}",
+                    sample.timestamp,
+                    sample.live,
+                    sample.rss,
+                    sample.overhead()
+                )?;
+            }
+            Ok(())
+        }
+        ReportFormat::Json => {
+            output.write_all(b"{\"samples\":[")?;
+            for (i, sample) in samples.iter().enumerate() {
+                if i > 0 {
+                    output.write_all(b",")?;
+                }
+                write!(
+                    output,
+                    "{{\"timestamp_ms\":{},\"live_bytes\":{},\"rss_bytes\":{},\"overhead_bytes\":{}}}",
+                    sample.timestamp,
+                    sample.live,
+                    sample.rss,
+                    sample.overhead()
+                )?;
+            }
+            output.write_all(b"]}\n")
+        }
+    }
+}
+
+#[test]
+fn test_fragmentation() {
+    // 0x1000 bytes of other memory, then 0x3000 bytes of heap of which 0x1000 are freed without
+    // the resident set size going down
+    let profile = b"\
+v 10400 3
+R 1000
+c 1
+a 1000 1
+a 2000 1
++ 0
++ 1
+R 4000
+c 2
+- 0
+R 4000
+R x
+c 3
+- 1
+";
+    let samples = samples(&profile[..]).unwrap();
+    assert_eq!(
+        samples,
+        vec![
+            Sample {
+                timestamp: 0,
+                live: 0,
+                rss: 0x1000,
+            },
+            Sample {
+                timestamp: 1,
+                live: 0x3000,
+                rss: 0x4000,
+            },
+            Sample {
+                timestamp: 2,
+                live: 0x2000,
+                rss: 0x4000,
+            },
+        ]
+    );
+
+    let report = |format| {
+        let mut output = Vec::new();
+        write_report(&samples, format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    assert_eq!(
+        report(ReportFormat::Csv),
+        "timestamp_ms,live_bytes,rss_bytes,overhead_bytes\n\
+         0,0,4096,4096\n\
+         1,12288,16384,4096\n\
+         2,8192,16384,8192\n"
+    );
+    assert_eq!(
+        report(ReportFormat::Json),
+        "{\"samples\":[\
+         {\"timestamp_ms\":0,\"live_bytes\":0,\"rss_bytes\":4096,\"overhead_bytes\":4096},\
+         {\"timestamp_ms\":1,\"live_bytes\":12288,\"rss_bytes\":16384,\"overhead_bytes\":4096},\
+         {\"timestamp_ms\":2,\"live_bytes\":8192,\"rss_bytes\":16384,\"overhead_bytes\":8192}\
+         ]}\n"
+    );
+    assert!(report(ReportFormat::Text)
+        .ends_with("largest overhead: 8192 bytes at 2ms, 50.0% of the resident set size\n"));
+
+    let mut output = Vec::new();
+    write_report(&[], ReportFormat::Text, &mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .ends_with("no resident set sizes in the profile\n"));
+}
+
+#[test]
+fn test_parse_report_format() {
+    assert_eq!(parse_report_format("csv"), Ok(ReportFormat::Csv));
+    assert!(parse_report_format("yaml").is_err());
+}
@@ -0,0 +1,276 @@
+//! Extracting a single allocation into a profile of its own.
+//!
+//! The result contains only the first allocation of one allocation info, the free that matches
+//! it (see `lifetime` for how frees are matched), and the strings, instruction pointers and
+//! traces its backtrace needs, renumbered. heaptrack-gui shows it as one bump in the consumed
+//! memory graph, and one single stack in the flamegraph and caller/callee views.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+use std::slice;
+
+use crate::lifetime::Pairing;
+use crate::{malformed_line_error, parse_hex, write_hex};
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn args(line: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    line.trim_ascii_end()
+        .split(|x| *x == b' ')
+        .skip(1)
+        .map_while(|x| parse_hex(x).ok())
+}
+
+fn write_line(
+    mut output: impl Write,
+    instruction: u8,
+    args: impl Iterator<Item = u64>,
+) -> io::Result<()> {
+    output.write_all(&[instruction])?;
+    for arg in args {
+        output.write_all(b" ")?;
+        write_hex(&mut output, arg)?;
+    }
+    output.write_all(b"\n")
+}
+
+/// Strings, instruction pointers and traces are numbered from 1, 0 means none and stays 0.
+fn renumber(needed: &BTreeSet<u64>) -> HashMap<u64, u64> {
+    let mut map: HashMap<u64, u64> = (needed.iter().copied()).zip(1..).collect();
+    map.insert(0, 0);
+    map
+}
+
+pub fn isolate(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    allocation_index: u64,
+    preserve_time: bool,
+) -> io::Result<()> {
+    // "v", "X" and "I" lines, copied as they are
+    let mut metadata = Vec::new();
+    let mut strings = Vec::new();
+    let mut instruction_pointers = Vec::new();
+    let mut traces = Vec::new();
+    let mut allocation_infos = 0;
+    let mut allocation_info = None;
+
+    let mut pairing = Pairing::default();
+    let mut allocation = None;
+    let mut allocated_at = 0;
+    let mut freed_at = None;
+    let mut timestamp = 0;
+    let mut lineno = 0;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        lineno += 1;
+
+        match line[0] {
+            b'v' | b'X' | b'I' => metadata.extend_from_slice(&line),
+            b's' => strings.push(line.clone()),
+            b'i' => instruction_pointers.push(args(&line).collect::<Vec<_>>()),
+            b't' => traces.push(args(&line).collect::<Vec<_>>()),
+            b'a' => {
+                if allocation_infos == allocation_index {
+                    allocation_info = Some(args(&line).collect::<Vec<_>>());
+                }
+                allocation_infos += 1;
+            }
+            b'c' => timestamp = args(&line).next().unwrap_or(timestamp),
+            b'+' | b'-' => {
+                let index = args(&line)
+                    .next()
+                    .ok_or_else(|| malformed_line_error(lineno, slice::from_ref(&line)))?;
+                if index != allocation_index {
+                    continue;
+                }
+                if line[0] == b'+' {
+                    let ordinal = pairing.alloc(index);
+                    if allocation.is_none() {
+                        allocation = Some(ordinal);
+                        allocated_at = timestamp;
+                    }
+                } else if allocation.is_some() && pairing.free(index) == allocation {
+                    freed_at = Some(timestamp);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (Some(allocation_info), Some(_)) = (allocation_info, allocation) else {
+        return Err(invalid_data(format!(
+            "allocation index {:x} is never allocated",
+            allocation_index
+        )));
+    };
+    let [size, trace] = allocation_info[..] else {
+        return Err(invalid_data(format!(
+            "malformed allocation info {:x}",
+            allocation_index
+        )));
+    };
+
+    let get = |table: &[Vec<u64>], index: u64| -> io::Result<Vec<u64>> {
+        table
+            .get((index as usize).wrapping_sub(1))
+            .cloned()
+            .ok_or_else(|| invalid_data(format!("undefined index {:x}", index)))
+    };
+
+    // collect the backtrace, walking from the allocation up to the root
+    let mut needed_traces = BTreeSet::new();
+    let mut needed_ips = BTreeSet::new();
+    let mut current = trace;
+    while current != 0 {
+        let args = get(&traces, current)?;
+        needed_traces.insert(current);
+        needed_ips.insert(args[0]);
+        current = args.get(1).copied().unwrap_or(0);
+    }
+
+    // "i <ip> <module> [<function> <file> <line>]...", all but ip and line are strings
+    let is_string = |position: usize| position == 1 || (position > 1 && position % 3 != 1);
+    let mut needed_strings = BTreeSet::new();
+    for ip in &needed_ips {
+        let args = get(&instruction_pointers, *ip)?;
+        for (position, arg) in args.iter().enumerate().skip(1) {
+            if is_string(position) && *arg != 0 {
+                needed_strings.insert(*arg);
+            }
+        }
+    }
+
+    let string_map = renumber(&needed_strings);
+    let ip_map = renumber(&needed_ips);
+    let trace_map = renumber(&needed_traces);
+
+    output.write_all(&metadata)?;
+    for index in &needed_strings {
+        let string = strings
+            .get(*index as usize - 1)
+            .ok_or_else(|| invalid_data(format!("undefined string {:x}", index)))?;
+        output.write_all(string)?;
+    }
+    for ip in &needed_ips {
+        let args = get(&instruction_pointers, *ip)?;
+        let args = args.iter().enumerate().map(|(position, arg)| {
+            if is_string(position) {
+                string_map[arg]
+            } else {
+                *arg
+            }
+        });
+        write_line(&mut output, b'i', args)?;
+    }
+    for trace in &needed_traces {
+        let args = get(&traces, *trace)?;
+        write_line(
+            &mut output,
+            b't',
+            [
+                ip_map[&args[0]],
+                trace_map[&args.get(1).copied().unwrap_or(0)],
+            ]
+            .into_iter(),
+        )?;
+    }
+
+    let time = |timestamp: u64| {
+        if preserve_time {
+            timestamp
+        } else {
+            // saturating, since timestamps can go backwards
+            timestamp.saturating_sub(allocated_at)
+        }
+    };
+    write_line(&mut output, b'c', [time(allocated_at)].into_iter())?;
+    write_line(&mut output, b'a', [size, trace_map[&trace]].into_iter())?;
+    output.write_all(b"+ 0\n")?;
+    if let Some(freed_at) = freed_at {
+        write_line(&mut output, b'c', [time(freed_at)].into_iter())?;
+        output.write_all(b"- 0\n")?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_isolate() {
+    let input = b"\
+v 10400 3
+X ./a.out
+s a.out
+s main
+s unrelated
+s foo
+i 10 1 2 0 0
+i 20 1 3 0 0
+i 30 1 4 0 0
+t 1 0
+t 2 1
+t 3 1
+c 1
+a 8 2
++ 0
+a 10 3
++ 1
++ 1
+c 5
++ 0
+- 1
+c 8
+- 1
+c a
+- 0
+";
+
+    let mut output = Vec::new();
+    isolate(&input[..], &mut output, 1, false).unwrap();
+    // allocation 1 has the backtrace foo <- main, so only trace 3 and its parent 1 are needed
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+v 10400 3
+X ./a.out
+s a.out
+s main
+s foo
+i 10 1 2 0 0
+i 30 1 3 0 0
+t 1 0
+t 2 1
+c 0
+a 10 2
++ 0
+c 7
+- 0
+"
+    );
+
+    assert!(isolate(&input[..], io::sink(), 2, false).is_err());
+
+    let error = isolate(&b"v 10400 3\n+ zz\n"[..], io::sink(), 0, false).unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("line 2 of the input is malformed: \"+ zz\""));
+
+    // a free at an earlier timestamp than its allocation is at the start of the profile
+    let mut output = Vec::new();
+    isolate(
+        &b"v 10400 3\ns main\ni 10 1\nt 1 0\nc 5\na 8 1\n+ 0\nc 2\n- 0\n"[..],
+        &mut output,
+        0,
+        false,
+    )
+    .unwrap();
+    assert!(output.ends_with(b"c 0\na 8 1\n+ 0\nc 0\n- 0\n"));
+}
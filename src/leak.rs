@@ -0,0 +1,186 @@
+//! Listing the allocations that are never freed, for --leak-report.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// The allocations of one size and trace that were not freed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Leak {
+    pub size: u64,
+    pub trace: u64,
+    pub allocations: u64,
+}
+
+impl Leak {
+    pub fn bytes(&self) -> u64 {
+        self.size.saturating_mul(self.allocations)
+    }
+}
+
+/// Counts outstanding allocations per allocation info while a profile is read.
+#[derive(Default)]
+pub struct LeakTracker {
+    /// `(size, trace)` of every allocation info, by allocation index
+    allocation_infos: Vec<(u64, u64)>,
+    /// by allocation index
+    outstanding: Vec<u64>,
+}
+
+impl LeakTracker {
+    pub fn allocation_info(&mut self, size: u64, trace: u64) {
+        self.allocation_infos.push((size, trace));
+        self.outstanding.push(0);
+    }
+
+    /// Has to be called for every "+" and "-" line. Frees of allocations that are not
+    /// outstanding are ignored.
+    pub fn event(&mut self, instruction: u8, allocation_index: u64) {
+        let Some(outstanding) = self.outstanding.get_mut(allocation_index as usize) else {
+            return;
+        };
+        if instruction == b'+' {
+            *outstanding += 1;
+        } else {
+            *outstanding = outstanding.saturating_sub(1);
+        }
+    }
+
+    /// The outstanding allocations, grouped by size and trace, the most leaked bytes first.
+    pub fn leaks(&self) -> Vec<Leak> {
+        let mut by_site = HashMap::<(u64, u64), u64>::new();
+        for (info, outstanding) in self.allocation_infos.iter().zip(&self.outstanding) {
+            if *outstanding > 0 {
+                *by_site.entry(*info).or_default() += outstanding;
+            }
+        }
+        let mut leaks: Vec<_> = by_site
+            .into_iter()
+            .map(|((size, trace), allocations)| Leak {
+                size,
+                trace,
+                allocations,
+            })
+            .collect();
+        leaks.sort_by_key(|leak| (u64::MAX - leak.bytes(), leak.size, leak.trace));
+        leaks
+    }
+}
+
+/// One line per leak, and the total. Sizes are in bytes, traces in hex like in "a" lines.
+pub fn write_report(leaks: &[Leak], mut output: impl Write) -> io::Result<()> {
+    writeln!(
+        output,
+        "{:>14} {:>12} {:>12}  trace",
+        "leaked bytes", "allocations", "size"
+    )?;
+    for leak in leaks {
+        writeln!(
+            output,
+            "{:>14} {:>12} {:>12}  {:x}",
+            leak.bytes(),
+            leak.allocations,
+            leak.size,
+            leak.trace
+        )?;
+    }
+    writeln!(
+        output,
+        "total: {} bytes in {} allocations",
+        leaks.iter().map(Leak::bytes).sum::<u64>(),
+        leaks.iter().map(|leak| leak.allocations).sum::<u64>()
+    )
+}
+
+/// Like `write_report`, as a JSON object with everything in decimal:
+///
+/// ```text
+/// {"bytes":...,"allocations":...,"leaks":[{"size":16,"trace":1,"allocations":2,"bytes":32}]}
+/// ```
+pub fn write_json_report(leaks: &[Leak], mut output: impl Write) -> io::Result<()> {
+    write!(
+        output,
+        "{{\"bytes\":{},\"allocations\":{},\"leaks\":[",
+        leaks.iter().map(Leak::bytes).sum::<u64>(),
+        leaks.iter().map(|leak| leak.allocations).sum::<u64>()
+    )?;
+    for (i, leak) in leaks.iter().enumerate() {
+        if i > 0 {
+            output.write_all(b",")?;
+        }
+        write!(
+            output,
+            "{{\"size\":{},\"trace\":{},\"allocations\":{},\"bytes\":{}}}",
+            leak.size,
+            leak.trace,
+            leak.allocations,
+            leak.bytes()
+        )?;
+    }
+    output.write_all(b"]}\n")
+}
+
+#[test]
+fn test_leaks() {
+    let mut tracker = LeakTracker::default();
+    tracker.allocation_info(0x10, 1);
+    tracker.allocation_info(0x1000, 2);
+    tracker.allocation_info(0x8, 3);
+    // heaptrack does not write the same size and trace twice, but a broken profile might
+    tracker.allocation_info(0x10, 1);
+    for (instruction, index) in [
+        (b'+', 0),
+        (b'+', 0),
+        (b'+', 1),
+        (b'-', 1),
+        (b'+', 2),
+        (b'-', 0),
+        (b'+', 3),
+        (b'-', 2),
+        (b'-', 2),
+        (b'+', 9),
+    ] {
+        tracker.event(instruction, index);
+    }
+
+    let leaks = tracker.leaks();
+    assert_eq!(
+        leaks,
+        vec![Leak {
+            size: 0x10,
+            trace: 1,
+            allocations: 2
+        }]
+    );
+
+    let mut report = Vec::new();
+    write_report(&leaks, &mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "  leaked bytes  allocations         size  trace
+            32            2           16  1
+total: 32 bytes in 2 allocations
+"
+    );
+
+    let mut report = Vec::new();
+    write_json_report(&leaks, &mut report).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "{\"bytes\":32,\"allocations\":2,\"leaks\":[{\"size\":16,\"trace\":1,\"allocations\":2,\
+         \"bytes\":32}]}\n"
+    );
+}
+
+#[test]
+fn test_leaks_order() {
+    let mut tracker = LeakTracker::default();
+    tracker.allocation_info(0x10, 1);
+    tracker.allocation_info(0x20, 2);
+    tracker.allocation_info(0x8, 3);
+    for index in [0, 0, 1, 2, 2, 2, 2] {
+        tracker.event(b'+', index);
+    }
+    let order: Vec<_> = tracker.leaks().iter().map(|leak| leak.trace).collect();
+    // all of them leak 32 bytes, ties go to the smaller size
+    assert_eq!(order, vec![3, 1, 2]);
+}
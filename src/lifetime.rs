@@ -0,0 +1,351 @@
+//! Filtering allocations by how long they lived.
+//!
+//! The profile does not record which allocation a "-" line frees, only which allocation info
+//! (size and trace) it belongs to. Frees are matched to the most recent outstanding allocation of
+//! the same allocation info. Allocations that are never freed live until the end of the profile.
+
+use std::io::{self, BufRead, Write};
+
+use crate::reader::{Command, HeaptrackReader};
+
+/// Lifetimes are kept in a log-scale histogram: values below 8ms are exact, above that every
+/// power of two is split into 8 buckets, so the error is at most 12.5%. 256 buckets cover
+/// lifetimes up to 2^32ms, about 50 days.
+pub const BUCKETS: usize = 256;
+
+pub fn bucket(lifetime_ms: u64) -> u8 {
+    if lifetime_ms < 8 {
+        return lifetime_ms as u8;
+    }
+    let exponent = 63 - lifetime_ms.leading_zeros() as u64;
+    let mantissa = (lifetime_ms >> (exponent - 3)) & 7;
+    ((exponent - 2) * 8 + mantissa).min(BUCKETS as u64 - 1) as u8
+}
+
+/// Matches frees to allocations. Allocations are numbered by the order of their "+" lines.
+#[derive(Default)]
+pub struct Pairing {
+    /// outstanding allocations per allocation info
+    outstanding: Vec<Vec<u64>>,
+    allocations: u64,
+}
+
+impl Pairing {
+    pub fn alloc(&mut self, allocation_index: u64) -> u64 {
+        let index = allocation_index as usize;
+        if self.outstanding.len() <= index {
+            self.outstanding.resize_with(index + 1, Vec::new);
+        }
+        let allocation = self.allocations;
+        self.outstanding[index].push(allocation);
+        self.allocations += 1;
+        allocation
+    }
+
+    pub fn free(&mut self, allocation_index: u64) -> Option<u64> {
+        self.outstanding.get_mut(allocation_index as usize)?.pop()
+    }
+}
+
+/// The lifetime of every allocation in the profile, bucketed.
+pub struct Lifetimes {
+    /// bucket per allocation, only if asked for
+    buckets: Vec<u8>,
+    histogram: [u64; BUCKETS],
+    /// allocations without a matching free
+    never_freed: u64,
+}
+
+/// The first pass: replay the whole profile to find out how long each allocation lived. The
+/// lifetime is bucketed when the free comes, so only the allocations that are not freed yet are
+/// kept in memory, and with `per_allocation`, the bucket of every allocation, one byte each,
+/// which `percentile_filter` needs.
+pub fn lifetimes(input: impl BufRead, per_allocation: bool) -> io::Result<Lifetimes> {
+    // (allocation, allocated at) of the outstanding allocations per allocation info, matched like
+    // in `Pairing`
+    let mut outstanding: Vec<Vec<(u64, u64)>> = Vec::new();
+    let mut allocations = 0u64;
+    let mut lifetimes = Lifetimes {
+        buckets: Vec::new(),
+        histogram: [0; BUCKETS],
+        never_freed: 0,
+    };
+    let mut end = 0;
+
+    for command in HeaptrackReader::new(input).timed() {
+        let (timestamp, command) = command?;
+        end = timestamp;
+        match command {
+            Command::Alloc(index) => {
+                let index = index as usize;
+                if outstanding.len() <= index {
+                    outstanding.resize_with(index + 1, Vec::new);
+                }
+                outstanding[index].push((allocations, timestamp));
+                allocations += 1;
+                if per_allocation {
+                    lifetimes.buckets.push(0);
+                }
+            }
+            Command::Free(index) => {
+                if let Some((allocation, allocated_at)) =
+                    outstanding.get_mut(index as usize).and_then(Vec::pop)
+                {
+                    lifetimes.add(allocation, timestamp.saturating_sub(allocated_at));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (allocation, allocated_at) in outstanding.into_iter().flatten() {
+        lifetimes.add(allocation, end.saturating_sub(allocated_at));
+        lifetimes.never_freed += 1;
+    }
+    Ok(lifetimes)
+}
+
+/// The smallest lifetime in a bucket.
+pub fn bucket_start(bucket: usize) -> u64 {
+    if bucket < 8 {
+        bucket as u64
+    } else {
+        (8 + bucket as u64 % 8) << (bucket / 8 - 1)
+    }
+}
+
+impl Lifetimes {
+    fn add(&mut self, allocation: u64, lifetime_ms: u64) {
+        let bucket = bucket(lifetime_ms);
+        self.histogram[bucket as usize] += 1;
+        if let Some(x) = self.buckets.get_mut(allocation as usize) {
+            *x = bucket;
+        }
+    }
+
+    /// One line per power of two of milliseconds, from the shortest to the longest lifetime in
+    /// the profile.
+    pub fn write_histogram(&self, mut output: impl Write) -> io::Result<()> {
+        // 0ms, 1ms, 2-3ms, 4-7ms, ...
+        let mut rows = [0u64; 65];
+        for (bucket, count) in self.histogram.iter().enumerate() {
+            rows[64 - bucket_start(bucket).leading_zeros() as usize] += count;
+        }
+        let total: u64 = rows.iter().sum();
+
+        writeln!(output, "{:<24} {:>12}", "lifetime", "allocations")?;
+        let first = rows.iter().position(|x| *x > 0).unwrap_or(0);
+        let last = rows.iter().rposition(|x| *x > 0).unwrap_or(0);
+        for (row, count) in rows.iter().enumerate().take(last + 1).skip(first) {
+            let label = match row {
+                0 => "0ms".to_owned(),
+                1 => "1ms".to_owned(),
+                _ => format!("{}-{}ms", 1u64 << (row - 1), (1u64 << (row - 1)) * 2 - 1),
+            };
+            writeln!(
+                output,
+                "{:<24} {:>12} {:>5.1}%",
+                label,
+                count,
+                *count as f64 * 100.0 / total as f64
+            )?;
+        }
+        writeln!(
+            output,
+            "{} allocations were never freed, they are counted as living until the end",
+            self.never_freed
+        )
+    }
+
+    /// Keep allocations whose lifetime is between the `low` and `high` percentile, inclusive.
+    /// Needs the buckets of every allocation.
+    pub fn percentile_filter(self, low: f64, high: f64) -> AllocationFilter {
+        let low = percentile_bucket(&self.histogram, low);
+        let high = percentile_bucket(&self.histogram, high);
+        AllocationFilter::new(
+            self.buckets
+                .into_iter()
+                .map(|bucket| (low..=high).contains(&bucket))
+                .collect(),
+        )
+    }
+}
+
+/// The bucket of a histogram in which the given percentile of the counted values falls.
+pub fn percentile_bucket(histogram: &[u64; BUCKETS], percentile: f64) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let rank = ((percentile / 100.0 * total as f64).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (bucket, count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen >= rank {
+            return bucket as u8;
+        }
+    }
+    (BUCKETS - 1) as u8
+}
+
+/// Which allocations to keep, decided in a first pass. Allocations are numbered like in
+/// `Pairing`.
+pub struct AllocationFilter {
+    keep: Vec<bool>,
+}
+
+impl AllocationFilter {
+    pub fn new(keep: Vec<bool>) -> Self {
+        AllocationFilter { keep }
+    }
+
+    /// Whether to write a "+" or "-" line. Has to be called for every one of them, in order.
+    pub fn keep(&self, pairing: &mut Pairing, instruction: u8, allocation_index: u64) -> bool {
+        let allocation = if instruction == b'+' {
+            Some(pairing.alloc(allocation_index))
+        } else {
+            pairing.free(allocation_index)
+        };
+        // frees that do not match any allocation are none of our business
+        allocation.is_none_or(|x| self.keep.get(x as usize).copied().unwrap_or(true))
+    }
+}
+
+/// Parse `LOW:HIGH`, both percentiles between 0 and 100.
+pub fn parse_percentile_range(value: &str) -> Result<(f64, f64), String> {
+    let invalid = || {
+        format!(
+            "expected LOW:HIGH with 0 <= LOW <= HIGH <= 100, got {:?}",
+            value
+        )
+    };
+    let (low, high) = value.split_once(':').ok_or_else(invalid)?;
+    let low: f64 = low.parse().map_err(|_| invalid())?;
+    let high: f64 = high.parse().map_err(|_| invalid())?;
+    if !(0.0 <= low && low <= high && high <= 100.0) {
+        return Err(invalid());
+    }
+    Ok((low, high))
+}
+
+#[test]
+fn test_bucket() {
+    assert_eq!(bucket(0), 0);
+    assert_eq!(bucket(7), 7);
+    assert_eq!(bucket(8), 8);
+    assert_eq!(bucket(15), 15);
+    assert_eq!(bucket(16), 16);
+    assert_eq!(bucket(17), 16);
+    assert_eq!(bucket(18), 17);
+    assert!(bucket(1000) < bucket(1200));
+    assert_eq!(bucket(u64::MAX), 255);
+}
+
+#[test]
+fn test_bucket_start() {
+    for bucket in 0..BUCKETS {
+        assert_eq!(self::bucket(bucket_start(bucket)), bucket as u8);
+    }
+}
+
+#[test]
+fn test_histogram() {
+    // lifetimes of 0, 1, 3, 3, 100ms, and one that lives from 1 until the end at 100
+    let input = b"\
+a 10 1
+c 0
++ 0
+- 0
++ 0
+c 1
+- 0
++ 0
++ 0
++ 0
++ 0
+c 4
+- 0
+- 0
+c 64
+- 0
+";
+    let mut histogram = Vec::new();
+    lifetimes(&input[..], false)
+        .unwrap()
+        .write_histogram(&mut histogram)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(histogram).unwrap(),
+        "\
+lifetime                  allocations
+0ms                                 1  16.7%
+1ms                                 1  16.7%
+2-3ms                               2  33.3%
+4-7ms                               0   0.0%
+8-15ms                              0   0.0%
+16-31ms                             0   0.0%
+32-63ms                             0   0.0%
+64-127ms                            2  33.3%
+1 allocations were never freed, they are counted as living until the end
+"
+    );
+}
+
+#[test]
+fn test_parse_percentile_range() {
+    assert_eq!(parse_percentile_range("10:90"), Ok((10.0, 90.0)));
+    assert_eq!(parse_percentile_range("0:100"), Ok((0.0, 100.0)));
+    assert_eq!(parse_percentile_range("50:50"), Ok((50.0, 50.0)));
+    assert!(parse_percentile_range("90:10").is_err());
+    assert!(parse_percentile_range("10:101").is_err());
+    assert!(parse_percentile_range("10").is_err());
+    assert!(parse_percentile_range("a:b").is_err());
+}
+
+#[test]
+fn test_percentile_filter() {
+    // five allocations of the same allocation info that live 1, 2, 3, 100 and 1000ms
+    let input = b"\
+a 10 1
+c 0
++ 0
++ 0
++ 0
++ 0
++ 0
+c 1
+- 0
+c 2
+- 0
+c 3
+- 0
+c 64
+- 0
+c 3e8
+- 0
+";
+    let keeps = |low, high| {
+        let filter = lifetimes(&input[..], true)
+            .unwrap()
+            .percentile_filter(low, high);
+        let mut pairing = Pairing::default();
+        let allocations: Vec<bool> = (0..5).map(|_| filter.keep(&mut pairing, b'+', 0)).collect();
+        // frees come in reverse order of allocations, the one allocated last lives the shortest
+        let frees: Vec<bool> = (0..5).map(|_| filter.keep(&mut pairing, b'-', 0)).collect();
+        assert_eq!(
+            allocations.iter().rev().collect::<Vec<_>>(),
+            frees.iter().collect::<Vec<_>>()
+        );
+        frees
+    };
+
+    assert_eq!(keeps(0.0, 100.0), [true; 5]);
+    assert_eq!(keeps(0.0, 40.0), [true, true, false, false, false]);
+    assert_eq!(keeps(50.0, 80.0), [false, false, true, true, false]);
+    assert_eq!(keeps(100.0, 100.0), [false, false, false, false, true]);
+}
+
+#[test]
+fn test_backwards_timestamp() {
+    // freed before it was allocated, which counts as 0ms
+    let lifetimes = lifetimes(&b"c 5\na 10 0\n+ 0\nc 2\n- 0\n"[..], true).unwrap();
+    assert_eq!(lifetimes.buckets, [0]);
+    assert_eq!(lifetimes.histogram[0], 1);
+}
@@ -1,16 +1,66 @@
+use std::cell::RefCell;
 use std::cmp::max;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::os::fd::{FromRawFd, IntoRawFd};
+use std::io::{self, BufRead, BufReader, BufWriter, LineWriter, Read, Seek, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
 
 use argh::FromArgs;
 
+mod age;
+mod baseline;
+mod budget;
+mod clock;
+mod commands;
+mod diff;
+mod estimate;
+mod export;
+mod filter;
+mod format;
+mod fragmentation;
+mod isolate;
+mod leak;
+mod lifetime;
+mod metadata;
+mod pages;
+mod pipe;
+mod rate;
+mod reader;
+mod realloc;
+mod replay;
+mod report;
+mod rss;
+mod sizes;
+mod split;
+mod stable_id;
+mod stats;
+mod strings;
+mod text;
+mod traces;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "upload")]
+mod upload;
+mod verify;
+mod warnings;
+mod watch;
+
+use clock::{SystemClock, Throttle};
+use stats::{CountingWriter, TrimStats};
+use warnings::{Verbosity, Warning, Warnings};
+
 #[derive(FromArgs)]
 #[argh(description = "cut out irrelevant parts of heaptrack profiles, to reduce file size")]
 struct Cli {
-    /// skip the first N seconds of the profile. required.
+    /// skip the first N seconds of the profile. required for trimming unless --hotspot-window,
+    /// --around-ms, --start-from-index, --between-markers or --max-output-percent is given.
+    ///
+    /// Trimming starts at the first timestamp after the skipped part, except that 0 skips
+    /// nothing, not even what happened before the first timestamp, so the output is the input.
     #[argh(option)]
-    skip_seconds: u64,
+    skip_seconds: Option<u64>,
 
     /// do not rewrite timestamps, leaving the scale of graphs in heaptrack-gui intact.
     ///
@@ -19,228 +69,3591 @@ struct Cli {
     #[argh(switch)]
     preserve_time: bool,
 
-    /// how large should the read and write buffers be? defaults to 1e15 bytes
-    #[argh(option, default = "1 << 15")]
+    /// add N milliseconds, which may be negative, to every timestamp of the output, for example
+    /// to line it up with the logs of the program.
+    ///
+    /// Timestamps that would be negative are written as 0. Cannot be combined with
+    /// --preserve-time, which does not rewrite timestamps.
+    #[argh(option, default = "0", arg_name = "ms")]
+    time_offset_ms: i64,
+
+    /// drop "c" lines less than MS milliseconds after the last one that was written, so that the
+    /// events after them count as happening at that one instead, to make profiles with many
+    /// closely spaced timestamps smaller.
+    ///
+    /// No "+" or "-" lines are dropped and their order does not change, but timestamps become
+    /// coarser: events can appear up to MS milliseconds earlier than they happened. Since the
+    /// distance is measured from the last written "c" line, a gap of MS or more is never merged.
+    #[argh(option, arg_name = "ms")]
+    merge_timestamps: Option<u64>,
+
+    /// how large should the read and write buffers be? defaults to 32KiB, has to be at least
+    /// 1KiB.
+    #[argh(option, default = "1 << 15", from_str_fn(parse_buf_size))]
     buf_size: usize,
-}
 
-fn main() {
-    let cli: Cli = argh::from_env();
+    /// do not trim anything, instead check whether the given file is a heaptrack profile.
+    ///
+    /// Prints the compression and heaptrack version and exits with 0 if it is, exits with 1 if it
+    /// is not. Only the first line is read.
+    #[argh(option, arg_name = "file")]
+    probe: Option<PathBuf>,
 
-    // hacks to get large stdio buffer
-    let stdin = unsafe { File::from_raw_fd(0) };
-    let stdout = unsafe { File::from_raw_fd(1) };
-    let buf_size = cli.buf_size;
+    /// read the profile from this file instead of stdin. Options that need stdin to be a file
+    /// then read this one.
+    #[argh(option, arg_name = "file")]
+    input: Option<PathBuf>,
 
-    let mut reader = BufReader::with_capacity(buf_size, stdin);
-    let mut writer = BufWriter::with_capacity(buf_size, stdout);
+    /// instead of --skip-seconds, keep the N seconds in which the live heap grew the most.
+    ///
+    /// This reads the profile twice, so stdin has to be a file, not a pipe. If several windows
+    /// grew by the same amount, the earliest one is kept.
+    #[argh(option, arg_name = "seconds")]
+    hotspot_window: Option<u64>,
 
-    run_main(
-        cli.skip_seconds * 1000,
-        cli.preserve_time,
-        &mut reader,
-        &mut writer,
-    )
-    .unwrap();
+    /// instead of --skip-seconds, keep the --radius-seconds before and after this profile
+    /// timestamp, for example one found in the logs of the program.
+    ///
+    /// Like with --hotspot-window, the header is kept, and allocation indices and timestamps are
+    /// rebased to start at the start of the window. A window reaching before the start of the
+    /// profile starts at its start, one reaching past its end keeps everything up to the end,
+    /// so the window is shorter than twice the radius then.
+    #[argh(option, arg_name = "ms")]
+    around_ms: Option<u64>,
 
-    // do not close stdio
-    let _ = reader.into_inner().into_raw_fd();
-    let _ = writer.into_inner().unwrap().into_raw_fd();
-}
+    /// with --around-ms, how many seconds to keep on either side of it.
+    #[argh(option, arg_name = "seconds")]
+    radius_seconds: Option<u64>,
 
-fn run_main(
-    skip_timestamp: u64,
-    preserve_time: bool,
-    mut input: impl BufRead,
-    mut output: impl Write,
-) -> Result<(), io::Error> {
-    // the layout of a heaptrack profile was mostly reverse-engineered from C++ sourcecode of
-    // heaptrack-gui
-    // relevant files:
-    // https://github.com/KDE/heaptrack/blob/6b4ca14b78e46783750943afef71e4cb3cade537/src/analyze/accumulatedtracedata.cpp
-    // https://github.com/KDE/heaptrack/blob/6b4ca14b78e46783750943afef71e4cb3cade537/src/analyze/gui/parser.cpp
-    //
-    // parser.cpp is a subclass of accumulatedtracedata, and implements a few callbacks for
-    // GUI-specific datastructures. The meat of the parser is in accumulatedtracedata.
-    //
-    // tldr: after decompression, the file is plaintext, and each line in the file is a command.
-    // the command type is the first character on the line.
-    //
-    // * "c ..." command sets the current time since the start of the profile in milliseconds, in
-    //   hex. For example, "c deadbeef" means we are 0xdeadbeef milliseconds into the profile.
-    // * Allocations are registered with lines starting with "a ...", and get a sequential ID
-    //   starting from 0 called "allocation index."
-    // * Lines starting with "+ ..." or "- ..." refer to these allocations using their index in
-    //   hex. + means alloc, - means free.
-    // * There are a few other commands like "s ..." to index strings that then are referenced in
-    //   allocations. But this actually doesn't matter to us, because all that we are trying to do
-    //   is to reduce the file-size drastically, not make the smallest file possible. Since most
-    //   lines are + and -, it is actually sufficient to remove _only those_ and leave the rest
-    //   as-is, leaving potentially unused strings in the file.
-    // * However, the first reference to an allocation in -/+ lines MUST be 0.
-    //   If there is an allocation index N > 0 referenced in a -\+ command, an allocation index N - 1
-    //   must have been used before. Otherwise, heaptrack-gui segfaults as it tries to access
-    //   some internal array out of bounds. This means that we have to "rebase" all unfiltered
-    //   allocations to start at 0, and remove extraneous "a ..." lines.
-    let mut allocation_index_correction = 0u64;
-    let mut largest_written_allocation_index = 0u64;
+    /// instead of --skip-seconds, skip everything until the given allocation index (hex, as in
+    /// "+" lines) is first referenced, and keep everything from there.
+    ///
+    /// Like with --skip-seconds, strings, traces and the other header lines before it are still
+    /// written, and the allocation indices are rebased so that the given one becomes 0.
+    /// Timestamps start at 0 where it is referenced, unless --preserve-time is given.
+    #[argh(option, arg_name = "hex", from_str_fn(parse_hex_arg))]
+    start_from_index: Option<u64>,
 
-    let mut line_buf = Vec::new();
+    /// instead of --skip-seconds, only keep what is between two comment lines, for example
+    /// `--between-markers "phase load" --between-markers "phase run"` for everything between
+    /// the lines "# phase load" and "# phase run". Has to be given exactly twice, the start
+    /// marker first.
+    ///
+    /// Markers are "#" lines written by instrumentation in the profiled program, and match if
+    /// the text after the "#" is the same, ignoring surrounding whitespace. Like with
+    /// --start-from-index, the header is kept and allocation indices and timestamps are rebased
+    /// to start at the start marker, which is kept. Everything from the end marker on is
+    /// dropped. The end marker only counts after the start marker, so one before it is kept like
+    /// any other comment, and if the end marker is missing, everything up to the end of the input
+    /// is kept. If the start marker is missing, trimming fails.
+    #[argh(option, arg_name = "marker")]
+    between_markers: Vec<String>,
 
-    let mut is_skipping = true;
-    // duration since the start of the input profile
-    let mut current_abs_timestamp_ms = 0u64;
+    /// instead of --skip-seconds, skip as little as possible for the output to be at most P
+    /// percent of the size of the input.
+    ///
+    /// This reads the profile twice, so stdin has to be a file, not a pipe. The size is
+    /// approximate: the first pass assumes that all lines other than allocations and frees are
+    /// kept as they are, but timestamps and allocation indices get shorter when they are
+    /// rewritten, so the output is usually a bit smaller than P percent. Trimming can only start
+    /// at a timestamp, and never removes the header, so some sizes cannot be reached exactly.
+    #[argh(option, arg_name = "p", from_str_fn(parse_percent))]
+    max_output_percent: Option<f64>,
 
-    loop {
-        line_buf.clear();
-        let read_bytes = input.read_until(b'\n', &mut line_buf)?;
-        if read_bytes == 0 {
-            break;
-        }
+    /// do all the work, including formatting every output line, but throw the output away.
+    ///
+    /// Meant for benchmarking the parsing and formatting without measuring the cost of writing to
+    /// a pipe or disk.
+    #[argh(switch)]
+    null_output: bool,
 
-        let line = line_buf.as_slice();
+    /// only keep allocations whose lifetime is between the LOW and HIGH percentile of all
+    /// allocation lifetimes in the profile, for example 90:100 for the longest-lived 10%.
+    ///
+    /// This reads the profile twice, so stdin has to be a file, not a pipe. Percentiles are
+    /// approximate, lifetimes are grouped into buckets that are up to 12.5% wide. Frees are
+    /// attributed to the most recent allocation with the same size and trace, allocations that
+    /// are never freed live until the end of the profile. The first pass needs a byte of memory
+    /// for every allocation in the profile, and more for every allocation that is not freed yet.
+    #[argh(
+        option,
+        arg_name = "low:high",
+        from_str_fn(lifetime::parse_percentile_range)
+    )]
+    lifetime_percentile: Option<(f64, f64)>,
 
-        let instruction = line[0];
+    /// only keep allocations at least as large as the Pth percentile of all allocation sizes in
+    /// the profile, for example 90 for the largest 10%.
+    ///
+    /// This reads the profile twice, so stdin has to be a file, not a pipe. The percentile is
+    /// approximate: sizes are grouped into buckets that are up to 12.5% wide, and the cutoff is
+    /// the smallest size in the bucket of the percentile, so allocations slightly below the exact
+    /// percentile can be kept too. Frees are kept if their allocation is.
+    #[argh(option, arg_name = "p", from_str_fn(sizes::parse_percentile))]
+    min_size_percentile: Option<f64>,
 
-        match instruction {
-            b'c' => {
-                let mut args = line.trim_ascii_end().split(|x| *x == b' ').skip(1);
-                current_abs_timestamp_ms = parse_hex(args.next().unwrap()).unwrap();
-
-                if is_skipping && current_abs_timestamp_ms > skip_timestamp {
-                    eprintln!(
-                        "stopped skipping at profile timestamp {}, writing all data now",
-                        current_abs_timestamp_ms
-                    );
-                    is_skipping = false;
-                }
+    /// do not trim anything, instead print how many allocations lived how long, in buckets of
+    /// powers of two milliseconds.
+    ///
+    /// Frees are attributed like with --lifetime-percentile. This only reads stdin once, so it can
+    /// be a pipe, and only keeps the allocations that are not freed yet in memory.
+    #[argh(switch)]
+    lifetime_histogram: bool,
 
-                if !is_skipping {
-                    if preserve_time {
-                        output.write(line)?;
-                    } else {
-                        output.write(b"c ")?;
-                        write_hex(&mut output, current_abs_timestamp_ms - skip_timestamp)?;
-                        output.write(b"\n")?;
-                    }
-                }
-            }
-            b'+' | b'-' => {
-                let mut args = line.trim_ascii_end().split(|x| *x == b' ').skip(1);
-                let allocation_index = parse_hex(args.next().unwrap()).unwrap();
-                if allocation_index > allocation_index_correction {
-                    if is_skipping {
-                        allocation_index_correction = allocation_index;
-                    } else {
-                        let new_allocation_index =
-                            allocation_index - allocation_index_correction - 1;
-                        debug_assert!(
-                            new_allocation_index <= largest_written_allocation_index + 1,
-                            "{} not within bounds of {}",
-                            allocation_index,
-                            largest_written_allocation_index
-                        );
+    /// do not trim anything, instead show the live heap over time and the most allocated sizes
+    /// in the terminal.
+    ///
+    /// Commands are typed into the terminal, followed by enter: h and l move to earlier and later
+    /// parts of the profile, + and - zoom in and out, q quits. Every move reads the profile
+    /// again, so stdin has to be a file, not a pipe.
+    #[cfg(feature = "tui")]
+    #[argh(switch)]
+    tui: bool,
 
-                        output.write(&line[..1])?;
-                        output.write(b" ")?;
-                        write_hex(&mut output, new_allocation_index)?;
-                        output.write(b"\n")?;
+    /// do not trim anything, instead print the size of the live heap at every timestamp as CSV,
+    /// with the columns timestamp_ms and live_bytes.
+    ///
+    /// Sizes come from replaying the "+" and "-" lines, so they are what the program allocated,
+    /// not its resident set size. This only reads stdin once, so it can be a pipe.
+    #[argh(switch)]
+    replay_csv: bool,
 
-                        largest_written_allocation_index =
-                            max(new_allocation_index, largest_written_allocation_index);
-                    }
-                }
-            }
-            b'a' => {
-                if !is_skipping {
-                    output.write(&line)?;
-                }
-            }
-            _ => {
-                output.write(&line)?;
-            }
-        }
+    /// with --replay-csv, print at most N points, for plotting.
+    ///
+    /// The profile is split into N parts of the same duration, and the timestamp with the
+    /// largest live heap of each part is printed, so peaks are never lost, but what happened
+    /// between them is.
+    #[argh(option, arg_name = "n")]
+    timeline_points: Option<usize>,
+
+    /// do not trim anything, instead compare the live heap to the resident set size at every
+    /// "R" line, to estimate the overhead of the allocator, as text, csv or json.
+    ///
+    /// The overhead is the resident set size minus the live heap. This is an estimate: the
+    /// resident set size also includes code, stacks and memory not allocated with malloc, so
+    /// changes over time say more than the overhead itself, and it can even be negative when
+    /// allocated memory was never touched or swapped out. This only reads stdin once, so it can
+    /// be a pipe.
+    #[argh(
+        option,
+        arg_name = "format",
+        from_str_fn(fragmentation::parse_report_format)
+    )]
+    fragmentation_report: Option<fragmentation::ReportFormat>,
+
+    /// only keep allocations of exactly this many bytes. can be given several times to keep
+    /// allocations of any of the sizes.
+    #[argh(option, arg_name = "bytes")]
+    exact_size: Vec<u64>,
+
+    /// only keep allocations whose backtrace is at least N frames deep.
+    ///
+    /// The depth is the number of "t" lines from the trace of the allocation to the root, which
+    /// is the number of instruction pointers in its backtrace. Functions inlined at an
+    /// instruction pointer do not count as frames of their own.
+    #[argh(option, arg_name = "n")]
+    min_trace_depth: Option<u64>,
+
+    /// only keep the allocations of the N traces that allocated the most bytes, to focus on the
+    /// dominant allocation sites.
+    ///
+    /// This reads the profile twice, so stdin has to be a file, not a pipe. Traces are ranked by
+    /// the sum of the sizes of all their allocations in the whole profile, including the
+    /// skipped parts, on ties the trace defined first wins. Frees are attributed like with
+    /// --lifetime-percentile.
+    #[argh(option, arg_name = "n")]
+    max_traces: Option<usize>,
+
+    /// only keep allocations that were reallocated to at least FACTOR times their original
+    /// size, to find buffers that keep growing.
+    ///
+    /// This reads the profile twice, so stdin has to be a file, not a pipe. heaptrack does not
+    /// mark reallocations, a free directly followed by an allocation counts as one, so a free
+    /// followed by an unrelated allocation does too. All allocations of a growing chain of
+    /// reallocations are kept, with their frees.
+    #[argh(option, arg_name = "factor", from_str_fn(realloc::parse_growth_factor))]
+    min_realloc_growth: Option<f64>,
+
+    /// only keep allocations from sites that this baseline profile does not have, to see what
+    /// a change started allocating.
+    ///
+    /// A site is an allocation size with a backtrace, compared by the module, function, file and
+    /// line of every frame like with --stable-ids, so the baseline can come from a different
+    /// build. The baseline has to be uncompressed, and is read completely before the profile on
+    /// stdin, which is also read twice, so it has to be a file, not a pipe. Frees are kept if
+    /// their allocation is.
+    #[argh(option, arg_name = "path")]
+    diff_against: Option<PathBuf>,
+
+    /// fail after reading the whole input if it has fewer than N allocations, which usually means
+    /// the capture failed.
+    ///
+    /// The output is still written, but heaptrack-trim exits with an error.
+    #[argh(option, arg_name = "n")]
+    require_min_allocations: Option<u64>,
+
+    /// instead of rebasing allocation indices, replace each with an id derived from the size and
+    /// backtrace of the allocation, which is the same across profiles of the same program.
+    ///
+    /// For diffing allocations between profiles with other tools. heaptrack-gui cannot open the
+    /// output, it requires allocation indices to be numbered from 0.
+    #[argh(switch)]
+    stable_ids: bool,
+
+    /// drop the parts of the profile with fewer than N allocations and frees per second, and
+    /// the allocations made in them.
+    ///
+    /// This reads the profile twice, so stdin has to be a file, not a pipe. Parts are the
+    /// intervals between timestamps in the profile, which heaptrack records every few dozen
+    /// milliseconds. Frees of kept allocations are still written, and appear at the end of the
+    /// part before. Unless --preserve-time is given, the dropped parts are also cut out of the
+    /// timeline.
+    #[argh(option, arg_name = "n")]
+    min_alloc_rate: Option<f64>,
+
+    /// only keep allocations made while the resident set size of the process was between LOW and
+    /// HIGH bytes.
+    ///
+    /// heaptrack samples the resident set size about as often as it records timestamps, the most
+    /// recent sample before an allocation counts. Nothing is kept before the first sample. Frees
+    /// are attributed like with --lifetime-percentile, and kept if their allocation is.
+    #[argh(option, arg_name = "low:high", from_str_fn(rss::parse_rss_band))]
+    rss_band: Option<(u64, u64)>,
+
+    /// print how many allocations and frees each filter dropped, to stderr.
+    ///
+    /// The trimming itself counts as the first filter, followed by --lifetime-percentile,
+    /// --min-size-percentile, --min-alloc-rate, --max-traces, --min-realloc-growth,
+    /// --diff-against, --rss-band, --exact-size and --min-trace-depth, in that order, no matter
+    /// the order of the options. Every event counts for the first filter that drops it.
+    #[argh(switch)]
+    filter_stats: bool,
+
+    /// after trimming, write a JSON record of everything the trim did to this file: where
+    /// skipping stopped, how timestamps were rewritten, what every filter dropped, and the
+    /// statistics of --summary-only.
+    ///
+    /// The fields are described in the README. Sizes are given before and after --export,
+    /// compression by --pipe-to is not included.
+    #[argh(option, arg_name = "path")]
+    trim_report: Option<PathBuf>,
+
+    /// only keep every Nth "R" line, the first one and then one out of N, to make profiles with
+    /// very frequent resident set size samples smaller.
+    ///
+    /// The kept samples stay where they were between the "c" lines, so they keep their time.
+    /// The memory graph of heaptrack-gui still has the same overall shape, but short spikes
+    /// between the kept samples disappear. --rss-band still sees every sample.
+    #[argh(option, arg_name = "1/n", from_str_fn(rss::parse_rss_sampling))]
+    sample_rss: Option<u64>,
+
+    /// do not print anything to stderr except errors.
+    #[argh(switch)]
+    quiet: bool,
+
+    /// print every warning about the input as it is found, instead of only a summary at the end.
+    #[argh(switch)]
+    verbose: bool,
+
+    /// instead of stdout, write to the stdin of this command, for example "gzip > small.gz".
+    ///
+    /// The command is run with `sh -c`, so never pass untrusted input here. heaptrack-trim exits
+    /// with the exit code of the command.
+    #[argh(option, arg_name = "command")]
+    pipe_to: Option<String>,
+
+    /// instead of stdout, write to this URL: http:// is uploaded with a PUT request, file:// and
+    /// URLs without a scheme are files, and - is stdout.
+    ///
+    /// The output is streamed as it is written, and heaptrack-trim fails if the server does not
+    /// answer with a 2xx status. The environment variable HEAPTRACK_TRIM_AUTHORIZATION is sent as
+    /// the Authorization header, in cleartext since there is no TLS, with a warning unless the
+    /// server is on loopback or --quiet is given. https:// and s3:// are not supported, use --pipe-to with a
+    /// program that uploads, for example "aws s3 cp - s3://bucket/key", instead.
+    #[cfg(feature = "upload")]
+    #[argh(option, arg_name = "url")]
+    output_url: Option<String>,
+
+    /// with --pipe-to, after the command exits, decompress this file that it wrote and check that
+    /// it contains exactly the output.
+    ///
+    /// Guards against a broken compressor or disk, at the cost of decompressing all of the output
+    /// again, which usually takes a fraction of the time compressing took.
+    #[argh(option, arg_name = "path")]
+    verify_compression: Option<PathBuf>,
+
+    /// do not trim anything, instead check the profile on stdin for corruption.
+    ///
+    /// Exits with 1 if any problems were found. Use --verbose to see every problematic line.
+    #[argh(switch)]
+    verify: bool,
+
+    /// like --verify, and also check that every "R" line has a resident set size in hex and a
+    /// newer timestamp than the one before it.
+    ///
+    /// heaptrack writes one "R" line after every "c" line, samples before the first "c" line
+    /// or without a "c" line since the last one are reported.
+    #[argh(switch)]
+    validate_monotonic_rss: bool,
+
+    /// claim that the output has file format version N, no matter what the input has.
+    ///
+    /// For opening profiles in a heaptrack-gui that refuses the file format version of the
+    /// heaptrack that recorded it. This does not convert anything, if the formats actually differ,
+    /// heaptrack-gui will misinterpret the profile or crash.
+    #[argh(option, arg_name = "n")]
+    emit_version: Option<u64>,
+
+    /// instead of writing to stdout, write a new file after every N allocations, named
+    /// <split-prefix>.0, <split-prefix>.1 and so on.
+    ///
+    /// Every file can be opened on its own: it gets a copy of all strings, traces and other
+    /// metadata that came before it, its own numbering of allocations starting at 0, and its
+    /// timestamps start at 0 unless --preserve-time is given. Frees of allocations from previous
+    /// files are dropped. --skip-seconds is optional in this mode.
+    #[argh(option, arg_name = "n")]
+    split_every_allocations: Option<u64>,
+
+    /// path prefix of the files written by --split-every-allocations.
+    #[argh(option, arg_name = "prefix")]
+    split_prefix: Option<PathBuf>,
+
+    /// read trim commands from stdin, one per line, and trim the profile given with --input once
+    /// for every command, into the files `<prefix>.0`, `<prefix>.1`, ... of --output-prefix.
+    ///
+    /// A command is a line of options like `--skip-seconds 10 --exact-size 16`: either
+    /// --skip-seconds or --start-from-index, optionally with --exact-size, --min-trace-depth,
+    /// --rss-band and --preserve-time. These replace the same options of the command line, all
+    /// other options of the command line apply to every command. Empty lines and lines starting
+    /// with "#" are ignored. The path of every file is printed to stdout once it is written, a
+    /// command that cannot be parsed is reported on stderr and writes no file. The profile is
+    /// read into memory once, so its size is limited by the memory available.
+    #[argh(switch)]
+    commands_from_stdin: bool,
+
+    /// path prefix of the files written by --commands-from-stdin.
+    #[argh(option, arg_name = "prefix")]
+    output_prefix: Option<PathBuf>,
+
+    /// with --split-every-allocations, also write a JSON array describing every file written to
+    /// this path.
+    ///
+    /// Every element has the "path" of the file, the "start_ms" timestamp and "input_offset" byte
+    /// offset in the input where the file starts, and "stats" like --summary-only --json prints
+    /// them, where "profile_duration_ms" is the input timestamp where the file ends.
+    #[argh(option, arg_name = "path")]
+    manifest: Option<PathBuf>,
+
+    /// write which allocation indices of the input were kept to this file, one line per index
+    /// with the index in the input and the index in the output, both in hex like in "+" lines.
+    ///
+    /// Lines are in the order in which the indices first appear in the output.
+    #[argh(option, arg_name = "path")]
+    kept_indices_out: Option<PathBuf>,
+
+    /// write which lines of the input were removed to this file, one line per range of
+    /// consecutive removed lines, like "3,7d" for lines 3 to 7 or "3d" for only line 3.
+    ///
+    /// Lines are numbered from 1. "12,$d" means that everything from line 12 to the end of the
+    /// input was removed. Lines that were kept but rewritten, such as timestamps and allocation
+    /// indices, are not listed.
+    #[argh(option, arg_name = "path")]
+    diff_out: Option<PathBuf>,
+
+    /// fail if the output references a string, instruction pointer, trace or allocation info
+    /// before defining it, which heaptrack-gui does not support.
+    #[argh(switch)]
+    check_ordering: bool,
+
+    /// instead of failing on a timestamp, allocation, free or other line that cannot be parsed,
+    /// drop it and continue, for salvaging what is left of a corrupt profile.
+    ///
+    /// Dropped lines are counted in the warnings, use --verbose to see all of them. Only what
+    /// heaptrack-trim needs to parse is checked, which are "c", "+" and "-" lines, and "a", "R"
+    /// or "t" lines with options that need them. The output has gaps where lines were dropped
+    /// and can still be broken in ways that heaptrack-gui does not accept, run --verify on it
+    /// to find out. Without this, trimming stops at the first such line, and prints it with
+    /// what it should look like and the lines before it.
+    ///
+    /// Only the trim itself skips lines, so this cannot be combined with options that read the
+    /// input before trimming or instead of it, like --max-traces or --lifetime-histogram.
+    #[argh(switch)]
+    skip_bad_lines: bool,
+
+    /// fail if the allocations in the output need a string, instruction pointer, trace or
+    /// allocation info that is not defined anywhere in the output, and list every such line.
+    ///
+    /// Only what the kept allocations need is checked: the allocation infos of "+" and "-"
+    /// lines, their traces up to the root, the instruction pointers of those and the strings
+    /// that the instruction pointers reference. Unlike --check-ordering, definitions may come
+    /// after they are used. This keeps everything the output defines in memory.
+    #[argh(switch)]
+    validate_header_completeness: bool,
+
+    /// do not write the trimmed profile anywhere, only print how large it would be to stdout.
+    ///
+    /// Unlike --null-output, this guarantees that no profile data ends up on stdout, even if it
+    /// is redirected to a file.
+    #[argh(switch)]
+    summary_only: bool,
+
+    /// hash every byte of the input into a key for caching the output.
+    ///
+    /// The key is 16 hex digits, and the same for the same input, no matter the other options,
+    /// so a cache has to add those to it itself. A byte order mark at the start of the input is
+    /// not part of it. The key is part of the statistics of --summary-only, and otherwise
+    /// printed to stderr after trimming.
+    #[argh(switch)]
+    cache_key: bool,
+
+    /// do not trim anything, instead print the metadata of the profile on stdin: its version,
+    /// the command line of the profiled program, the page size and physical memory of the
+    /// system, and whether heaptrack was attached to a running process.
+    ///
+    /// Only the header is read, up to the first timestamp. heaptrack does not record when the
+    /// profile was captured or which allocator the program used. With --json, the metadata is
+    /// printed as a JSON object, with null for what the header does not have.
+    #[argh(switch)]
+    dump_header: bool,
+
+    /// do not trim anything, instead quickly guess how large the output of --skip-seconds would
+    /// be, from the first and last 4MiB of the profile.
+    ///
+    /// stdin has to be an uncompressed file, not a pipe. The guess assumes that allocations are
+    /// spread evenly over the profile, use --summary-only for exact numbers.
+    #[argh(switch)]
+    estimate: bool,
+
+    /// the format of the output: heaptrack (the default), ndjson, one JSON object per line,
+    /// packed, heaptrack's format with consecutive "+" and "-" lines packed into one line,
+    /// allocations-only, heaptrack's format with only the "a", "+" and "-" lines and the "c"
+    /// lines before them, or folded, the folded stacks flamegraph tools read.
+    ///
+    /// Only heaptrack can be opened in heaptrack-gui, the others are meant for other tools.
+    /// folded writes one line per backtrace with the bytes it allocated in the output, summed
+    /// over all of its allocations, freed or not, for example "main;parse;malloc 4096", only
+    /// once the whole profile was read.
+    /// allocations-only drops the header, strings and traces, which heaptrack-gui needs.
+    /// packed output is smaller, but NOT a valid heaptrack profile: heaptrack-gui refuses to
+    /// open it. Use --unpack to turn it back into one. A packed line is "p " followed by up to
+    /// --allocations-per-line events without separators, for example "p +0+1a-0".
+    #[argh(
+        option,
+        default = "export::ExportFormat::Heaptrack",
+        arg_name = "format",
+        from_str_fn(export::parse_export_format)
+    )]
+    export: export::ExportFormat,
+
+    /// with --export packed, how many "+" and "-" events to pack into one line at most. defaults
+    /// to 64.
+    #[argh(option, arg_name = "n")]
+    allocations_per_line: Option<usize>,
+
+    /// do not trim anything, instead turn a profile written with --export packed back into one
+    /// that heaptrack-gui can open.
+    #[argh(switch)]
+    unpack: bool,
+
+    /// write the strings sorted by their bytes, all at the place of the first one, and renumber
+    /// their references, so that profiles of the same program have the same strings in the same
+    /// order and can be compared with diff.
+    ///
+    /// This reads the profile twice, so stdin has to be a file, not a pipe, and keeps all strings
+    /// in memory.
+    #[argh(switch)]
+    sort_strings: bool,
+
+    /// pad the allocation indices in "+" and "-" lines with leading zeros to N hex digits, for
+    /// tools that expect them to have a fixed width.
+    ///
+    /// heaptrack writes indices without leading zeros, and heaptrack-gui is not meant to read
+    /// anything else: do not use this for profiles you want to open in it. Larger indices are
+    /// still written in full. Not supported with --export formats other than heaptrack.
+    #[argh(option, arg_name = "n", from_str_fn(parse_index_width))]
+    index_width: Option<usize>,
+
+    /// how to rewrite the allocation indices of the kept "+" and "-" lines: dense (the default),
+    /// shift or none.
+    ///
+    /// dense subtracts the number of allocations referenced before the kept part, so that the
+    /// first kept one becomes 0, as heaptrack-gui requires. shift subtracts the number of "a"
+    /// lines dropped before the kept part instead, so that every index still points at the right
+    /// "a" line and differs from the one in the input by the same constant, but the first one
+    /// may not be 0. none keeps every "a" line and leaves indices as in the input, which makes
+    /// cross-referencing with the full profile easiest. heaptrack-gui cannot open the output of
+    /// shift or none.
+    #[argh(
+        option,
+        default = "RebaseMode::Dense",
+        arg_name = "mode",
+        from_str_fn(parse_rebase_mode)
+    )]
+    rebase_mode: RebaseMode,
+
+    /// end every line of the output with lf (the default) or crlf, no matter how the lines of
+    /// the input end.
+    #[argh(
+        option,
+        default = "Newline::Lf",
+        arg_name = "lf|crlf",
+        from_str_fn(parse_newline)
+    )]
+    newline: Newline,
+
+    /// refuse to do anything if stdin was last written to longer than this ago, for example 12h
+    /// or 7d, to skip stale captures in automated pipelines.
+    ///
+    /// heaptrack does not record when a profile was captured, so this is based on the
+    /// modification time of the file, which is when heaptrack finished writing it. stdin has to
+    /// be a file, not a pipe, whose modification time means nothing. Units are s, m, h and d,
+    /// without a unit the duration is in seconds.
+    #[argh(option, arg_name = "duration", from_str_fn(age::parse_duration))]
+    max_age: Option<Duration>,
+
+    /// after trimming, write the allocations that were never freed to this file, grouped by
+    /// size and trace, the most leaked bytes first.
+    ///
+    /// This is the state at the end of the input, not of the output, and includes allocations
+    /// that were skipped. If the profile ended before the process exited, the allocations
+    /// that were still in use count as leaked, unlike in heaptrack-gui, which only counts what
+    /// was still allocated when the process exited. Traces are in hex, like in "a" lines. With
+    /// --json, the report is a JSON object instead.
+    #[argh(option, arg_name = "path")]
+    leak_report: Option<PathBuf>,
+
+    /// round the size of every allocation up to a multiple of this many bytes, for example
+    /// 4096, to look at the pages allocations occupy rather than the bytes they asked for.
+    ///
+    /// This only changes what --exact-size, --min-size-percentile, --max-traces and
+    /// --leak-report see, they compare and sum the rounded sizes. The output keeps the sizes of the input, unless
+    /// --rewrite-page-sizes is given.
+    #[argh(option, arg_name = "bytes", from_str_fn(pages::parse_page_size))]
+    normalize_sizes_to_pages: Option<u64>,
+
+    /// with --normalize-sizes-to-pages, also write the rounded sizes into the "a" lines of the
+    /// output.
+    ///
+    /// This changes the data: heaptrack-gui then shows every allocation with its rounded size,
+    /// as if the program had asked for that much, and allocation infos that only differed in
+    /// size can become duplicates, which --verify reports.
+    #[argh(switch)]
+    rewrite_page_sizes: bool,
+
+    /// print the statistics of --summary-only and --dump-header, and write --leak-report and
+    /// --progress-file, as JSON.
+    #[argh(switch)]
+    json: bool,
+
+    /// instead of trimming, write a tiny profile with only the first allocation of the given
+    /// allocation index (hex, as in "+" lines), its free, and the part of the header it needs.
+    ///
+    /// Timestamps start at the allocation unless --preserve-time is given.
+    #[argh(option, arg_name = "hex", from_str_fn(parse_hex_arg))]
+    isolate_index: Option<u64>,
+
+    /// keep reading stdin after reaching its end, like `tail -f`, to trim a profile while
+    /// heaptrack is still writing it.
+    ///
+    /// stdin has to be an uncompressed file. The output is flushed whenever there is no new input,
+    /// and heaptrack-trim exits once the input has not grown for --watch-idle-seconds.
+    #[argh(switch)]
+    watch: bool,
+
+    /// with --watch, how many seconds without new input mean that the profile is complete.
+    /// defaults to 10.
+    #[argh(option, default = "10", arg_name = "seconds")]
+    watch_idle_seconds: u64,
+
+    /// print progress, and with --verbose warnings, at most once every N milliseconds. defaults
+    /// to 1000.
+    #[argh(option, default = "1000", arg_name = "ms")]
+    log_interval_ms: u64,
+
+    /// write progress to this file instead of stderr, one line per update, or with --json one
+    /// JSON object per line, with the fields input_bytes and timestamp_ms.
+    ///
+    /// Warnings, errors and everything else still go to stderr. --quiet only silences stderr,
+    /// so progress is still written to the file with it.
+    #[argh(option, arg_name = "path")]
+    progress_file: Option<PathBuf>,
+}
+
+/// Smaller buffers work, but make the tool orders of magnitude slower for no good reason.
+const MIN_BUF_SIZE: usize = 1 << 10;
+
+fn parse_buf_size(value: &str) -> Result<usize, String> {
+    let size = value
+        .parse()
+        .map_err(|e| format!("invalid --buf-size {:?}: {}", value, e))?;
+    if size < MIN_BUF_SIZE {
+        return Err(format!(
+            "--buf-size must be at least {} bytes, got {}",
+            MIN_BUF_SIZE, size
+        ));
     }
+    Ok(size)
+}
 
-    eprintln!(
-        "done. total time of profile was {}",
-        current_abs_timestamp_ms
-    );
-    Ok(())
+fn parse_index_width(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(width @ 1..=16) => Ok(width),
+        _ => Err(format!(
+            "invalid --index-width {:?}, expected 1 to 16 hex digits",
+            value
+        )),
+    }
 }
 
-#[inline]
-fn parse_hex(input: &[u8]) -> Result<u64, ()> {
-    let mut rv = 0u64;
-    for c in input {
-        rv *= 16;
-        rv |= match c {
-            b'0'..=b'9' => c - b'0',
-            b'a'..=b'f' => 10 + c - b'a',
-            b'A'..=b'F' => 10 + c - b'A',
-            _ => return Err(()),
-        } as u64;
+fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent),
+        _ => Err(format!(
+            "invalid percentage {:?}, expected a number from 0 to 100",
+            value
+        )),
     }
+}
 
-    Ok(rv)
+fn parse_hex_arg(value: &str) -> Result<u64, String> {
+    parse_hex(value.as_bytes()).map_err(|()| format!("invalid hex number {:?}", value))
 }
 
-// do not use writeln!(), format strings or from_str_radix, those are slow and handle more
-// edgecases than we need.
-#[inline]
-fn write_hex(mut writer: impl Write, input: u64) -> Result<(), io::Error> {
-    if input == 0 {
-        writer.write(b"0")?;
-        return Ok(());
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Newline {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl Newline {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Newline::Lf => b"\n",
+            Newline::Crlf => b"\r\n",
+        }
+    }
+}
+
+fn parse_newline(value: &str) -> Result<Newline, String> {
+    match value {
+        "lf" => Ok(Newline::Lf),
+        "crlf" => Ok(Newline::Crlf),
+        _ => Err(format!("expected lf or crlf, got {:?}", value)),
     }
+}
 
-    for byte in input.to_be_bytes() {
-        for c in [(byte / 16) as u8, (byte % 16) as u8] {
-            if c != 0 {
-                writer.write(&[if c < 10 { b'0' + c } else { b'a' + (c - 10) }])?;
-            }
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum RebaseMode {
+    #[default]
+    Dense,
+    Shift,
+    None,
+}
+
+impl RebaseMode {
+    fn name(self) -> &'static str {
+        match self {
+            RebaseMode::Dense => "dense",
+            RebaseMode::Shift => "shift",
+            RebaseMode::None => "none",
         }
     }
+}
 
-    Ok(())
+fn parse_rebase_mode(value: &str) -> Result<RebaseMode, String> {
+    match value {
+        "dense" => Ok(RebaseMode::Dense),
+        "shift" => Ok(RebaseMode::Shift),
+        "none" => Ok(RebaseMode::None),
+        _ => Err(format!("expected dense, shift or none, got {:?}", value)),
+    }
 }
 
-#[test]
-fn test_hex() {
-    assert_eq!(parse_hex(b"1"), 1);
-    assert_eq!(parse_hex(b"a"), 10);
-    assert_eq!(parse_hex(b"7d0"), 2000);
-    assert_eq!(parse_hex(b"3e8"), 1000);
+/// How much of each end of the profile --estimate reads.
+const ESTIMATE_SAMPLE_SIZE: u64 = 4 << 20;
+
+/// The file format versions whose layout is described in `run_main`. Version 1 did not have "a"
+/// lines yet.
+const SUPPORTED_FILE_FORMATS: [u64; 2] = [2, 3];
+
+/// What to cut out of the profile. Timestamps are in milliseconds since the start of the profile.
+#[derive(Default)]
+struct TrimOptions {
+    /// drop everything until the first timestamp after this one, 0 drops nothing
+    skip_timestamp: u64,
+    /// instead of skip_timestamp, drop everything until this allocation index is first
+    /// referenced, in a "+" or "-" line
+    start_index: Option<u64>,
+    /// instead of skip_timestamp, drop everything until a "#" line with the first text, and
+    /// from one with the second
+    between_markers: Option<(Vec<u8>, Vec<u8>)>,
+    /// drop everything from the first timestamp after this one
+    stop_timestamp: Option<u64>,
+    preserve_time: bool,
+    /// added to every rewritten timestamp, negative results are clamped to 0
+    time_offset_ms: i64,
+    /// drop "c" lines closer than this to the last written one
+    merge_timestamps_ms: Option<u64>,
+    /// drop +/- lines of allocations these don't keep, named by their option
+    allocation_filters: Vec<(&'static str, lifetime::AllocationFilter)>,
+    /// drop +/- lines of allocations smaller than this, see `sizes`
+    min_size: Option<u64>,
+    /// per interval between "c" lines, whether to drop it, see `rate`
+    idle_intervals: Option<Vec<bool>>,
+    /// drop +/- lines of allocations made while the RSS was outside of this band
+    rss_band: Option<(u64, u64)>,
+    /// only write every Nth "R" line
+    sample_rss: Option<u64>,
+    /// if not empty, drop +/- lines of allocations with other sizes
+    exact_sizes: Vec<u64>,
+    /// drop +/- lines of allocations with shallower backtraces
+    min_trace_depth: Option<u64>,
+    /// fail if the input has fewer "+" lines
+    min_allocations: Option<u64>,
+    /// write ids from `stable_id` instead of allocation indices
+    stable_ids: bool,
+    /// how to rewrite allocation indices otherwise
+    rebase_mode: RebaseMode,
+    /// where to write the mapping of kept allocation indices
+    kept_indices_out: Option<PathBuf>,
+    /// where to write the ranges of removed input lines
+    diff_out: Option<PathBuf>,
+    /// the line ending of every output line
+    newline: Newline,
+    /// applied by `trim`, `run_main` always writes heaptrack's format
+    export: export::ExportFormat,
+    /// pad allocation indices in "+" and "-" lines to this many hex digits
+    index_width: usize,
+    /// where to write the allocations never freed in the input, and whether as JSON
+    leak_report: Option<PathBuf>,
+    leak_report_json: bool,
+    /// round sizes of "a" lines up to multiples of this, for the filters and the leak report
+    page_size: Option<u64>,
+    /// also write the rounded sizes
+    rewrite_page_sizes: bool,
+    /// drop malformed lines instead of failing
+    skip_bad_lines: bool,
+    /// applied by `trim`, see `verify::CheckOrdering`
+    check_ordering: bool,
+    /// applied by `trim`, see `strings::SortStrings`
+    string_order: Option<strings::StringOrder>,
+    /// applied by `trim`, see `verify::HeaderCompleteness`
+    validate_header_completeness: bool,
+    /// rewrite the file format version in the "v" line
+    emit_file_format: Option<u64>,
+    verbosity: Verbosity,
+    /// how often to print progress and warnings
+    log_interval_ms: u64,
+    /// where to write progress instead of stderr, and whether as JSON
+    progress_file: Option<PathBuf>,
+    progress_json: bool,
+    /// print how many events each filter dropped
+    filter_stats: bool,
+    /// where to write the `report::TrimReport`
+    trim_report: Option<PathBuf>,
+    /// hash the input into `TrimStats::cache_key`
+    cache_key: bool,
 }
 
-#[test]
-fn basic() {
-    use std::io::Cursor;
+impl TrimOptions {
+    /// Whether skipping stops at skip_timestamp, rather than at something that is only found
+    /// while reading.
+    fn skips_by_time(&self) -> bool {
+        self.start_index.is_none() && self.between_markers.is_none()
+    }
 
-    let mut output = Vec::<u8>::new();
-    run_main(
-        1000,
-        false,
+    /// Whether "a" lines have to be parsed. The default trim only copies them, which matters for
+    /// throughput since there can be millions of them.
+    fn parses_allocation_infos(&self) -> bool {
+        !self.exact_sizes.is_empty()
+            || self.min_size.is_some()
+            || self.min_trace_depth.is_some()
+            || self.leak_report.is_some()
+            || self.rewrite_page_sizes
+    }
+}
+
+fn main() {
+    let cli: Cli = argh::from_env();
+
+    let verbosity = match (cli.quiet, cli.verbose) {
+        (false, false) => Verbosity::Normal,
+        (true, false) => Verbosity::Quiet,
+        (false, true) => Verbosity::Verbose,
+        (true, true) => {
+            eprintln!("--quiet and --verbose cannot be used together");
+            process::exit(1);
+        }
+    };
+
+    if let Some(path) = cli.probe {
+        match format::probe(&path) {
+            Ok((compression, version)) => {
+                println!("{}, {}", compression, version);
+                return;
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // hacks to get large stdio buffer, through duplicates of the stdio file descriptors so that
+    // dropping them on any return path does not close stdio
+    let stdin = match &cli.input {
+        Some(path) => match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        None => exit_on_error(duplicate_stdio(io::stdin().as_fd())),
+    };
+    let stdout = exit_on_error(duplicate_stdio(io::stdout().as_fd()));
+    let buf_size = cli.buf_size;
+
+    let mut reader = BufReader::with_capacity(buf_size, stdin);
+    let mut writer = BufWriter::with_capacity(buf_size, stdout);
+    exit_on_error(text::skip_byte_order_mark(&mut reader));
+
+    if let Some(max_age) = cli.max_age {
+        let metadata = match reader.get_ref().metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                eprintln!("--max-age: cannot read the modification time: {}", e);
+                process::exit(1);
+            }
+        };
+        if !metadata.is_file() {
+            eprintln!("--max-age needs stdin to be a file, it cannot be a pipe");
+            process::exit(1);
+        }
+        if let Err(e) = age::check_age(metadata.modified(), SystemTime::now(), max_age) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(file_format) = cli.emit_version {
+        if verbosity != Verbosity::Quiet {
+            eprintln!(
+                "WARNING: writing file format version {} regardless of the input. If the input has \
+                 a different format, heaptrack-gui will misinterpret the output.",
+                file_format
+            );
+        }
+    }
+
+    if cli.summary_only && (cli.pipe_to.is_some() || cli.split_every_allocations.is_some()) {
+        eprintln!("--summary-only cannot be combined with options that write output");
+        process::exit(1);
+    }
+
+    if cli.commands_from_stdin {
+        if cli.input.is_none() || cli.output_prefix.is_none() {
+            eprintln!("--commands-from-stdin requires --input and --output-prefix");
+            process::exit(1);
+        }
+        if cli.watch || cli.pipe_to.is_some() || cli.summary_only || cli.null_output {
+            eprintln!(
+                "--commands-from-stdin cannot be combined with --watch, --pipe-to, \
+                 --summary-only or --null-output"
+            );
+            process::exit(1);
+        }
+    } else if cli.output_prefix.is_some() {
+        eprintln!("--output-prefix requires --commands-from-stdin");
+        process::exit(1);
+    }
+
+    #[cfg(feature = "upload")]
+    if cli.output_url.is_some()
+        && (cli.pipe_to.is_some()
+            || cli.summary_only
+            || cli.null_output
+            || cli.watch
+            || cli.commands_from_stdin)
+    {
+        eprintln!(
+            "--output-url cannot be combined with --pipe-to, --summary-only, --null-output, \
+             --watch or --commands-from-stdin"
+        );
+        process::exit(1);
+    }
+
+    if cli.watch
+        && (cli.hotspot_window.is_some()
+            || cli.lifetime_percentile.is_some()
+            || cli.min_size_percentile.is_some()
+            || cli.min_alloc_rate.is_some()
+            || cli.max_traces.is_some()
+            || cli.min_realloc_growth.is_some()
+            || cli.diff_against.is_some()
+            || cli.sort_strings)
+    {
+        eprintln!("--watch cannot be combined with options that read the input twice");
+        process::exit(1);
+    }
+
+    if cli.skip_bad_lines
+        && (cli.hotspot_window.is_some()
+            || cli.lifetime_percentile.is_some()
+            || cli.min_size_percentile.is_some()
+            || cli.min_alloc_rate.is_some()
+            || cli.max_traces.is_some()
+            || cli.min_realloc_growth.is_some()
+            || cli.lifetime_histogram
+            || cli.replay_csv
+            || cli.fragmentation_report.is_some()
+            || cli.split_every_allocations.is_some()
+            || cli.isolate_index.is_some())
+    {
+        eprintln!(
+            "--skip-bad-lines only applies to trimming, it cannot be combined with options that \
+             read the input before trimming or instead of it"
+        );
+        process::exit(1);
+    }
+
+    if cli.index_width.is_some() {
+        if cli.export != export::ExportFormat::Heaptrack {
+            eprintln!("--index-width requires --export heaptrack");
+            process::exit(1);
+        }
+        if verbosity != Verbosity::Quiet {
+            eprintln!(
+                "WARNING: padding allocation indices with --index-width, heaptrack-gui is not \
+                 meant to open the output."
+            );
+        }
+    }
+
+    if cli.rebase_mode != RebaseMode::Dense
+        && cli.export == export::ExportFormat::Heaptrack
+        && verbosity != Verbosity::Quiet
+    {
+        eprintln!(
+            "WARNING: not rebasing allocation indices densely with --rebase-mode, heaptrack-gui \
+             cannot open the output."
+        );
+    }
+
+    if cli.rewrite_page_sizes && cli.normalize_sizes_to_pages.is_none() {
+        eprintln!("--rewrite-page-sizes requires --normalize-sizes-to-pages");
+        process::exit(1);
+    }
+
+    if cli.time_offset_ms != 0 && cli.preserve_time {
+        eprintln!("--time-offset-ms cannot be combined with --preserve-time");
+        process::exit(1);
+    }
+
+    if cli.verify_compression.is_some() && cli.pipe_to.is_none() {
+        eprintln!("--verify-compression requires --pipe-to");
+        process::exit(1);
+    }
+
+    if cli.manifest.is_some() && cli.split_every_allocations.is_none() {
+        eprintln!("--manifest requires --split-every-allocations");
+        process::exit(1);
+    }
+
+    let export = match (cli.export, cli.allocations_per_line) {
+        (_, Some(0)) => {
+            eprintln!("--allocations-per-line must be at least 1");
+            process::exit(1);
+        }
+        (export::ExportFormat::Packed(_), Some(n)) => export::ExportFormat::Packed(n),
+        (_, Some(_)) => {
+            eprintln!("--allocations-per-line requires --export packed");
+            process::exit(1);
+        }
+        (export, None) => export,
+    };
+
+    if cli.verify || cli.validate_monotonic_rss {
+        let mut warnings = Warnings::new(verbosity).throttled(Throttle::new(
+            Box::new(SystemClock::new()),
+            cli.log_interval_ms,
+        ));
+        exit_on_error(verify::verify(
+            &mut reader,
+            cli.validate_monotonic_rss,
+            &mut warnings,
+        ));
+        warnings.write_summary(io::stderr()).unwrap();
+        if !warnings.is_empty() {
+            process::exit(1);
+        }
+        if verbosity != Verbosity::Quiet {
+            eprintln!("no problems found");
+        }
+        return;
+    }
+
+    if cli.dump_header {
+        let metadata = exit_on_error(metadata::read_metadata(&mut reader));
+        if cli.json {
+            metadata.write_json(io::stdout()).unwrap();
+            println!();
+        } else {
+            println!("{}", metadata);
+        }
+        return;
+    }
+
+    #[cfg(feature = "tui")]
+    if cli.tui {
+        let tty = match File::open("/dev/tty") {
+            Ok(tty) => BufReader::new(tty),
+            Err(e) => {
+                eprintln!("--tui needs a terminal: /dev/tty: {}", e);
+                process::exit(1);
+            }
+        };
+        if let Err(e) = tui::run(&mut reader, tty, io::stdout()) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if cli.lifetime_histogram {
+        let lifetimes = exit_on_error(lifetime::lifetimes(&mut reader, false));
+        lifetimes.write_histogram(io::stdout()).unwrap();
+        return;
+    }
+
+    if cli.timeline_points.is_some() && !cli.replay_csv {
+        eprintln!("--timeline-points requires --replay-csv");
+        process::exit(1);
+    }
+
+    if cli.replay_csv {
+        let sizes = exit_on_error(replay::live_sizes(&mut reader));
+        let samples = match cli.timeline_points {
+            Some(0) => {
+                eprintln!("--timeline-points must be at least 1");
+                process::exit(1);
+            }
+            Some(points) => replay::downsample(&sizes.samples, points),
+            None => sizes.samples,
+        };
+        replay::write_csv(&samples, &mut writer).unwrap();
+        writer.flush().unwrap();
+        return;
+    }
+
+    if let Some(format) = cli.fragmentation_report {
+        let samples = exit_on_error(fragmentation::samples(&mut reader));
+        fragmentation::write_report(&samples, format, &mut writer).unwrap();
+        writer.flush().unwrap();
+        return;
+    }
+
+    if cli.unpack {
+        exit_on_error(export::unpack(&mut reader, &mut writer));
+        writer.flush().unwrap();
+        return;
+    }
+
+    if cli.estimate {
+        let Some(skip_seconds) = cli.skip_seconds else {
+            eprintln!("--estimate requires --skip-seconds");
+            process::exit(1);
+        };
+        let mut stdin = reader.into_inner();
+        match estimate::estimate(&mut stdin, skip_seconds * 1000, ESTIMATE_SAMPLE_SIZE) {
+            Ok(estimate) => println!("{}", estimate),
+            Err(e) if e.kind() == io::ErrorKind::NotSeekable => {
+                eprintln!(
+                    "--estimate needs stdin to be a file, it cannot be a pipe: {}",
+                    e
+                );
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        writer.flush().unwrap();
+        return;
+    }
+
+    if let Some(allocation_index) = cli.isolate_index {
+        if let Err(e) = isolate::isolate(
+            &mut reader,
+            &mut writer,
+            allocation_index,
+            cli.preserve_time,
+        ) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        writer.flush().unwrap();
+        return;
+    }
+
+    if let Some(allocations) = cli.split_every_allocations {
+        let Some(prefix) = &cli.split_prefix else {
+            eprintln!("--split-every-allocations requires --split-prefix");
+            process::exit(1);
+        };
+        if allocations == 0 {
+            eprintln!("--split-every-allocations has to be at least 1");
+            process::exit(1);
+        }
+        let chunks = exit_on_error(split::split_every_allocations(
+            &mut reader,
+            cli.skip_seconds.unwrap_or(0) * 1000,
+            allocations,
+            prefix,
+            buf_size,
+            cli.preserve_time,
+        ));
+        if let Some(path) = &cli.manifest {
+            let mut manifest = BufWriter::new(File::create(path).unwrap());
+            split::write_manifest(&chunks, &mut manifest).unwrap();
+            manifest.flush().unwrap();
+        }
+        if verbosity != Verbosity::Quiet {
+            eprintln!("wrote {} files", chunks.len());
+        }
+        return;
+    }
+
+    let between_markers = match &cli.between_markers[..] {
+        [] => None,
+        [start, end] => Some((start.as_bytes().to_vec(), end.as_bytes().to_vec())),
+        _ => {
+            eprintln!("--between-markers has to be given exactly twice, for the start and end");
+            process::exit(1);
+        }
+    };
+
+    if cli.around_ms.is_some() != cli.radius_seconds.is_some() {
+        eprintln!("--around-ms and --radius-seconds have to be given together");
+        process::exit(1);
+    }
+
+    let (skip_timestamp, stop_timestamp) = match (
+        cli.skip_seconds,
+        cli.hotspot_window,
+        cli.start_from_index,
+        cli.max_output_percent,
+        &between_markers,
+        cli.around_ms,
+    ) {
+        (None, None, None, None, None, None) if cli.commands_from_stdin => (0, None),
+        _ if cli.commands_from_stdin => {
+            eprintln!(
+                "with --commands-from-stdin, --skip-seconds and --start-from-index are given per \
+                 command, and --hotspot-window, --around-ms, --max-output-percent and \
+                 --between-markers are not supported"
+            );
+            process::exit(1);
+        }
+        (Some(skip_seconds), None, None, None, None, None) => (skip_seconds * 1000, None),
+        (None, None, Some(_), None, None, None) | (None, None, None, None, Some(_), None) => {
+            (0, None)
+        }
+        (None, Some(window_seconds), None, None, None, None) => {
+            // saturating, so that huge windows mean the rest of the profile
+            let window_ms = window_seconds.saturating_mul(1000);
+            let sizes = exit_on_error(replay::live_sizes(&mut reader));
+            if rewind(&mut reader).is_err() {
+                eprintln!("--hotspot-window needs to read stdin twice, it cannot be a pipe");
+                process::exit(1);
+            }
+            let skip_timestamp = sizes.hotspot(window_ms);
+            if verbosity != Verbosity::Quiet {
+                eprintln!(
+                    "live heap grew the most between profile timestamps {} and {}",
+                    skip_timestamp,
+                    skip_timestamp.saturating_add(window_ms)
+                );
+            }
+            (
+                skip_timestamp,
+                Some(skip_timestamp.saturating_add(window_ms)),
+            )
+        }
+        (None, None, None, None, None, Some(around_ms)) => {
+            let radius_ms = cli.radius_seconds.unwrap_or(0) * 1000;
+            let (skip_timestamp, stop_timestamp) = around_window(around_ms, radius_ms);
+            if verbosity != Verbosity::Quiet {
+                eprintln!(
+                    "keeping profile timestamps {} to {}",
+                    skip_timestamp, stop_timestamp
+                );
+            }
+            (skip_timestamp, Some(stop_timestamp))
+        }
+        (None, None, None, Some(percent), None, None) => {
+            let model = exit_on_error(budget::size_model(&mut reader));
+            if rewind(&mut reader).is_err() {
+                eprintln!("--max-output-percent needs to read stdin twice, it cannot be a pipe");
+                process::exit(1);
+            }
+            let max_output_bytes = (model.input_bytes as f64 * percent / 100.0) as u64;
+            let skip_timestamp = model.skip_timestamp(max_output_bytes);
+            if verbosity != Verbosity::Quiet {
+                eprintln!(
+                    "skipping to profile timestamp {} to write at most {} of {} bytes",
+                    skip_timestamp, max_output_bytes, model.input_bytes
+                );
+            }
+            (skip_timestamp, None)
+        }
+        _ => {
+            eprintln!(
+                "exactly one of --skip-seconds, --hotspot-window, --around-ms, \
+                 --start-from-index, --max-output-percent or --between-markers is required"
+            );
+            process::exit(1);
+        }
+    };
+
+    let mut options = TrimOptions {
+        skip_timestamp,
+        start_index: cli.start_from_index,
+        between_markers,
+        stop_timestamp,
+        preserve_time: cli.preserve_time,
+        time_offset_ms: cli.time_offset_ms,
+        merge_timestamps_ms: cli.merge_timestamps,
+        allocation_filters: Vec::new(),
+        min_size: None,
+        idle_intervals: None,
+        rss_band: cli.rss_band,
+        sample_rss: cli.sample_rss,
+        exact_sizes: cli.exact_size.clone(),
+        min_trace_depth: cli.min_trace_depth,
+        min_allocations: cli.require_min_allocations,
+        stable_ids: cli.stable_ids,
+        rebase_mode: cli.rebase_mode,
+        kept_indices_out: cli.kept_indices_out.clone(),
+        diff_out: cli.diff_out.clone(),
+        newline: cli.newline,
+        export,
+        skip_bad_lines: cli.skip_bad_lines,
+        leak_report: cli.leak_report.clone(),
+        leak_report_json: cli.json,
+        page_size: cli.normalize_sizes_to_pages,
+        rewrite_page_sizes: cli.rewrite_page_sizes,
+        index_width: cli.index_width.unwrap_or(0),
+        check_ordering: cli.check_ordering,
+        string_order: None,
+        validate_header_completeness: cli.validate_header_completeness,
+        emit_file_format: cli.emit_version,
+        verbosity,
+        log_interval_ms: cli.log_interval_ms,
+        progress_file: cli.progress_file.clone(),
+        progress_json: cli.json,
+        filter_stats: cli.filter_stats,
+        trim_report: cli.trim_report.clone(),
+        cache_key: cli.cache_key,
+    };
+
+    if let Some((low, high)) = cli.lifetime_percentile {
+        let lifetimes = exit_on_error(lifetime::lifetimes(&mut reader, true));
+        if rewind(&mut reader).is_err() {
+            eprintln!("--lifetime-percentile needs to read stdin twice, it cannot be a pipe");
+            process::exit(1);
+        }
+        options.allocation_filters.push((
+            "--lifetime-percentile",
+            lifetimes.percentile_filter(low, high),
+        ));
+    }
+
+    if let Some(percentile) = cli.min_size_percentile {
+        let cutoff = exit_on_error(sizes::percentile_filter(
+            &mut reader,
+            percentile,
+            cli.normalize_sizes_to_pages,
+        ));
+        if rewind(&mut reader).is_err() {
+            eprintln!("--min-size-percentile needs to read stdin twice, it cannot be a pipe");
+            process::exit(1);
+        }
+        if verbosity != Verbosity::Quiet {
+            eprintln!(
+                "--min-size-percentile keeps allocations of at least {} bytes",
+                cutoff
+            );
+        }
+        options.min_size = Some(cutoff);
+    }
+
+    if let Some(min_rate) = cli.min_alloc_rate {
+        let activity = exit_on_error(rate::activity(&mut reader, min_rate));
+        if rewind(&mut reader).is_err() {
+            eprintln!("--min-alloc-rate needs to read stdin twice, it cannot be a pipe");
+            process::exit(1);
+        }
+        options
+            .allocation_filters
+            .push(("--min-alloc-rate", activity.filter));
+        options.idle_intervals = Some(activity.idle);
+    }
+
+    if let Some(max_traces) = cli.max_traces {
+        let filter = exit_on_error(traces::top_traces_filter(
+            &mut reader,
+            max_traces,
+            cli.normalize_sizes_to_pages,
+        ));
+        if rewind(&mut reader).is_err() {
+            eprintln!("--max-traces needs to read stdin twice, it cannot be a pipe");
+            process::exit(1);
+        }
+        options.allocation_filters.push(("--max-traces", filter));
+    }
+
+    if let Some(min_factor) = cli.min_realloc_growth {
+        let (filter, chains) = exit_on_error(realloc::growth_filter(&mut reader, min_factor));
+        if rewind(&mut reader).is_err() {
+            eprintln!("--min-realloc-growth needs to read stdin twice, it cannot be a pipe");
+            process::exit(1);
+        }
+        if chains == 0 && verbosity != Verbosity::Quiet {
+            eprintln!(
+                "WARNING: --min-realloc-growth found no reallocations in the profile, nothing \
+                 is kept"
+            );
+        }
+        options
+            .allocation_filters
+            .push(("--min-realloc-growth", filter));
+    }
+
+    if let Some(path) = &cli.diff_against {
+        let baseline = match File::open(path) {
+            Ok(file) => baseline::baseline_sites(BufReader::with_capacity(buf_size, file)),
+            Err(e) => Err(e),
+        };
+        let baseline = match baseline {
+            Ok(baseline) => baseline,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        };
+        let (filter, new_sites, sites) =
+            exit_on_error(baseline::new_sites_filter(&mut reader, &baseline));
+        if rewind(&mut reader).is_err() {
+            eprintln!("--diff-against needs to read stdin twice, it cannot be a pipe");
+            process::exit(1);
+        }
+        if verbosity != Verbosity::Quiet {
+            eprintln!(
+                "{} of {} allocation sites are not in the baseline",
+                new_sites, sites
+            );
+        }
+        options.allocation_filters.push(("--diff-against", filter));
+    }
+
+    if cli.sort_strings {
+        let order = exit_on_error(strings::string_order(&mut reader));
+        if rewind(&mut reader).is_err() {
+            eprintln!("--sort-strings needs to read stdin twice, it cannot be a pipe");
+            process::exit(1);
+        }
+        options.string_order = Some(order);
+    }
+
+    if let (true, Some(prefix)) = (cli.commands_from_stdin, &cli.output_prefix) {
+        let mut profile = Vec::new();
+        reader.read_to_end(&mut profile).unwrap();
+        exit_on_error(run_commands(
+            &mut options,
+            &profile,
+            io::stdin().lock(),
+            prefix,
+            io::stdout(),
+        ));
+        writer.flush().unwrap();
+        return;
+    }
+
+    #[cfg(feature = "upload")]
+    if let Some(url) = &cli.output_url {
+        let mut output = match upload::open(url, buf_size, verbosity == Verbosity::Quiet) {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("{}: {}", url, e);
+                process::exit(1);
+            }
+        };
+        let stats = exit_on_error(trim(&options, &mut reader, &mut output));
+        if let Err(e) = output.finish() {
+            eprintln!("{}: {}", url, e);
+            process::exit(1);
+        }
+        print_cache_key(&stats);
+        writer.flush().unwrap();
+        return;
+    }
+
+    if let Some(command) = &cli.pipe_to {
+        let mut written = None;
+        let mut trim_stats = None;
+        let status = exit_on_error(pipe::pipe_to(command, buf_size, |output| {
+            let mut output = stats::HashingWriter::new(output);
+            trim_stats = Some(trim(&options, &mut reader, &mut output)?);
+            written = Some((output.bytes, output.hash));
+            Ok(())
+        }));
+        if let Some(stats) = &trim_stats {
+            print_cache_key(stats);
+        }
+        if let (Some(path), Some(written), true) =
+            (&cli.verify_compression, written, status.success())
+        {
+            if let Err(e) = format::verify_compressed(path, written.0, written.1) {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+            if verbosity != Verbosity::Quiet {
+                eprintln!("{}: verified", path.display());
+            }
+        }
+        process::exit(status.code().unwrap_or(1));
+    } else if cli.summary_only {
+        exit_on_error(summary_only(&options, &mut reader, io::stdout(), cli.json));
+    } else if cli.null_output {
+        let stats = exit_on_error(trim(&options, &mut reader, io::sink()));
+        print_cache_key(&stats);
+    } else if cli.watch {
+        let output = RefCell::new(&mut writer);
+        let input = watch::Follow::new(
+            &mut reader,
+            Box::new(SystemClock::new()),
+            cli.watch_idle_seconds * 1000,
+            || output.borrow_mut().flush(),
+        );
+        let input = BufReader::with_capacity(buf_size, input);
+        let stats = exit_on_error(trim(&options, input, watch::Shared(&output)));
+        print_cache_key(&stats);
+    } else {
+        let stats = exit_on_error(trim(&options, &mut reader, &mut writer));
+        print_cache_key(&stats);
+    }
+
+    writer.flush().unwrap();
+}
+
+fn duplicate_stdio(fd: BorrowedFd<'_>) -> io::Result<File> {
+    Ok(File::from(fd.try_clone_to_owned()?))
+}
+
+/// For --cache-key without --summary-only.
+fn print_cache_key(stats: &TrimStats) {
+    if let Some(cache_key) = stats.cache_key {
+        eprintln!("cache key: {:016x}", cache_key);
+    }
+}
+
+/// The skip and stop timestamps of --around-ms, clamped to the start of the profile.
+fn around_window(around_ms: u64, radius_ms: u64) -> (u64, u64) {
+    (
+        around_ms.saturating_sub(radius_ms),
+        around_ms.saturating_add(radius_ms),
+    )
+}
+
+/// For --commands-from-stdin: trim `profile` once for every command in `commands`, into
+/// `<prefix>.0`, `<prefix>.1`, ..., and print the path of every file to `report` once it is
+/// written. Returns how many files were written.
+fn run_commands(
+    options: &mut TrimOptions,
+    profile: &[u8],
+    commands: impl BufRead,
+    prefix: &Path,
+    mut report: impl Write,
+) -> io::Result<u64> {
+    // from the command line, for commands without them
+    let exact_sizes = options.exact_sizes.clone();
+    let min_trace_depth = options.min_trace_depth;
+    let rss_band = options.rss_band;
+    let preserve_time = options.preserve_time;
+
+    let mut files = 0;
+    for line in commands.lines() {
+        let command = match commands::parse_command(&line?) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("invalid command: {}", e);
+                continue;
+            }
+        };
+        options.skip_timestamp = command.skip_seconds * 1000;
+        options.start_index = command.start_from_index;
+        options.exact_sizes = if command.exact_sizes.is_empty() {
+            exact_sizes.clone()
+        } else {
+            command.exact_sizes
+        };
+        options.min_trace_depth = command.min_trace_depth.or(min_trace_depth);
+        options.rss_band = command.rss_band.or(rss_band);
+        options.preserve_time = command.preserve_time || preserve_time;
+
+        let mut path = prefix.as_os_str().to_owned();
+        path.push(format!(".{}", files));
+        let mut output = BufWriter::new(File::create(&path)?);
+        trim(options, profile, &mut output)?;
+        output.flush()?;
+        files += 1;
+        writeln!(report, "{}", Path::new(&path).display())?;
+        report.flush()?;
+    }
+    Ok(files)
+}
+
+/// Print errors in the input, like malformed lines, instead of panicking on them.
+fn exit_on_error<T>(result: io::Result<T>) -> T {
+    match result {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Go back to the start of stdin for another pass.
+fn rewind(reader: &mut BufReader<File>) -> io::Result<()> {
+    reader.rewind()?;
+    text::skip_byte_order_mark(reader)
+}
+
+/// Trim into nothing, and write only the resulting statistics to `report`.
+fn summary_only(
+    options: &TrimOptions,
+    input: impl BufRead,
+    mut report: impl Write,
+    json: bool,
+) -> Result<(), io::Error> {
+    let stats = run_main(options, input, io::sink())?;
+    write_trim_report(options, &stats, stats.output_bytes)?;
+    if json {
+        stats.write_json(&mut report)?;
+        writeln!(report)
+    } else {
+        writeln!(report, "{}", stats)
+    }
+}
+
+/// `run_main`, optionally checking the references in the output, and exporting it to another
+/// format, then writing the --trim-report. `output_bytes` in the result does not include
+/// exporting.
+fn trim(
+    options: &TrimOptions,
+    input: impl BufRead,
+    output: impl Write,
+) -> Result<TrimStats, io::Error> {
+    let mut output = CountingWriter {
+        inner: output,
+        bytes: 0,
+    };
+    let stats = if options.export == export::ExportFormat::Heaptrack {
+        trim_heaptrack(options, input, &mut output)?
+    } else {
+        let mut export = export::Export::new(&mut output, options.export.output_format());
+        let stats = trim_heaptrack(options, input, &mut export)?;
+        export.finish()?;
+        stats
+    };
+    write_trim_report(options, &stats, output.bytes)?;
+    Ok(stats)
+}
+
+/// With --trim-report, write the report the trim collected. `exported_bytes` is the size of the
+/// output after --export.
+fn write_trim_report(
+    options: &TrimOptions,
+    stats: &TrimStats,
+    exported_bytes: u64,
+) -> io::Result<()> {
+    let (Some(path), Some(report)) = (&options.trim_report, &stats.report) else {
+        return Ok(());
+    };
+    let mut output = BufWriter::new(File::create(path)?);
+    report.write_json(stats, exported_bytes, &mut output)?;
+    output.flush()
+}
+
+fn trim_heaptrack(
+    options: &TrimOptions,
+    input: impl BufRead,
+    output: impl Write,
+) -> Result<TrimStats, io::Error> {
+    if !options.validate_header_completeness {
+        return trim_checking_ordering(options, input, output);
+    }
+
+    let mut output = verify::HeaderCompleteness::new(output);
+    let stats = trim_checking_ordering(options, input, &mut output)?;
+    output.finish()?;
+    Ok(stats)
+}
+
+fn trim_checking_ordering(
+    options: &TrimOptions,
+    input: impl BufRead,
+    output: impl Write,
+) -> Result<TrimStats, io::Error> {
+    if options.check_ordering {
+        trim_sorting_strings(options, input, verify::CheckOrdering::new(output))
+    } else {
+        trim_sorting_strings(options, input, output)
+    }
+}
+
+fn trim_sorting_strings(
+    options: &TrimOptions,
+    input: impl BufRead,
+    output: impl Write,
+) -> Result<TrimStats, io::Error> {
+    let Some(order) = &options.string_order else {
+        return run_main(options, input, output);
+    };
+
+    let mut output = strings::SortStrings::new(output, order);
+    let stats = run_main(options, input, &mut output)?;
+    output.finish()?;
+    Ok(stats)
+}
+
+/// The filters of the options, in the order described in `filter`.
+fn filter_chain(options: &TrimOptions) -> filter::FilterChain<'_> {
+    let mut filters = filter::FilterChain::default();
+    let mut allocation_filters = options.allocation_filters.iter().peekable();
+    // --min-size-percentile comes after --lifetime-percentile and before the other first passes
+    if let Some((name, filter)) =
+        allocation_filters.next_if(|(name, _)| *name == "--lifetime-percentile")
+    {
+        filters.push(name, Box::new(filter::FirstPassFilter::new(filter)));
+    }
+    if let Some(min_size) = options.min_size {
+        filters.push(
+            "--min-size-percentile",
+            Box::new(sizes::MinSize::new(min_size)),
+        );
+    }
+    for (name, filter) in allocation_filters {
+        filters.push(name, Box::new(filter::FirstPassFilter::new(filter)));
+    }
+    if let Some((low, high)) = options.rss_band {
+        filters.push("--rss-band", Box::new(rss::RssBand::new(low, high)));
+    }
+    if !options.exact_sizes.is_empty() {
+        filters.push(
+            "--exact-size",
+            Box::new(filter::ExactSizes::new(&options.exact_sizes)),
+        );
+    }
+    if let Some(min_depth) = options.min_trace_depth {
+        filters.push(
+            "--min-trace-depth",
+            Box::new(filter::MinTraceDepth::new(min_depth)),
+        );
+    }
+    filters
+}
+
+/// The "c" line where skipping stops at something other than a timestamp, since the one before
+/// was dropped.
+fn write_first_timestamp(
+    output: &mut impl Write,
+    options: &TrimOptions,
+    timestamp: u64,
+) -> io::Result<()> {
+    output.write_all(b"c ")?;
+    if options.preserve_time {
+        write_hex(&mut *output, timestamp)?;
+    } else {
+        write_hex(
+            &mut *output,
+            0u64.saturating_add_signed(options.time_offset_ms),
+        )?;
+    }
+    output.write_all(options.newline.as_bytes())
+}
+
+fn run_main(
+    options: &TrimOptions,
+    mut input: impl BufRead,
+    output: impl Write,
+) -> Result<TrimStats, io::Error> {
+    // the layout of a heaptrack profile was mostly reverse-engineered from C++ sourcecode of
+    // heaptrack-gui
+    // relevant files:
+    // https://github.com/KDE/heaptrack/blob/6b4ca14b78e46783750943afef71e4cb3cade537/src/analyze/accumulatedtracedata.cpp
+    // https://github.com/KDE/heaptrack/blob/6b4ca14b78e46783750943afef71e4cb3cade537/src/analyze/gui/parser.cpp
+    //
+    // parser.cpp is a subclass of accumulatedtracedata, and implements a few callbacks for
+    // GUI-specific datastructures. The meat of the parser is in accumulatedtracedata.
+    //
+    // tldr: after decompression, the file is plaintext, and each line in the file is a command.
+    // the command type is the first character on the line.
+    //
+    // * "c ..." command sets the current time since the start of the profile in milliseconds, in
+    //   hex. For example, "c deadbeef" means we are 0xdeadbeef milliseconds into the profile.
+    // * Allocations are registered with lines starting with "a ...", and get a sequential ID
+    //   starting from 0 called "allocation index."
+    // * Lines starting with "+ ..." or "- ..." refer to these allocations using their index in
+    //   hex. + means alloc, - means free.
+    // * There are a few other commands like "s ..." to index strings that then are referenced in
+    //   allocations. But this actually doesn't matter to us, because all that we are trying to do
+    //   is to reduce the file-size drastically, not make the smallest file possible. Since most
+    //   lines are + and -, it is actually sufficient to remove _only those_ and leave the rest
+    //   as-is, leaving potentially unused strings in the file.
+    // * However, the first reference to an allocation in -/+ lines MUST be 0.
+    //   If there is an allocation index N > 0 referenced in a -\+ command, an allocation index N - 1
+    //   must have been used before. Otherwise, heaptrack-gui segfaults as it tries to access
+    //   some internal array out of bounds. This means that we have to "rebase" all unfiltered
+    //   allocations to start at 0, and remove extraneous "a ..." lines.
+    //
+    // allocation_index_correction is the number of allocation indices that were referenced while
+    // skipping, i.e. the first index that is not skipped.
+    let mut allocation_index_correction = 0u64;
+    // for --rebase-mode shift, the number of "a" lines dropped while skipping. references to them
+    // are dropped too.
+    let mut dropped_allocation_infos = 0u64;
+    let mut largest_written_allocation_index = 0u64;
+
+    let mut warnings = Warnings::new(options.verbosity).throttled(Throttle::new(
+        Box::new(SystemClock::new()),
+        options.log_interval_ms,
+    ));
+    let mut progress = Throttle::new(Box::new(SystemClock::new()), options.log_interval_ms);
+    // the first progress message should come after one interval, not right away
+    progress.ready();
+    let mut progress_file = match &options.progress_file {
+        Some(path) => Some(LineWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let mut lineno = 0u64;
+    // number of "a" lines in the input, to detect references to undefined allocation infos
+    let mut allocation_infos = 0u64;
+    // number of "+" lines in the input, for --require-min-allocations
+    let mut allocations = 0u64;
+    let parses_allocation_infos = options.parses_allocation_infos();
+    let mut filters = filter_chain(options);
+    // number of "R" lines in the input, for --sample-rss
+    let mut rss_samples = 0u64;
+    let is_filtering = !filters.is_empty();
+    let mut leaks = options
+        .leak_report
+        .as_ref()
+        .map(|_| leak::LeakTracker::default());
+    let mut stable_ids = options.stable_ids.then(stable_id::StableIds::default);
+    let mut kept_indices = match &options.kept_indices_out {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    // by index in the output, for --kept-indices-out
+    let mut is_index_written = Vec::new();
+    let mut removed_lines = match &options.diff_out {
+        Some(path) => Some(diff::RemovedLines::new(BufWriter::new(File::create(path)?))),
+        None => None,
+    };
+    // whether the stop timestamp ended the loop before the end of the input
+    let mut is_truncated = false;
+    let mut is_start_marker_found = false;
+
+    let mut stats = TrimStats::default();
+    let mut cache_key = options.cache_key.then_some(stable_id::INITIAL);
+    let mut output = CountingWriter {
+        inner: output,
+        bytes: 0,
+    };
+
+    let newline = options.newline.as_bytes();
+    // the current line and the ones before it, for the context of errors, by line number
+    let mut recent_lines: [Vec<u8>; ERROR_CONTEXT_LINES + 1] = Default::default();
+
+    let mut is_skipping = !(options.skips_by_time() && options.skip_timestamp == 0);
+    // with --start-from-index, the timestamp at which it was first referenced
+    let mut skip_timestamp = options.skip_timestamp;
+    // with --start-from-index, the "a" lines skipped so far that define it or indices after it.
+    // they come before the first reference, so they have to be written once skipping stops.
+    let mut skipped_allocation_infos: Vec<Vec<u8>> = Vec::new();
+    // a second "v" line means that allocation indices restart, see `Warning::IndexReset`
+    let mut is_version_seen = false;
+    // duration since the start of the input profile
+    let mut current_abs_timestamp_ms = 0u64;
+    // for --merge-timestamps, the input timestamp of the last "c" line written
+    let mut last_written_timestamp = None;
+    // number of "c" lines so far, and for --min-alloc-rate, the total duration of the idle
+    // intervals cut out of the timeline and when the current one started
+    let mut intervals = 0usize;
+    let mut idle_ms = 0u64;
+    let mut idle_since = None;
+    // "c" lines dropped by --merge-timestamps, for --trim-report
+    let mut merged_timestamps = 0u64;
+
+    'lines: loop {
+        let line_buf = &mut recent_lines[(lineno + 1) as usize % recent_lines.len()];
+        line_buf.clear();
+        let read_bytes = input.read_until(b'\n', line_buf)?;
+        if read_bytes == 0 {
+            break;
+        }
+
+        stats.input_bytes += read_bytes as u64;
+        lineno += 1;
+        if let Some(cache_key) = &mut cache_key {
+            *cache_key = stable_id::hash(*cache_key, line_buf);
+        }
+        let output_bytes_before = output.bytes;
+
+        // reading the clock for every line would be measurable
+        if lineno.is_multiple_of(4096)
+            && (progress_file.is_some() || options.verbosity != Verbosity::Quiet)
+            && progress.ready()
+        {
+            match &mut progress_file {
+                Some(file) if options.progress_json => writeln!(
+                    file,
+                    "{{\"input_bytes\":{},\"timestamp_ms\":{}}}",
+                    stats.input_bytes, current_abs_timestamp_ms
+                )?,
+                Some(file) => writeln!(
+                    file,
+                    "read {}MiB, at profile timestamp {}",
+                    stats.input_bytes >> 20,
+                    current_abs_timestamp_ms
+                )?,
+                None => eprintln!(
+                    "read {}MiB, at profile timestamp {}",
+                    stats.input_bytes >> 20,
+                    current_abs_timestamp_ms
+                ),
+            }
+        }
+        let line = recent_lines[lineno as usize % recent_lines.len()].as_slice();
+
+        let instruction = line[0];
+
+        if let Some(stable_ids) = &mut stable_ids {
+            stable_ids.add(line);
+        }
+
+        // true if a line that has to be parsed turns out to be malformed
+        let is_malformed = 'line: {
+            match instruction {
+                b'c' => {
+                    let Some(timestamp) = hex_arg(line, 0) else {
+                        break 'line true;
+                    };
+                    if timestamp < current_abs_timestamp_ms {
+                        warnings.warn(Warning::BackwardsTimestamp, lineno, line);
+                    }
+                    current_abs_timestamp_ms = timestamp;
+
+                    if options
+                        .stop_timestamp
+                        .is_some_and(|stop| current_abs_timestamp_ms > stop)
+                    {
+                        if options.verbosity != Verbosity::Quiet {
+                            eprintln!(
+                                "stopped writing at profile timestamp {}, ignoring the rest",
+                                current_abs_timestamp_ms
+                            );
+                        }
+                        if let Some(removed_lines) = &mut removed_lines {
+                            removed_lines.line(lineno, false)?;
+                        }
+                        is_truncated = true;
+                        break 'lines;
+                    }
+
+                    if is_skipping
+                        && options.skips_by_time()
+                        && current_abs_timestamp_ms > skip_timestamp
+                    {
+                        if options.verbosity != Verbosity::Quiet {
+                            eprintln!(
+                                "stopped skipping at profile timestamp {}, writing all data now",
+                                current_abs_timestamp_ms
+                            );
+                        }
+                        is_skipping = false;
+                        dropped_allocation_infos = allocation_infos;
+                    }
+
+                    intervals += 1;
+                    if let Some(since) = idle_since.take() {
+                        idle_ms += current_abs_timestamp_ms.saturating_sub(since);
+                    }
+                    let is_idle = options
+                        .idle_intervals
+                        .as_ref()
+                        .is_some_and(|idle| idle.get(intervals).copied().unwrap_or(false));
+                    if is_idle && !is_skipping {
+                        idle_since = Some(current_abs_timestamp_ms);
+                    }
+
+                    let is_merged = options.merge_timestamps_ms.is_some_and(|min_delta| {
+                        last_written_timestamp.is_some_and(|last| {
+                            current_abs_timestamp_ms.saturating_sub(last) < min_delta
+                        })
+                    });
+
+                    if !is_skipping && !is_idle && is_merged {
+                        merged_timestamps += 1;
+                    }
+                    if !is_skipping && !is_idle && !is_merged {
+                        last_written_timestamp = Some(current_abs_timestamp_ms);
+                        if options.preserve_time {
+                            write_line(&mut output, line, newline)?;
+                        } else {
+                            output.write_all(b"c ")?;
+                            // saturating, since timestamps can go backwards
+                            let timestamp = current_abs_timestamp_ms
+                                .saturating_sub(skip_timestamp)
+                                .saturating_sub(idle_ms);
+                            write_hex(
+                                &mut output,
+                                timestamp.saturating_add_signed(options.time_offset_ms),
+                            )?;
+                            output.write_all(newline)?;
+                        }
+                    }
+                }
+                b'+' | b'-' => {
+                    let Some(allocation_index) = hex_arg(line, 0) else {
+                        break 'line true;
+                    };
+                    stats.input_events += 1;
+                    if instruction == b'+' {
+                        allocations += 1;
+                    }
+                    if let Some(leaks) = &mut leaks {
+                        leaks.event(instruction, allocation_index);
+                    }
+                    if allocation_index >= allocation_infos {
+                        warnings.warn(Warning::UndefinedAllocationInfo, lineno, line);
+                    }
+
+                    if is_skipping && options.start_index == Some(allocation_index) {
+                        if options.verbosity != Verbosity::Quiet {
+                            eprintln!(
+                                "stopped skipping at allocation index {:x}, profile timestamp {}, \
+                             writing all data now",
+                                allocation_index, current_abs_timestamp_ms
+                            );
+                        }
+                        is_skipping = false;
+                        allocation_index_correction = allocation_index;
+                        dropped_allocation_infos =
+                            allocation_infos - skipped_allocation_infos.len() as u64;
+                        skip_timestamp = current_abs_timestamp_ms;
+                        last_written_timestamp = Some(skip_timestamp);
+                        write_first_timestamp(&mut output, options, skip_timestamp)?;
+                        for info in skipped_allocation_infos.drain(..) {
+                            write_line(&mut output, &info, newline)?;
+                        }
+                    }
+
+                    // events while skipping, and of allocations referenced while skipping or
+                    // whose "a" line was dropped with --rebase-mode shift
+                    let is_trimmed = is_skipping
+                        || allocation_index < allocation_index_correction
+                        || options.rebase_mode == RebaseMode::Shift
+                            && allocation_index < dropped_allocation_infos;
+                    if is_skipping && allocation_index >= allocation_index_correction {
+                        allocation_index_correction = allocation_index + 1;
+                    }
+
+                    // the filters have to see every allocation, even the ones we skip anyway
+                    if filters.keep(is_trimmed, instruction, allocation_index) {
+                        let new_allocation_index = allocation_index
+                            - match options.rebase_mode {
+                                RebaseMode::Dense => allocation_index_correction,
+                                RebaseMode::Shift => dropped_allocation_infos,
+                                RebaseMode::None => 0,
+                            };
+                        // filters may drop the first allocation of an allocation info, but its
+                        // "a" line is still written, so the gap is harmless
+                        debug_assert!(
+                            is_filtering
+                                || options.rebase_mode != RebaseMode::Dense
+                                || new_allocation_index <= largest_written_allocation_index + 1,
+                            "{} not within bounds of {}",
+                            allocation_index,
+                            largest_written_allocation_index
+                        );
+
+                        output.write_all(&line[..1])?;
+                        output.write_all(b" ")?;
+                        match &stable_ids {
+                            Some(stable_ids) => write_hex_padded(
+                                &mut output,
+                                stable_ids.id(allocation_index),
+                                options.index_width,
+                            )?,
+                            None => write_hex_padded(
+                                &mut output,
+                                new_allocation_index,
+                                options.index_width,
+                            )?,
+                        }
+                        output.write_all(newline)?;
+                        stats.output_events += 1;
+
+                        if let Some(kept_indices) = &mut kept_indices {
+                            let written = new_allocation_index as usize;
+                            if is_index_written.len() <= written {
+                                is_index_written.resize(written + 1, false);
+                            }
+                            if !is_index_written[written] {
+                                is_index_written[written] = true;
+                                write_hex(&mut *kept_indices, allocation_index)?;
+                                kept_indices.write_all(b" ")?;
+                                match &stable_ids {
+                                    Some(stable_ids) => write_hex(
+                                        &mut *kept_indices,
+                                        stable_ids.id(allocation_index),
+                                    )?,
+                                    None => write_hex(&mut *kept_indices, new_allocation_index)?,
+                                }
+                                kept_indices.write_all(b"\n")?;
+                            }
+                        }
+
+                        largest_written_allocation_index =
+                            max(new_allocation_index, largest_written_allocation_index);
+                    }
+                }
+                b'a' => {
+                    let mut rewritten = None;
+                    if parses_allocation_infos {
+                        let (Some(size), Some(trace)) = (hex_arg(line, 0), hex_arg(line, 1)) else {
+                            break 'line true;
+                        };
+                        let size = options.page_size.map_or(size, |x| pages::round_up(size, x));
+                        if options.rewrite_page_sizes {
+                            let mut line = b"a ".to_vec();
+                            write_hex(&mut line, size)?;
+                            line.push(b' ');
+                            write_hex(&mut line, trace)?;
+                            rewritten = Some(line);
+                        }
+                        filters.allocation_info(size, trace);
+                        if let Some(leaks) = &mut leaks {
+                            leaks.allocation_info(size, trace);
+                        }
+                    }
+                    let line = rewritten.as_deref().unwrap_or(line);
+                    allocation_infos += 1;
+                    if !is_skipping || options.rebase_mode == RebaseMode::None {
+                        write_line(&mut output, line, newline)?;
+                    } else if options
+                        .start_index
+                        .is_some_and(|start| allocation_infos > start)
+                    {
+                        skipped_allocation_infos.push(line.to_vec());
+                    }
+                }
+                b'v' => {
+                    let version = format::Version::parse(line);
+                    if !version.is_some_and(|v| SUPPORTED_FILE_FORMATS.contains(&v.file_format)) {
+                        warnings.warn(Warning::VersionMismatch, lineno, line);
+                    }
+                    if is_version_seen {
+                        warnings.warn(Warning::IndexReset, lineno, line);
+                    }
+                    is_version_seen = true;
+
+                    match (version, options.emit_file_format) {
+                        (Some(version), Some(file_format)) => {
+                            output.write_all(b"v ")?;
+                            write_hex(&mut output, version.heaptrack)?;
+                            output.write_all(b" ")?;
+                            write_hex(&mut output, file_format)?;
+                            output.write_all(newline)?;
+                        }
+                        _ => write_line(&mut output, line, newline)?,
+                    }
+                }
+                _ => {
+                    if let (b'#', Some((start, end))) = (instruction, &options.between_markers) {
+                        let text = line[1..].trim_ascii();
+                        if is_skipping && text == start {
+                            if options.verbosity != Verbosity::Quiet {
+                                eprintln!(
+                                    "stopped skipping at the start marker, profile timestamp {}, \
+                                     writing all data now",
+                                    current_abs_timestamp_ms
+                                );
+                            }
+                            is_skipping = false;
+                            is_start_marker_found = true;
+                            dropped_allocation_infos = allocation_infos;
+                            skip_timestamp = current_abs_timestamp_ms;
+                            last_written_timestamp = Some(skip_timestamp);
+                            write_first_timestamp(&mut output, options, skip_timestamp)?;
+                        } else if !is_skipping && text == end {
+                            if options.verbosity != Verbosity::Quiet {
+                                eprintln!(
+                                    "stopped writing at the end marker, profile timestamp {}, \
+                                     ignoring the rest",
+                                    current_abs_timestamp_ms
+                                );
+                            }
+                            if let Some(removed_lines) = &mut removed_lines {
+                                removed_lines.line(lineno, false)?;
+                            }
+                            is_truncated = true;
+                            break 'lines;
+                        }
+                    }
+                    if let (b'R', Some(_)) = (instruction, options.rss_band) {
+                        let Some(rss) = hex_arg(line, 0) else {
+                            break 'line true;
+                        };
+                        filters.rss(rss);
+                    }
+                    if instruction == b'R' {
+                        rss_samples += 1;
+                        if options
+                            .sample_rss
+                            .is_some_and(|n| !(rss_samples - 1).is_multiple_of(n))
+                        {
+                            break 'line false;
+                        }
+                    }
+                    if let (b't', Some(_)) = (instruction, options.min_trace_depth) {
+                        // "t <ip> <parent trace>"
+                        let Some(parent) = hex_arg(line, 1) else {
+                            break 'line true;
+                        };
+                        filters.trace(parent);
+                    }
+                    match instruction {
+                        // the other commands heaptrack-gui knows about, see the top of this function
+                        b'X' | b'I' | b's' | b't' | b'i' | b'R' | b'A' | b'S' | b'#' | b'\n'
+                        | b'\r' => {}
+                        _ => warnings.warn(Warning::UnknownCommand, lineno, line),
+                    }
+                    write_line(&mut output, line, newline)?;
+                }
+            }
+            false
+        };
+
+        if is_malformed {
+            if !options.skip_bad_lines {
+                return Err(malformed_line_error(lineno, &recent_lines));
+            }
+            // dropped, as if it was not there
+            warnings.warn(Warning::MalformedLine, lineno, line);
+        }
+
+        if let Some(removed_lines) = &mut removed_lines {
+            removed_lines.line(lineno, output.bytes != output_bytes_before)?;
+        }
+    }
+
+    if options.verbosity != Verbosity::Quiet {
+        eprintln!(
+            "done. total time of profile was {}",
+            current_abs_timestamp_ms
+        );
+    }
+    warnings.write_summary(io::stderr())?;
+    if options.filter_stats {
+        filters.write_stats(io::stderr())?;
+    }
+
+    if options.between_markers.is_some() && !is_start_marker_found {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the start marker of --between-markers is not in the profile",
+        ));
+    }
+
+    if let Some(min_allocations) = options.min_allocations {
+        if allocations < min_allocations {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the profile has only {} allocations, but --require-min-allocations is {}",
+                    allocations, min_allocations
+                ),
+            ));
+        }
+    }
+
+    if let Some(mut kept_indices) = kept_indices {
+        kept_indices.flush()?;
+    }
+    if let Some(removed_lines) = removed_lines {
+        removed_lines.finish(is_truncated)?;
+    }
+    if let (Some(path), Some(leaks)) = (&options.leak_report, leaks) {
+        let mut report = BufWriter::new(File::create(path)?);
+        if options.leak_report_json {
+            leak::write_json_report(&leaks.leaks(), &mut report)?;
+        } else {
+            leak::write_report(&leaks.leaks(), &mut report)?;
+        }
+        report.flush()?;
+    }
+
+    stats.input_lines = lineno;
+    stats.output_bytes = output.bytes;
+    stats.profile_duration_ms = current_abs_timestamp_ms;
+    stats.allocation_index_correction = allocation_index_correction;
+    stats.largest_written_allocation_index = largest_written_allocation_index;
+    stats.cache_key = cache_key;
+
+    if options.trim_report.is_some() {
+        let skip_mode = if options.start_index.is_some() {
+            "index"
+        } else if options.between_markers.is_some() {
+            "markers"
+        } else if options.skip_timestamp == 0 {
+            "none"
+        } else {
+            "time"
+        };
+        stats.report = Some(report::TrimReport {
+            skip_mode,
+            skip_timestamp_ms: skip_timestamp,
+            stop_timestamp_ms: options.stop_timestamp,
+            is_truncated,
+            preserve_time: options.preserve_time,
+            time_offset_ms: options.time_offset_ms,
+            merge_timestamps_ms: options.merge_timestamps_ms,
+            merged_timestamps,
+            idle_ms,
+            rebase_mode: options.rebase_mode.name(),
+            stages: filters.stage_stats(),
+            export: options.export.name(),
+        });
+    }
+    Ok(stats)
+}
+
+/// Write `line` with its line ending, if any, replaced by `newline`.
+#[inline]
+fn write_line(mut output: impl Write, line: &[u8], newline: &[u8]) -> Result<(), io::Error> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    output.write_all(line)?;
+    output.write_all(newline)
+}
+
+/// The argument of a line at `position`, counting from 0 after the command, in hex. None if the
+/// line does not have that many arguments or it is not a hex number.
+fn hex_arg(line: &[u8], position: usize) -> Option<u64> {
+    let arg = line
+        .trim_ascii_end()
+        .split(|x| *x == b' ')
+        .nth(position + 1)?;
+    if arg.is_empty() {
+        return None;
+    }
+    parse_hex(arg).ok()
+}
+
+/// How many lines before a malformed one to show in the error.
+const ERROR_CONTEXT_LINES: usize = 3;
+
+/// What a line has to look like, for the commands that `run_main` parses.
+fn expected_syntax(instruction: u8) -> Option<&'static str> {
+    Some(match instruction {
+        b'c' => "c <timestamp>",
+        b'+' => "+ <allocation index>",
+        b'-' => "- <allocation index>",
+        b'a' => "a <size> <trace>",
+        b'R' => "R <resident set size>",
+        b't' => "t <instruction pointer> <parent trace>",
+        _ => return None,
+    })
+}
+
+/// The error for line `lineno`, with what it should look like and the lines before it.
+/// `recent_lines` is indexed by line number modulo its length, like in `run_main`.
+fn malformed_line_error(lineno: u64, recent_lines: &[Vec<u8>]) -> io::Error {
+    let line_at = |lineno: u64| &recent_lines[lineno as usize % recent_lines.len()];
+    let line = line_at(lineno);
+    let mut message = if line[0] == b'a' && is_hex_overflow(line, 0) {
+        format!(
+            "line {} of the input has an allocation size that does not fit into 64 bits: {:?}",
+            lineno,
+            warnings::quote_line(line)
+        )
+    } else {
+        let mut message = format!(
+            "line {} of the input is malformed: {:?}",
+            lineno,
+            warnings::quote_line(line)
+        );
+        if let Some(expected) = expected_syntax(line[0]) {
+            message.push_str(&format!(
+                "\nexpected {:?}, with all numbers in hex",
+                expected
+            ));
+        }
+        message
+    };
+    let first = lineno.saturating_sub(recent_lines.len() as u64 - 1).max(1);
+    for context_lineno in first..=lineno {
+        let marker = if context_lineno == lineno { ">" } else { " " };
+        message.push_str(&format!(
+            "\n{} {:>8} | {}",
+            marker,
+            context_lineno,
+            warnings::quote_line(line_at(context_lineno))
+        ));
+    }
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Whether the argument at `position` is a valid hex number, but too large for `parse_hex`.
+fn is_hex_overflow(line: &[u8], position: usize) -> bool {
+    let arg = line
+        .trim_ascii_end()
+        .split(|x| *x == b' ')
+        .nth(position + 1);
+    arg.is_some_and(|arg| arg.iter().all(u8::is_ascii_hexdigit) && parse_hex(arg).is_err())
+}
+
+/// Fails on numbers that do not fit into 64 bits, instead of wrapping around.
+#[inline]
+fn parse_hex(input: &[u8]) -> Result<u64, ()> {
+    let mut rv = 0u64;
+    for c in input {
+        if rv > u64::MAX >> 4 {
+            return Err(());
+        }
+        rv *= 16;
+        rv |= match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => 10 + c - b'a',
+            b'A'..=b'F' => 10 + c - b'A',
+            _ => return Err(()),
+        } as u64;
+    }
+
+    Ok(rv)
+}
+
+// do not use writeln!(), format strings or from_str_radix, those are slow and handle more
+// edgecases than we need.
+#[inline]
+fn write_hex(mut writer: impl Write, input: u64) -> Result<(), io::Error> {
+    let mut buf = [0u8; 16];
+    let mut start = buf.len();
+    let mut rest = input;
+
+    loop {
+        start -= 1;
+        let c = (rest % 16) as u8;
+        buf[start] = if c < 10 { b'0' + c } else { b'a' + (c - 10) };
+        rest /= 16;
+        if rest == 0 {
+            break;
+        }
+    }
+
+    writer.write_all(&buf[start..])
+}
+
+/// Like `write_hex`, but with leading zeros to make it at least `width` digits long.
+fn write_hex_padded(mut writer: impl Write, input: u64, width: usize) -> Result<(), io::Error> {
+    let digits = (64 - input.leading_zeros() as usize).div_ceil(4).max(1);
+    for _ in digits..width {
+        writer.write_all(b"0")?;
+    }
+    write_hex(writer, input)
+}
+
+#[test]
+fn test_hex() {
+    assert_eq!(parse_hex(b"1"), Ok(1));
+    assert_eq!(parse_hex(b"a"), Ok(10));
+    assert_eq!(parse_hex(b"7d0"), Ok(2000));
+    assert_eq!(parse_hex(b"3e8"), Ok(1000));
+    assert_eq!(parse_hex(b"ffffffffffffffff"), Ok(u64::MAX));
+    assert_eq!(parse_hex(b"00000000000000000001"), Ok(1));
+    assert_eq!(parse_hex(b"10000000000000000"), Err(()));
+}
+
+#[test]
+fn cache_key() {
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        cache_key: true,
+        ..TrimOptions::default()
+    };
+    let key = |input: &[u8]| run_main(&options, input, io::sink()).unwrap().cache_key;
+    let input = b"v 10400 3\nc 1\na 8 1\n+ 0\nc 7d0\n- 0\n";
+    assert_eq!(key(input), key(input));
+    assert_eq!(key(input), Some(stable_id::hash(stable_id::INITIAL, input)));
+    // a change in a part that is trimmed away still changes the key
+    assert_ne!(
+        key(input),
+        key(b"v 10400 3\nc 1\na 8 1\n+ 0\n+ 0\nc 7d0\n- 0\n")
+    );
+    assert_eq!(
+        run_main(&TrimOptions::default(), &input[..], io::sink())
+            .unwrap()
+            .cache_key,
+        None
+    );
+}
+
+#[test]
+fn size_overflow() {
+    let input = b"v 10400 3\nc 1\na 10000000000000008 1\na 8 1\n+ 0\n+ 1\n";
+    let options = TrimOptions {
+        exact_sizes: vec![8],
+        ..TrimOptions::default()
+    };
+    let error = run_main(&options, &input[..], io::sink()).unwrap_err();
+    assert!(error.to_string().starts_with(
+        "line 3 of the input has an allocation size that does not fit into 64 bits: \
+         \"a 10000000000000008 1\"\n"
+    ));
+
+    // the first "a" line is dropped, so the second one is now allocation info 0
+    let options = TrimOptions {
+        skip_bad_lines: true,
+        ..options
+    };
+    let mut output = Vec::new();
+    run_main(&options, &input[..], &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 1\na 8 1\n+ 0\n"
+    );
+}
+
+#[test]
+fn basic() {
+    use std::io::Cursor;
+
+    let mut output = Vec::<u8>::new();
+    run_main(
+        &TrimOptions {
+            skip_timestamp: 1000,
+            ..TrimOptions::default()
+        },
         Cursor::new(
             b"\
 + 0
 c 7d0
 + 1
 + 2
-+ 3
-+ 4",
-        ),
++ 3
++ 4",
+        ),
+        &mut output,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+c 3e8
++ 0
++ 1
++ 2
++ 3\n"
+    );
+}
+
+#[test]
+fn skip_nothing() {
+    // allocations before the first timestamp and at timestamp 0 are kept, with and without
+    // --preserve-time
+    let profile = b"\
+v 10400 3
+X prog
+s 6d61696e
+i 1 1 1
+t 1 0
+a 10 1
++ 0
+c 0
+a 20 1
++ 1
+- 0
+c 64
++ 0
+R 1000
+c c8
+- 1
+";
+    for preserve_time in [false, true] {
+        let mut output = Vec::<u8>::new();
+        run_main(
+            &TrimOptions {
+                skip_timestamp: 0,
+                preserve_time,
+                ..TrimOptions::default()
+            },
+            &profile[..],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            str::from_utf8(profile).unwrap()
+        );
+    }
+}
+
+#[test]
+fn stop_timestamp() {
+    let mut output = Vec::<u8>::new();
+    run_main(
+        &TrimOptions {
+            skip_timestamp: 1000,
+            stop_timestamp: Some(3000),
+            preserve_time: true,
+            ..TrimOptions::default()
+        },
+        &b"\
+a 1 1
++ 0
+c 7d0
+a 2 1
++ 1
+c bb8
++ 1
+c fa0
++ 1
+"[..],
+        &mut output,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+c 7d0
+a 2 1
++ 0
+c bb8
++ 0
+"
+    );
+}
+
+#[test]
+fn time_offset_ms() {
+    let input = b"v 10400 3\nc 1\nc 3e8\nc 7d0\n";
+    let trim_offset = |time_offset_ms| {
+        let options = TrimOptions {
+            time_offset_ms,
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(trim_offset(0), "v 10400 3\nc 1\nc 3e8\nc 7d0\n");
+    assert_eq!(trim_offset(0x10), "v 10400 3\nc 11\nc 3f8\nc 7e0\n");
+    assert_eq!(trim_offset(-0x10), "v 10400 3\nc 0\nc 3d8\nc 7c0\n");
+    assert_eq!(trim_offset(-1000), "v 10400 3\nc 0\nc 0\nc 3e8\n");
+}
+
+#[test]
+fn backwards_timestamp() {
+    // back to before the skipped part, which is clamped to the start of the output
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        ..TrimOptions::default()
+    };
+    let mut output = Vec::new();
+    run_main(
+        &options,
+        &b"v 10400 3\nc 7d0\na 10 0\n+ 0\nc 1\n- 0\n"[..],
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 3e8\na 10 0\n+ 0\nc 0\n- 0\n"
+    );
+}
+
+#[test]
+fn skip_bad_lines() {
+    let input = b"\
+v 10400 3
+c 1
+a 8 1
++ 0
++ zz
+c
+- 0
+c 3e8
++
+- 0
+";
+    let options = TrimOptions {
+        skip_bad_lines: true,
+        ..TrimOptions::default()
+    };
+    let mut output = Vec::new();
+    run_main(&options, &input[..], &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 1\na 8 1\n+ 0\n- 0\nc 3e8\n- 0\n"
+    );
+
+    let error = run_main(&TrimOptions::default(), &input[..], io::sink()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "\
+line 5 of the input is malformed: \"+ zz\"
+expected \"+ <allocation index>\", with all numbers in hex
+         2 | c 1
+         3 | a 8 1
+         4 | + 0
+>        5 | + zz"
+    );
+
+    // no lines before the first
+    let error = run_main(&TrimOptions::default(), &b"c x\n"[..], io::sink()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "\
+line 1 of the input is malformed: \"c x\"
+expected \"c <timestamp>\", with all numbers in hex
+>        1 | c x"
+    );
+}
+
+#[test]
+fn leak_report() {
+    let path = std::env::temp_dir().join(format!("heaptrack-trim-leaks-{}", process::id()));
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        leak_report: Some(path.clone()),
+        ..TrimOptions::default()
+    };
+    // the leak from before the skipped part counts too
+    let input = b"v 10400 3\nc 1\na 10 1\n+ 0\n+ 0\nc 7d0\n- 0\na 100 2\n+ 1\n+ 1\n+ 1\n- 1\n";
+    run_main(&options, &input[..], io::sink()).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "  leaked bytes  allocations         size  trace
+           512            2          256  2
+            16            1           16  1
+total: 528 bytes in 3 allocations
+"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn trim_report() {
+    let path = std::env::temp_dir().join(format!("heaptrack-trim-report-{}", process::id()));
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        exact_sizes: vec![0x30],
+        merge_timestamps_ms: Some(10),
+        trim_report: Some(path.clone()),
+        ..TrimOptions::default()
+    };
+    let input = b"\
+v 10400 3
+a 10 1
++ 0
+c 3e8
+a 20 1
+c 7d0
++ 0
+a 30 1
++ 1
++ 2
+c 7d5
+- 2
+";
+    trim(&options, &input[..], io::sink()).unwrap();
+    // both "+ 0" are trimmed, --exact-size drops the allocation of 0x20 bytes, and "c 7d5" is
+    // merged into "c 7d0"
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "{\"skip\":{\"mode\":\"time\",\"skip_timestamp_ms\":1000,\"stop_timestamp_ms\":null,\
+         \"stopped_early\":false},\"time\":{\"preserve_time\":false,\"offset_ms\":0,\
+         \"merge_timestamps_ms\":10,\"merged_timestamps\":1,\"idle_ms\":0},\
+         \"rebase_mode\":\"dense\",\"stages\":[{\"name\":\"trimming\",\"events\":5,\"dropped\":2},\
+         {\"name\":\"--exact-size\",\"events\":3,\"dropped\":1}],\"export\":\"heaptrack\",\
+         \"bytes\":{\"input\":69,\"trimmed\":31,\"exported\":31},\
+         \"stats\":{\"input_bytes\":69,\"input_lines\":12,\"output_bytes\":31,\"input_events\":5,\
+         \"output_events\":2,\"profile_duration_ms\":2005,\"allocation_index_correction\":1,\
+         \"largest_written_allocation_index\":1}}\n"
+    );
+
+    // the size after exporting is the one of what was written
+    let options = TrimOptions {
+        export: export::ExportFormat::Ndjson,
+        ..options
+    };
+    let mut output = Vec::new();
+    trim(&options, &input[..], &mut output).unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().contains(&format!(
+        "\"export\":\"ndjson\",\"bytes\":{{\"input\":69,\"trimmed\":31,\"exported\":{}}}",
+        output.len()
+    )));
+    assert_ne!(output.len(), 31);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn progress_file() {
+    let path = std::env::temp_dir().join(format!("heaptrack-trim-progress-{}", process::id()));
+    let mut input = b"v 10400 3\na 10 1\nc 2a\n".to_vec();
+    for _ in 0..5000 {
+        input.extend_from_slice(b"+ 0\n");
+    }
+    for progress_json in [false, true] {
+        let options = TrimOptions {
+            verbosity: Verbosity::Quiet,
+            log_interval_ms: 0,
+            progress_file: Some(path.clone()),
+            progress_json,
+            ..TrimOptions::default()
+        };
+        run_main(&options, &input[..], io::sink()).unwrap();
+        let expected = if progress_json {
+            "{\"input_bytes\":16394,\"timestamp_ms\":42}\n"
+        } else {
+            "read 0MiB, at profile timestamp 42\n"
+        };
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_parse_buf_size() {
+    assert_eq!(parse_buf_size("32768"), Ok(32768));
+    assert_eq!(parse_buf_size("1024"), Ok(1024));
+    assert!(parse_buf_size("1023").is_err());
+    assert!(parse_buf_size("1").is_err());
+    assert!(parse_buf_size("0").is_err());
+    assert!(parse_buf_size("-1").is_err());
+    assert!(parse_buf_size("1e15").is_err());
+}
+
+#[test]
+fn tiny_buffers() {
+    let input = b"\
+v 10400 3
+a 1 1
++ 0
+c 7d0
+a 2 1
++ 1
+- 1
+c fa0
+";
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        ..TrimOptions::default()
+    };
+
+    let mut expected = Vec::new();
+    run_main(&options, &input[..], &mut expected).unwrap();
+
+    // lines are longer than the buffers, which only costs performance
+    for buf_size in [1, 2, 3] {
+        let mut output = BufWriter::with_capacity(buf_size, Vec::new());
+        run_main(
+            &options,
+            BufReader::with_capacity(buf_size, &input[..]),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output.into_inner().unwrap(), expected, "{}", buf_size);
+    }
+}
+
+#[test]
+fn emit_version() {
+    let mut output = Vec::<u8>::new();
+    run_main(
+        &TrimOptions {
+            emit_file_format: Some(2),
+            ..TrimOptions::default()
+        },
+        &b"v 10400 3\nX ./a.out\nc 1\n"[..],
         &mut output,
     )
     .unwrap();
 
     assert_eq!(
         String::from_utf8(output).unwrap(),
-        "\
-c 3e8
+        "v 10400 2\nX ./a.out\nc 1\n"
+    );
+}
+
+#[test]
+fn test_summary_only() {
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        ..TrimOptions::default()
+    };
+    let input = b"v 10400 3\na 1 1\n+ 0\nc 7d0\na 2 1\n+ 1\n- 1\n";
+
+    let mut report = Vec::new();
+    summary_only(&options, &input[..], &mut report, true).unwrap();
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "{\"input_bytes\":40,\"input_lines\":7,\"output_bytes\":30,\"input_events\":3,\
+         \"output_events\":2,\"profile_duration_ms\":2000,\"allocation_index_correction\":1,\
+         \"largest_written_allocation_index\":0}\n"
+    );
+}
+
+#[test]
+fn exact_size() {
+    let input = b"v 10400 3\nc 1\na 8 1\na 10 1\na 18 1\n+ 0\n+ 1\n+ 2\n- 1\n- 0\n";
+
+    let trim_sizes = |exact_sizes: Vec<u64>| {
+        let options = TrimOptions {
+            exact_sizes,
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(
+        trim_sizes(vec![16]),
+        "v 10400 3\nc 1\na 8 1\na 10 1\na 18 1\n+ 1\n- 1\n"
+    );
+    assert_eq!(
+        trim_sizes(vec![8, 24]),
+        "v 10400 3\nc 1\na 8 1\na 10 1\na 18 1\n+ 0\n+ 2\n- 0\n"
+    );
+}
+
+#[test]
+fn require_min_allocations() {
+    let input = b"v 10400 3\nc 1\na 8 1\n+ 0\n- 0\n+ 0\n";
+    let options = |min_allocations| TrimOptions {
+        min_allocations: Some(min_allocations),
+        ..TrimOptions::default()
+    };
+
+    run_main(&options(2), &input[..], io::sink()).unwrap();
+    let error = run_main(&options(3), &input[..], io::sink()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the profile has only 2 allocations, but --require-min-allocations is 3"
+    );
+}
+
+#[test]
+fn byte_order_mark() {
+    let mut input = &b"\xef\xbb\xbfv 10400 3\nc 1\na 8 1\n+ 0\n"[..];
+    text::skip_byte_order_mark(&mut input).unwrap();
+    let mut output = Vec::new();
+    run_main(&TrimOptions::default(), input, &mut output).unwrap();
+    assert_eq!(output, b"v 10400 3\nc 1\na 8 1\n+ 0\n");
+}
+
+#[test]
+fn allocation_index_correction() {
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        ..TrimOptions::default()
+    };
+    // indices 0 to 2 are referenced while skipping, 3 and 4 are written as 0 and 1
+    let input =
+        b"v 10400 3\na 1 1\na 2 1\na 3 1\n+ 0\n+ 2\n+ 1\nc 7d0\na 4 1\na 5 1\n+ 3\n- 0\n+ 4\n";
+    let stats = run_main(&options, &input[..], io::sink()).unwrap();
+    assert_eq!(stats.allocation_index_correction, 3);
+    assert_eq!(stats.largest_written_allocation_index, 1);
+}
+
+#[test]
+fn kept_indices_out() {
+    let path = std::env::temp_dir().join(format!("heaptrack-trim-kept-{}", process::id()));
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        kept_indices_out: Some(path.clone()),
+        ..TrimOptions::default()
+    };
+    let input = b"v 10400 3\na 1 1\n+ 0\nc 7d0\na 2 1\na 3 1\n+ 2\n+ 1\n- 2\n+ 1\n";
+    run_main(&options, &input[..], io::sink()).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "2 1\n1 0\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn diff_out() {
+    let path = std::env::temp_dir().join(format!("heaptrack-trim-diff-{}", process::id()));
+    let options = TrimOptions {
+        skip_timestamp: 1000,
+        stop_timestamp: Some(3000),
+        diff_out: Some(path.clone()),
+        ..TrimOptions::default()
+    };
+    let input = b"v 10400 3\na 1 1\n+ 0\n- 0\nc 7d0\na 2 1\n+ 1\n+ 0\nc fa0\n+ 1\n- 1\n";
+    let mut output = Vec::new();
+    run_main(&options, &input[..], &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 3e8\na 2 1\n+ 0\n"
+    );
+    // "+ 0" refers to an allocation from before the skipped part
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "2,4d\n8,$d\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn min_alloc_rate() {
+    let input = b"\
+v 10400 3
+c 1
+a 8 1
++ 0
++ 0
+c b
++ 0
+c 3e9
+- 0
+- 0
+- 0
+c 3f3
++ 0
+";
+    let activity = rate::activity(&input[..], 100.0).unwrap();
+    let options = TrimOptions {
+        allocation_filters: vec![("--min-alloc-rate", activity.filter)],
+        idle_intervals: Some(activity.idle),
+        ..TrimOptions::default()
+    };
+    let mut output = Vec::new();
+    run_main(&options, &input[..], &mut output).unwrap();
+    // the allocation from 0xb to 0x3e9 is dropped along with its free, and the time in between
+    // is cut out
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 1\na 8 1\n+ 0\n+ 0\nc b\n- 0\n- 0\nc 15\n+ 0\n"
+    );
+}
+
+#[test]
+fn newline() {
+    let input = b"v 10400 3\nc 1\r\na 8 1\n+ 0\n\n- 0";
+    let trim_newline = |newline| {
+        let options = TrimOptions {
+            newline,
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(
+        trim_newline(Newline::Crlf),
+        "v 10400 3\r\nc 1\r\na 8 1\r\n+ 0\r\n\r\n- 0\r\n"
+    );
+    assert_eq!(
+        trim_newline(Newline::Lf),
+        "v 10400 3\nc 1\na 8 1\n+ 0\n\n- 0\n"
+    );
+}
+
+#[test]
+fn rss_band() {
+    let input = b"v 10400 3\nc 1\na 8 1\n+ 0\nR 64\n+ 0\nR 3e8\n+ 0\n- 0\n- 0\n- 0\n";
+    let options = TrimOptions {
+        rss_band: Some((50, 200)),
+        ..TrimOptions::default()
+    };
+    let mut output = Vec::new();
+    run_main(&options, &input[..], &mut output).unwrap();
+    // only the allocation while the RSS was 0x64 is kept, it is the second one freed
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 1\na 8 1\nR 64\n+ 0\nR 3e8\n- 0\n"
+    );
+}
+
+#[test]
+fn start_from_index() {
+    let input = b"\
+v 10400 3
+s foo
+c 1
+a 8 1
 + 0
+a 10 1
 + 1
+c 5
+- 0
+a 20 1
 + 2
-+ 3\n"
++ 1
+c 9
+- 1
+- 2
+";
+    let trim_from = |start_index, preserve_time| {
+        let options = TrimOptions {
+            start_index: Some(start_index),
+            preserve_time,
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(
+        trim_from(2, false),
+        "v 10400 3\ns foo\nc 0\na 20 1\n+ 0\nc 4\n- 0\n"
+    );
+    // allocation index 0 comes before the start, so its free is dropped
+    assert_eq!(
+        trim_from(1, true),
+        "v 10400 3\ns foo\nc 1\na 10 1\n+ 0\nc 5\na 20 1\n+ 1\n+ 0\nc 9\n- 0\n- 1\n"
+    );
+}
+
+#[test]
+fn allocation_sizes_are_not_parsed_by_default() {
+    // parsing would panic on this size
+    let input = b"v 10400 3\nc 1\na zz 1\n+ 0\n";
+    assert!(!TrimOptions::default().parses_allocation_infos());
+    let mut output = Vec::new();
+    run_main(&TrimOptions::default(), &input[..], &mut output).unwrap();
+    assert_eq!(output, input);
+
+    let options = TrimOptions {
+        exact_sizes: vec![8],
+        ..TrimOptions::default()
+    };
+    assert!(options.parses_allocation_infos());
+}
+
+#[test]
+fn min_trace_depth() {
+    // trace 3 is three frames deep, trace 4 one frame
+    let input = b"\
+v 10400 3
+t 1 0
+t 2 1
+t 3 2
+t 4 0
+c 1
+a 8 3
+a 10 4
+a 18 2
++ 0
++ 1
++ 2
+- 1
+- 0
+";
+    let trim_depth = |min_trace_depth| {
+        let options = TrimOptions {
+            min_trace_depth: Some(min_trace_depth),
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        output
+            .lines()
+            .filter(|line| line.starts_with(['+', '-']))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    assert_eq!(trim_depth(0), "+ 0,+ 1,+ 2,- 1,- 0");
+    assert_eq!(trim_depth(2), "+ 0,+ 2,- 0");
+    assert_eq!(trim_depth(3), "+ 0,- 0");
+    assert_eq!(trim_depth(4), "");
+}
+
+#[test]
+fn test_hex_roundtrip() {
+    for input in [0, 1, 0xf, 0x10, 0x100, 0x1001, 0xdeadbeef, u64::MAX] {
+        let mut output = Vec::new();
+        write_hex(&mut output, input).unwrap();
+        assert_eq!(parse_hex(&output), Ok(input), "{:x}", input);
+    }
+}
+
+#[test]
+fn test_write_hex_padded() {
+    let padded = |input, width| {
+        let mut output = Vec::new();
+        write_hex_padded(&mut output, input, width).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    assert_eq!(padded(0, 0), "0");
+    assert_eq!(padded(0, 4), "0000");
+    assert_eq!(padded(0x1a, 4), "001a");
+    assert_eq!(padded(0x1a, 1), "1a");
+    assert_eq!(padded(0x10000, 4), "10000");
+    assert_eq!(padded(u64::MAX, 16), "ffffffffffffffff");
+}
+
+#[test]
+fn index_width() {
+    let input = b"v 10400 3\nc 1\na 8 1\n+ 0\nc 2\n- 0\n";
+    let options = TrimOptions {
+        index_width: 8,
+        ..TrimOptions::default()
+    };
+    let mut output = Vec::new();
+    run_main(&options, &input[..], &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 1\na 8 1\n+ 00000000\nc 2\n- 00000000\n"
+    );
+}
+
+#[test]
+fn rebase_mode() {
+    // index 1 is defined while skipping, but first referenced after it
+    let input = b"v 10400 3\nc 1\na 8 1\na 9 1\n+ 0\nc 7d0\n+ 1\na a 1\n+ 2\n- 0\n";
+    let trim = |rebase_mode| {
+        let options = TrimOptions {
+            skip_timestamp: 1000,
+            rebase_mode,
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    // the first kept index is 0, even though it now refers to the wrong "a" line
+    assert_eq!(
+        trim(RebaseMode::Dense),
+        "v 10400 3\nc 3e8\n+ 0\na a 1\n+ 1\n"
+    );
+    // both "a" lines before the kept part are dropped, and so are references to them
+    assert_eq!(trim(RebaseMode::Shift), "v 10400 3\nc 3e8\na a 1\n+ 0\n");
+    assert_eq!(
+        trim(RebaseMode::None),
+        "v 10400 3\na 8 1\na 9 1\nc 3e8\n+ 1\na a 1\n+ 2\n"
+    );
+}
+
+#[test]
+fn sample_rss() {
+    let mut input = "v 10400 3\n".to_owned();
+    for i in 1..=7 {
+        input.push_str(&format!("c {:x}\nR {:x}\n", i, i * 1000));
+    }
+    let options = TrimOptions {
+        sample_rss: Some(3),
+        ..TrimOptions::default()
+    };
+    let mut output = Vec::new();
+    run_main(&options, input.as_bytes(), &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 1\nR 3e8\nc 2\nc 3\nc 4\nR fa0\nc 5\nc 6\nc 7\nR 1b58\n"
+    );
+}
+
+#[test]
+fn between_markers() {
+    let input = b"\
+v 10400 3
+s foo
+c 1
+# phase run
+a 8 1
++ 0
+c 5
+# phase load
+a 10 1
++ 1
+- 0
+c 9
+# phase run
++ 1
+c a
+# phase load
+- 1
+";
+    let trim = |start: &str, end: &str| {
+        let options = TrimOptions {
+            between_markers: Some((start.as_bytes().to_vec(), end.as_bytes().to_vec())),
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).map(|_| String::from_utf8(output).unwrap())
+    };
+
+    assert_eq!(
+        trim("phase load", "phase run").unwrap(),
+        "v 10400 3\ns foo\n# phase run\nc 0\n# phase load\na 10 1\n+ 0\nc 4\n"
+    );
+    // comments before the start marker are kept like the rest of the header, and an end marker
+    // only ends the output after the start marker
+    assert_eq!(
+        trim("phase load", "phase done").unwrap(),
+        "v 10400 3\ns foo\n# phase run\nc 0\n# phase load\na 10 1\n+ 0\nc 4\n# phase \
+         run\n+ 0\nc 5\n# phase load\n- 0\n"
+    );
+    assert!(trim("phase done", "phase run").is_err());
+}
+
+#[test]
+fn merge_timestamps() {
+    let input = b"\
+v 10400 3
+c 1
+a 8 1
++ 0
+c 2
++ 0
+c 5
+- 0
+c 9
+c a
+- 0
+c 32
++ 0
+c 33
+";
+    let options = TrimOptions {
+        merge_timestamps_ms: Some(5),
+        ..TrimOptions::default()
+    };
+    let mut output = Vec::new();
+    let stats = run_main(&options, &input[..], &mut output).unwrap();
+    // 9 is only 4 after 5, but merging it into 1 would move events by 8
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 1\na 8 1\n+ 0\n+ 0\n- 0\nc 9\n- 0\nc 32\n+ 0\n"
+    );
+    assert_eq!(stats.output_events, stats.input_events);
+
+    // a timestamp going backwards is merged into the last one
+    let mut output = Vec::new();
+    run_main(
+        &options,
+        &b"v 10400 3\nc 5\na 10 0\n+ 0\nc 2\n- 0\n"[..],
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "v 10400 3\nc 5\na 10 0\n+ 0\n- 0\n"
+    );
+}
+
+#[test]
+fn commands_from_stdin() {
+    let dir = std::env::temp_dir().join(format!("heaptrack-trim-commands-{}", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let prefix = dir.join("trimmed");
+    let mut options = TrimOptions::default();
+    let input = b"v 10400 3\nc 1\na 8 1\n+ 0\nc 7d0\na 10 1\n+ 1\n- 0\n";
+    let commands = b"--skip-seconds 1\n\n--skip-seconds 0 --exact-size 16\n--skip-seconds\n";
+    // the empty line is ignored, the invalid last command is reported
+    let mut report = Vec::new();
+    let files = run_commands(&mut options, input, &commands[..], &prefix, &mut report).unwrap();
+    assert_eq!(files, 2);
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        format!("{}.0\n{}.1\n", prefix.display(), prefix.display())
+    );
+    let file = |n| std::fs::read_to_string(format!("{}.{}", prefix.display(), n)).unwrap();
+    assert_eq!(file(0), "v 10400 3\nc 3e8\na 10 1\n+ 0\n");
+    // only the allocation of 16 bytes
+    assert_eq!(file(1), "v 10400 3\nc 1\na 8 1\nc 7d0\na 10 1\n+ 1\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn around_ms() {
+    assert_eq!(around_window(5000, 2000), (3000, 7000));
+    assert_eq!(around_window(1000, 2000), (0, 3000));
+    assert_eq!(around_window(u64::MAX, 1000), (u64::MAX - 1000, u64::MAX));
+
+    // one allocation of 1 to 4 bytes at 1, 2000, 4000 and 6000
+    let input =
+        b"v 10400 3\nc 1\na 1 1\n+ 0\nc 7d0\na 2 1\n+ 1\nc fa0\na 3 1\n+ 2\nc 1770\na 4 1\n+ 3\n";
+    let trimmed = |around_ms, radius_ms| {
+        let (skip_timestamp, stop_timestamp) = around_window(around_ms, radius_ms);
+        let options = TrimOptions {
+            skip_timestamp,
+            stop_timestamp: Some(stop_timestamp),
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    // 2000 to 4000 around 3000, which like with --skip-seconds starts at the first timestamp
+    // after 2000 and ends at the first one after 4000
+    assert_eq!(trimmed(3000, 1000), "v 10400 3\nc 7d0\na 3 1\n+ 0\n");
+    // clamped to the start of the profile
+    assert_eq!(trimmed(1000, 2000), trimmed(0, 3000));
+    assert_eq!(
+        trimmed(0, 3000),
+        "v 10400 3\nc 1\na 1 1\n+ 0\nc 7d0\na 2 1\n+ 1\n"
+    );
+    // past the end, everything after 4000
+    assert_eq!(trimmed(6000, 2000), "v 10400 3\nc 7d0\na 4 1\n+ 0\n");
+}
+
+#[test]
+fn normalize_sizes_to_pages() {
+    let input = b"v 10400 3\nc 1\na fff 1\na 1000 1\na 1001 1\n+ 0\n+ 1\n+ 2\n";
+    let trimmed = |exact_sizes: Vec<u64>, rewrite_page_sizes| {
+        let options = TrimOptions {
+            exact_sizes,
+            page_size: Some(4096),
+            rewrite_page_sizes,
+            ..TrimOptions::default()
+        };
+        let mut output = Vec::new();
+        run_main(&options, &input[..], &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    // 4095 and 4096 bytes are one page, 4097 are two
+    assert_eq!(
+        trimmed(vec![4096], false),
+        "v 10400 3\nc 1\na fff 1\na 1000 1\na 1001 1\n+ 0\n+ 1\n"
     );
+    assert_eq!(
+        trimmed(vec![8192], true),
+        "v 10400 3\nc 1\na 1000 1\na 1000 1\na 2000 1\n+ 2\n"
+    );
+}
+
+#[test]
+fn lines_longer_than_buffer() {
+    // a long symbol, an "i" line with many inlined frames, and an "a" line padded with zeros
+    let mut input = b"v 10400 3\ns ".to_vec();
+    input.extend_from_slice(&[b'x'; 5000]);
+    input.extend_from_slice(b"\ni 10 1");
+    for _ in 0..500 {
+        input.extend_from_slice(b" 1 1 0");
+    }
+    input.extend_from_slice(b"\nt 1 0\nc 1\na ");
+    input.extend_from_slice(&[b'0'; 3000]);
+    input.extend_from_slice(b"10 1\na 8 1\n+ 0\n+ 1\nc 7d0\n+ 0\n");
+
+    for options in [
+        TrimOptions {
+            exact_sizes: vec![16],
+            ..TrimOptions::default()
+        },
+        TrimOptions {
+            skip_timestamp: 1000,
+            ..TrimOptions::default()
+        },
+    ] {
+        let mut expected = Vec::new();
+        run_main(&options, &input[..], &mut expected).unwrap();
+        let mut output = Vec::new();
+        let reader = BufReader::with_capacity(7, &input[..]);
+        run_main(&options, reader, &mut output).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    let mut output = Vec::new();
+    let options = TrimOptions {
+        exact_sizes: vec![16],
+        ..TrimOptions::default()
+    };
+    run_main(
+        &options,
+        BufReader::with_capacity(7, &input[..]),
+        &mut output,
+    )
+    .unwrap();
+    // the 16 byte allocation is the only one kept
+    assert!(output.ends_with(b"10 1\na 8 1\n+ 0\nc 7d0\n+ 0\n"));
+    assert_eq!(output.len(), input.len() - b"+ 1\n".len());
+}
+
+/// Generate random profiles, trim them and replay the output through a model of how heaptrack-gui
+/// indexes allocations. Set `HEAPTRACK_TRIM_SEED` to reproduce a single failing seed.
+#[cfg(test)]
+mod fuzz {
+    use super::{run_main, TrimOptions};
+    use crate::verify::CheckOrdering;
+
+    // xorshift64*, good enough to generate test data and we do not need a dependency for it.
+    struct Rng(u64);
+
+    impl Rng {
+        fn new(seed: u64) -> Self {
+            Rng(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1)
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545f4914f6cdd1d) % n
+        }
+    }
+
+    /// Mimics what heaptrack_interpret writes: "a" lines are written right before the first "+"
+    /// referencing them, timestamps only ever increase.
+    fn generate_profile(rng: &mut Rng) -> (Vec<u8>, u64) {
+        let mut profile = b"v 10100 3\nX ./a.out\ns main\ni 1234 1 1\nt 1 0\n".to_vec();
+        let mut time = 0u64;
+        let mut allocation_infos = 0u64;
+        let mut live = Vec::new();
+
+        for _ in 0..rng.below(2000) {
+            match rng.below(10) {
+                0 => {
+                    time += rng.below(0x300);
+                    profile.extend(format!("c {:x}\n", time).bytes());
+                }
+                1..=3 => {
+                    // sizes are unique so that the replay can tell allocations apart
+                    profile.extend(format!("a {:x} 1\n", allocation_infos).bytes());
+                    profile.extend(format!("+ {:x}\n", allocation_infos).bytes());
+                    live.push(allocation_infos);
+                    allocation_infos += 1;
+                }
+                4..=5 if allocation_infos > 0 => {
+                    let index = rng.below(allocation_infos);
+                    profile.extend(format!("+ {:x}\n", index).bytes());
+                    live.push(index);
+                }
+                6..=8 if !live.is_empty() => {
+                    let index = live.swap_remove(rng.below(live.len() as u64) as usize);
+                    profile.extend(format!("- {:x}\n", index).bytes());
+                }
+                _ => profile.extend(format!("R {:x}\n", rng.below(1 << 30)).bytes()),
+            }
+        }
+
+        (profile, time)
+    }
+
+    fn parse_line(line: &str) -> (u8, u64) {
+        let mut args = line.split(' ');
+        let instruction = args.next().unwrap().as_bytes()[0];
+        let value = match instruction {
+            b'a' | b'+' | b'-' | b'c' => u64::from_str_radix(args.next().unwrap(), 16).unwrap(),
+            _ => 0,
+        };
+        (instruction, value)
+    }
+
+    /// Replay a trimmed profile the way heaptrack-gui would, returning the size of the allocation
+    /// info that every +/- line refers to.
+    fn replay(profile: &str) -> Result<Vec<(u8, u64)>, String> {
+        let mut allocation_infos = Vec::new();
+        let mut events = Vec::new();
+        let mut last_timestamp = 0;
+
+        for (lineno, line) in profile.lines().enumerate() {
+            let (instruction, value) = parse_line(line);
+            match instruction {
+                b'a' => allocation_infos.push(value),
+                b'+' | b'-' => match allocation_infos.get(value as usize) {
+                    Some(size) => events.push((instruction, *size)),
+                    None => {
+                        return Err(format!(
+                            "line {}: {:?} is out of bounds of {} allocation infos",
+                            lineno,
+                            line,
+                            allocation_infos.len()
+                        ))
+                    }
+                },
+                b'c' if value < last_timestamp => {
+                    return Err(format!("line {}: timestamp went backwards", lineno));
+                }
+                b'c' => last_timestamp = value,
+                _ => {}
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// The events that should survive the trim, computed the slow and obvious way.
+    fn expected_events(profile: &str, skip_timestamp: u64) -> Vec<(u8, u64)> {
+        let mut is_skipping = skip_timestamp > 0;
+        let mut allocation_infos = Vec::new();
+        let mut events = Vec::new();
+
+        for line in profile.lines() {
+            let (instruction, value) = parse_line(line);
+            match instruction {
+                b'c' if value > skip_timestamp => is_skipping = false,
+                b'a' => allocation_infos.push((value, !is_skipping)),
+                b'+' | b'-' => {
+                    let (size, defined_after_skip) = allocation_infos[value as usize];
+                    if defined_after_skip {
+                        events.push((instruction, size));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        events
+    }
+
+    fn check_seed(seed: u64) {
+        let mut rng = Rng::new(seed);
+        let (profile, duration) = generate_profile(&mut rng);
+        let skip_timestamp = rng.below(duration + 2);
+
+        let mut output = Vec::new();
+        let options = TrimOptions {
+            skip_timestamp,
+            ..TrimOptions::default()
+        };
+        run_main(
+            &options,
+            profile.as_slice(),
+            CheckOrdering::new(&mut output),
+        )
+        .unwrap_or_else(|e| panic!("seed {}: {}", seed, e));
+
+        let input = String::from_utf8(profile).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let events = replay(&output).unwrap_or_else(|e| panic!("seed {}: {}", seed, e));
+        assert_eq!(
+            events,
+            expected_events(&input, skip_timestamp),
+            "seed {}",
+            seed
+        );
+    }
+
+    #[test]
+    fn trimmed_profiles_replay_cleanly() {
+        match std::env::var("HEAPTRACK_TRIM_SEED") {
+            Ok(seed) => check_seed(seed.parse().unwrap()),
+            Err(_) => (0..500).for_each(check_seed),
+        }
+    }
 }
@@ -0,0 +1,182 @@
+//! The metadata in the header of a profile, for --dump-header.
+//!
+//! heaptrack writes everything it knows about the process at the start of the profile, before the
+//! first "c" line:
+//!
+//! * "v <heaptrack version> <file format version>"
+//! * "X <command line>", the command line of the profiled program
+//! * "I <page size> <physical pages>", both in hex
+//! * "A", only if heaptrack was attached to a running process instead of starting it
+//!
+//! heaptrack records neither when the profile was captured nor which allocator the program used.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::format::Version;
+use crate::parse_hex;
+use crate::stats::write_json_string;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ProfileMetadata {
+    pub version: Option<Version>,
+    pub command_line: Option<String>,
+    pub page_size: Option<u64>,
+    pub physical_pages: Option<u64>,
+    pub attached: bool,
+}
+
+impl ProfileMetadata {
+    /// Every field as a JSON object, missing ones as null:
+    ///
+    /// ```text
+    /// {"heaptrack_version":"1.4.0","file_format":3,"command_line":"./a.out","page_size":4096,
+    ///  "physical_pages":4194304,"attached":false}
+    /// ```
+    pub fn write_json(&self, mut output: impl Write) -> io::Result<()> {
+        let number = |value: Option<u64>| value.map_or("null".to_owned(), |x| x.to_string());
+        output.write_all(b"{\"heaptrack_version\":")?;
+        match &self.version {
+            Some(version) => write!(
+                output,
+                "\"{}.{}.{}\",\"file_format\":{}",
+                version.heaptrack >> 16,
+                (version.heaptrack >> 8) & 0xff,
+                version.heaptrack & 0xff,
+                version.file_format
+            )?,
+            None => output.write_all(b"null,\"file_format\":null")?,
+        }
+        output.write_all(b",\"command_line\":")?;
+        match &self.command_line {
+            Some(command_line) => write_json_string(&mut output, command_line)?,
+            None => output.write_all(b"null")?,
+        }
+        write!(
+            output,
+            ",\"page_size\":{},\"physical_pages\":{},\"attached\":{}}}",
+            number(self.page_size),
+            number(self.physical_pages),
+            self.attached
+        )
+    }
+}
+
+impl fmt::Display for ProfileMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown = |value: Option<u64>| value.map_or("unknown".to_owned(), |x| x.to_string());
+        match &self.version {
+            Some(version) => writeln!(f, "version: {}", version)?,
+            None => writeln!(f, "version: unknown")?,
+        }
+        writeln!(
+            f,
+            "command line: {}",
+            self.command_line.as_deref().unwrap_or("unknown")
+        )?;
+        match self.page_size {
+            Some(page_size) => writeln!(f, "page size: {} bytes", page_size)?,
+            None => writeln!(f, "page size: unknown")?,
+        }
+        writeln!(f, "physical pages: {}", unknown(self.physical_pages))?;
+        write!(
+            f,
+            "attached: {}",
+            if self.attached {
+                "yes, heaptrack was attached to a running process"
+            } else {
+                "no"
+            }
+        )
+    }
+}
+
+/// Parse the header, reading only up to the first "c" line.
+pub fn read_metadata(mut input: impl BufRead) -> io::Result<ProfileMetadata> {
+    let mut metadata = ProfileMetadata::default();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(metadata);
+        }
+        let trimmed = line.trim_ascii_end();
+        let mut args = trimmed.split(|x| *x == b' ').skip(1);
+        match line[0] {
+            b'c' => return Ok(metadata),
+            b'v' => metadata.version = Version::parse(trimmed),
+            b'X' => {
+                let command_line = trimmed.get(2..).unwrap_or_default();
+                metadata.command_line = Some(String::from_utf8_lossy(command_line).into_owned());
+            }
+            b'I' => {
+                metadata.page_size = args.next().and_then(|x| parse_hex(x).ok());
+                metadata.physical_pages = args.next().and_then(|x| parse_hex(x).ok());
+            }
+            b'A' => metadata.attached = true,
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_read_metadata() {
+    let profile = b"\
+v 10400 3
+X ./a.out --flag \"x\"
+I 1000 3e8000
+s main
+c 1
+A
+";
+    let metadata = read_metadata(&profile[..]).unwrap();
+    assert_eq!(
+        metadata,
+        ProfileMetadata {
+            version: Some(Version {
+                heaptrack: 0x10400,
+                file_format: 3
+            }),
+            command_line: Some("./a.out --flag \"x\"".to_owned()),
+            page_size: Some(4096),
+            physical_pages: Some(4096000),
+            // after the header, so it does not count
+            attached: false,
+        }
+    );
+    assert_eq!(
+        metadata.to_string(),
+        "\
+version: heaptrack 1.4.0, file format 3
+command line: ./a.out --flag \"x\"
+page size: 4096 bytes
+physical pages: 4096000
+attached: no"
+    );
+
+    let mut json = Vec::new();
+    metadata.write_json(&mut json).unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        "{\"heaptrack_version\":\"1.4.0\",\"file_format\":3,\"command_line\":\"./a.out --flag \
+         \\\"x\\\"\",\"page_size\":4096,\"physical_pages\":4096000,\"attached\":false}"
+    );
+}
+
+#[test]
+fn test_read_metadata_missing() {
+    let metadata = read_metadata(&b"v 10400 3\nA\nc 1\n"[..]).unwrap();
+    assert!(metadata.attached);
+    assert!(metadata.to_string().contains("page size: unknown\n"));
+
+    let mut json = Vec::new();
+    read_metadata(&b""[..])
+        .unwrap()
+        .write_json(&mut json)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        "{\"heaptrack_version\":null,\"file_format\":null,\"command_line\":null,\
+         \"page_size\":null,\"physical_pages\":null,\"attached\":false}"
+    );
+}
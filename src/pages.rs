@@ -0,0 +1,43 @@
+//! Rounding allocation sizes up to whole pages, for --normalize-sizes-to-pages.
+//!
+//! The sizes in "a" lines are what the program asked for. What it costs in memory depends on the
+//! allocator, and for large allocations, which get pages of their own, is closer to the size
+//! rounded up to the page size. Anything the allocator puts several of into one page is
+//! overestimated by this.
+
+/// `size` rounded up to a multiple of `page_size`. Saturates instead of overflowing.
+pub fn round_up(size: u64, page_size: u64) -> u64 {
+    match size % page_size {
+        0 => size,
+        rest => size.saturating_add(page_size - rest),
+    }
+}
+
+pub fn parse_page_size(value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(page_size) if page_size > 0 => Ok(page_size),
+        _ => Err(format!(
+            "invalid page size {:?}, expected a number of bytes of at least 1",
+            value
+        )),
+    }
+}
+
+#[test]
+fn test_round_up() {
+    assert_eq!(round_up(0, 4096), 0);
+    assert_eq!(round_up(1, 4096), 4096);
+    assert_eq!(round_up(4095, 4096), 4096);
+    assert_eq!(round_up(4096, 4096), 4096);
+    assert_eq!(round_up(4097, 4096), 8192);
+    assert_eq!(round_up(10, 3), 12);
+    assert_eq!(round_up(u64::MAX - 1, 4096), u64::MAX);
+    assert_eq!(round_up(7, 1), 7);
+}
+
+#[test]
+fn test_parse_page_size() {
+    assert_eq!(parse_page_size("4096"), Ok(4096));
+    assert!(parse_page_size("0").is_err());
+    assert!(parse_page_size("4k").is_err());
+}
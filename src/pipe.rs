@@ -0,0 +1,61 @@
+//! Writing the output into the stdin of another program.
+
+use std::io::{self, BufWriter, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Run `command` with `sh -c` and call `write` with a writer to its stdin.
+///
+/// If the command exits before reading everything, the resulting `BrokenPipe` error is not
+/// treated as an error, it is up to the command to decide whether that is a failure.
+pub fn pipe_to(
+    command: &str,
+    buf_size: usize,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<ExitStatus> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = BufWriter::with_capacity(buf_size, child.stdin.take().unwrap());
+    let result = write(&mut stdin).and_then(|()| stdin.flush());
+    // close stdin, so the command sees EOF
+    drop(stdin);
+
+    let status = child.wait()?;
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+            eprintln!("{:?} exited before reading all output", command);
+            Ok(status)
+        }
+        Err(e) => Err(e),
+        Ok(()) => Ok(status),
+    }
+}
+
+#[test]
+fn test_pipe_to() {
+    let path = std::env::temp_dir().join(format!("heaptrack-trim-pipe-{}", std::process::id()));
+    let command = format!("cat > '{}'", path.display());
+    let status = pipe_to(&command, 1024, |output| output.write_all(b"c 1\n+ 0\n")).unwrap();
+    assert!(status.success());
+    assert_eq!(std::fs::read(&path).unwrap(), b"c 1\n+ 0\n");
+    std::fs::remove_file(&path).unwrap();
+
+    let status = pipe_to("cat > /dev/null; exit 3", 1024, |_| Ok(())).unwrap();
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn test_pipe_to_early_exit() {
+    let status = pipe_to("exit 0", 1024, |output| {
+        // way more than fits in a pipe buffer
+        for _ in 0..100_000 {
+            output.write_all(b"+ deadbeef\n")?;
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert!(status.success());
+}
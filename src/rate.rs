@@ -0,0 +1,85 @@
+//! Finding the idle parts of a profile, for --min-alloc-rate.
+//!
+//! The profile is divided into intervals at its "c" lines, so their granularity is whatever
+//! heaptrack used when recording, usually in the tens of milliseconds. An interval is idle if it
+//! has fewer "+" and "-" lines per second than the minimum rate, except for the last one, whose
+//! duration is unknown.
+
+use std::io::{self, BufRead};
+
+use crate::lifetime::{AllocationFilter, Pairing};
+use crate::reader::{Command, HeaptrackReader};
+
+pub struct Activity {
+    /// per interval, the first one is before the first "c" line
+    pub idle: Vec<bool>,
+    /// drops the allocations made in idle intervals, frees follow their allocation
+    pub filter: AllocationFilter,
+}
+
+/// The first pass: count the allocations and frees of every interval.
+pub fn activity(input: impl BufRead, min_rate: f64) -> io::Result<Activity> {
+    // per interval: start timestamp and number of events
+    let mut intervals = vec![(0u64, 0u64)];
+    // interval of every allocation
+    let mut allocated_in = Vec::new();
+    let mut pairing = Pairing::default();
+
+    for command in HeaptrackReader::new(input) {
+        match command? {
+            Command::Timestamp(timestamp) => intervals.push((timestamp, 0)),
+            Command::Alloc(allocation_index) => {
+                pairing.alloc(allocation_index);
+                allocated_in.push(intervals.len() - 1);
+                intervals.last_mut().unwrap().1 += 1;
+            }
+            Command::Free(_) => intervals.last_mut().unwrap().1 += 1,
+            _ => {}
+        }
+    }
+
+    let mut idle: Vec<bool> = intervals
+        .windows(2)
+        .map(|pair| {
+            let ((start, events), (end, _)) = (pair[0], pair[1]);
+            let duration_ms = end.saturating_sub(start);
+            duration_ms > 0 && (events as f64 * 1000.0 / duration_ms as f64) < min_rate
+        })
+        .collect();
+    idle.push(false);
+
+    let filter = AllocationFilter::new(allocated_in.into_iter().map(|x| !idle[x]).collect());
+    Ok(Activity { idle, filter })
+}
+
+#[test]
+fn test_activity() {
+    let activity = activity(
+        &b"\
+a 8 1
+c 0
++ 0
++ 0
+c a
++ 0
+c 3e8
+- 0
+- 0
+- 0
+c 3f2
++ 0
+"[..],
+        100.0,
+    )
+    .unwrap();
+    // 2 events in 10ms, 1 in 990ms, 3 in 10ms and the last one
+    assert_eq!(activity.idle, [false, false, true, false, false]);
+
+    let mut pairing = Pairing::default();
+    let kept: Vec<_> = [b'+', b'+', b'+', b'-', b'-', b'-', b'+']
+        .into_iter()
+        .map(|instruction| activity.filter.keep(&mut pairing, instruction, 0))
+        .collect();
+    // the third allocation is dropped, and so is its free, which is the first one
+    assert_eq!(kept, [true, true, false, false, true, true, true]);
+}
@@ -0,0 +1,159 @@
+//! Parsing a profile into commands, for analyses that don't need to be as fast as trimming.
+
+use std::io::{self, BufRead};
+use std::slice;
+
+use crate::format::Version;
+use crate::{malformed_line_error, parse_hex};
+
+/// One line of a profile. See `run_main` for what they mean.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// "v"
+    Version(Version),
+    /// "c", milliseconds since the start of the profile
+    Timestamp(u64),
+    /// "a", defines the next allocation index
+    AllocationInfo { size: u64, trace: u64 },
+    /// "+", refers to an allocation index
+    Alloc(u64),
+    /// "-", refers to an allocation index
+    Free(u64),
+    /// any other line, without the trailing newline
+    Other(Vec<u8>),
+}
+
+pub struct HeaptrackReader<R> {
+    input: R,
+    line: Vec<u8>,
+    lineno: u64,
+}
+
+impl<R: BufRead> HeaptrackReader<R> {
+    pub fn new(input: R) -> Self {
+        HeaptrackReader {
+            input,
+            line: Vec::new(),
+            lineno: 0,
+        }
+    }
+
+    /// Tag every command with the timestamp it happened at.
+    pub fn timed(self) -> TimedCommands<R> {
+        TimedCommands {
+            reader: self,
+            timestamp: 0,
+        }
+    }
+}
+
+/// Parse a single line, with or without the trailing newline. None if it is malformed.
+pub fn parse_command(line: &[u8]) -> Option<Command> {
+    let line = line.trim_ascii_end();
+    let mut args = line.split(|x| *x == b' ').skip(1);
+    let mut arg = || parse_hex(args.next()?).ok();
+
+    Some(match line.first() {
+        Some(b'v') => Command::Version(Version::parse(line)?),
+        Some(b'c') => Command::Timestamp(arg()?),
+        Some(b'a') => Command::AllocationInfo {
+            size: arg()?,
+            trace: arg()?,
+        },
+        Some(b'+') => Command::Alloc(arg()?),
+        Some(b'-') => Command::Free(arg()?),
+        _ => Command::Other(line.to_vec()),
+    })
+}
+
+impl<R: BufRead> Iterator for HeaptrackReader<R> {
+    type Item = io::Result<Command>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.line.clear();
+        match self.input.read_until(b'\n', &mut self.line) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        self.lineno += 1;
+
+        Some(
+            parse_command(&self.line)
+                .ok_or_else(|| malformed_line_error(self.lineno, slice::from_ref(&self.line))),
+        )
+    }
+}
+
+/// Yields every command together with the most recent timestamp, or 0 before the first "c" line.
+/// For "c" lines, that is the timestamp of the line itself.
+pub struct TimedCommands<R> {
+    reader: HeaptrackReader<R>,
+    timestamp: u64,
+}
+
+impl<R: BufRead> Iterator for TimedCommands<R> {
+    type Item = io::Result<(u64, Command)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.reader.next()?.map(|command| {
+            if let Command::Timestamp(timestamp) = command {
+                self.timestamp = timestamp;
+            }
+            (self.timestamp, command)
+        }))
+    }
+}
+
+#[test]
+fn test_commands() {
+    let commands: Vec<Command> =
+        HeaptrackReader::new(&b"v 10400 3\nX ./a.out\na 10 1\n+ 0\n- 0\nc 3e8\n"[..])
+            .collect::<io::Result<_>>()
+            .unwrap();
+    assert_eq!(
+        commands,
+        [
+            Command::Version(Version {
+                heaptrack: 0x10400,
+                file_format: 3
+            }),
+            Command::Other(b"X ./a.out".to_vec()),
+            Command::AllocationInfo { size: 16, trace: 1 },
+            Command::Alloc(0),
+            Command::Free(0),
+            Command::Timestamp(1000),
+        ]
+    );
+
+    let error = HeaptrackReader::new(&b"c 1\n+ zz\n"[..])
+        .nth(1)
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "line 2 of the input is malformed: \"+ zz\"
+expected \"+ <allocation index>\", with all numbers in hex
+>        2 | + zz"
+    );
+}
+
+#[test]
+fn test_timed_commands() {
+    let commands: Vec<(u64, Command)> =
+        HeaptrackReader::new(&b"a 10 1\n+ 0\nc 5\n+ 0\nc 7\n- 0\n"[..])
+            .timed()
+            .collect::<io::Result<_>>()
+            .unwrap();
+    assert_eq!(
+        commands,
+        [
+            (0, Command::AllocationInfo { size: 16, trace: 1 }),
+            (0, Command::Alloc(0)),
+            (5, Command::Timestamp(5)),
+            (5, Command::Alloc(0)),
+            (7, Command::Timestamp(7)),
+            (7, Command::Free(0)),
+        ]
+    );
+}
@@ -0,0 +1,148 @@
+//! Keeping only allocations that grow by being reallocated, for --min-realloc-growth.
+//!
+//! heaptrack does not record reallocations as such: `realloc` is written as a "-" line for the
+//! old allocation directly followed by a "+" line for the new one, at most with the "a" line of
+//! the new allocation info in between. Nothing in the format links the two, so a free directly
+//! followed by an allocation counts as a reallocation, which also catches a `free` directly
+//! followed by an unrelated `malloc`. Consecutive reallocations form a chain, starting at an
+//! allocation that does not directly follow a free.
+//!
+//! Profiles without any such pairs keep nothing.
+
+use std::io::{self, BufRead};
+
+use crate::lifetime::{AllocationFilter, Pairing};
+use crate::reader::{Command, HeaptrackReader};
+
+/// The first pass: follow the reallocation chains, and keep the allocations of every chain whose
+/// largest size is at least `min_factor` times its first size. Frees follow their allocation.
+/// Also returns how many chains had at least one reallocation.
+pub fn growth_filter(input: impl BufRead, min_factor: f64) -> io::Result<(AllocationFilter, u64)> {
+    let mut allocation_sizes = Vec::new();
+    let mut pairing = Pairing::default();
+    // chain of every allocation
+    let mut chain_of = Vec::new();
+    // (first size, largest size, reallocations) of every chain
+    let mut chains: Vec<(u64, u64, u64)> = Vec::new();
+    // the allocation freed by the last line, if nothing but "a" lines came after it
+    let mut freed = None;
+
+    for command in HeaptrackReader::new(input) {
+        match command? {
+            Command::AllocationInfo { size, .. } => allocation_sizes.push(size),
+            Command::Free(allocation_index) => {
+                freed = pairing.free(allocation_index);
+            }
+            Command::Alloc(allocation_index) => {
+                pairing.alloc(allocation_index);
+                let size = allocation_sizes
+                    .get(allocation_index as usize)
+                    .copied()
+                    .unwrap_or(0);
+                match freed.take() {
+                    Some(old) => {
+                        let chain = chain_of[old as usize];
+                        let (_, largest, reallocations) = &mut chains[chain];
+                        *largest = size.max(*largest);
+                        *reallocations += 1;
+                        chain_of.push(chain);
+                    }
+                    None => {
+                        chain_of.push(chains.len());
+                        chains.push((size, size, 0));
+                    }
+                }
+            }
+            _ => freed = None,
+        }
+    }
+
+    let reallocated = chains.iter().filter(|(_, _, x)| *x > 0).count() as u64;
+    let is_kept: Vec<_> = chains
+        .iter()
+        .map(|(first, largest, reallocations)| {
+            *reallocations > 0 && *largest as f64 >= *first as f64 * min_factor
+        })
+        .collect();
+    let filter = AllocationFilter::new(chain_of.into_iter().map(|x| is_kept[x]).collect());
+    Ok((filter, reallocated))
+}
+
+pub fn parse_growth_factor(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(factor) if factor >= 1.0 => Ok(factor),
+        _ => Err(format!(
+            "invalid growth factor {:?}, expected a number of at least 1",
+            value
+        )),
+    }
+}
+
+#[test]
+fn test_growth_filter() {
+    // a buffer growing from 0x10 to 0x40 bytes, and an unrelated allocation that is reallocated
+    // without growing, then freed and allocated again with a timestamp in between, which is not a
+    // reallocation
+    let profile = b"\
+v 10400 3
+c 1
+a 10 1
+a 8 2
++ 0
++ 1
+c 2
+- 0
+a 20 1
++ 2
+- 1
++ 1
+- 2
+a 40 1
++ 3
+- 1
+c 3
++ 1
+- 3
+";
+    let keep = |min_factor| {
+        let (filter, chains) = growth_filter(&profile[..], min_factor).unwrap();
+        let mut pairing = Pairing::default();
+        let kept: String = [
+            (b'+', 0),
+            (b'+', 1),
+            (b'-', 0),
+            (b'+', 2),
+            (b'-', 1),
+            (b'+', 1),
+            (b'-', 2),
+            (b'+', 3),
+            (b'-', 1),
+            (b'+', 1),
+            (b'-', 3),
+        ]
+        .into_iter()
+        .map(|(instruction, index)| {
+            if filter.keep(&mut pairing, instruction, index) {
+                'y'
+            } else {
+                'n'
+            }
+        })
+        .collect();
+        (kept, chains)
+    };
+
+    // the buffer grew by 4 times, the other allocation not at all
+    assert_eq!(keep(4.0), ("ynyynnyynny".to_owned(), 2));
+    assert_eq!(keep(4.5), ("nnnnnnnnnnn".to_owned(), 2));
+    // every reallocated allocation
+    assert_eq!(keep(1.0), ("yyyyyyyyyny".to_owned(), 2));
+}
+
+#[test]
+fn test_parse_growth_factor() {
+    assert_eq!(parse_growth_factor("2"), Ok(2.0));
+    assert_eq!(parse_growth_factor("1.5"), Ok(1.5));
+    assert!(parse_growth_factor("0.5").is_err());
+    assert!(parse_growth_factor("x").is_err());
+}
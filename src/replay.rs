@@ -0,0 +1,182 @@
+//! Replaying allocations to find out how large the live heap is over time.
+
+use std::io::{self, BufRead, Write};
+
+use crate::reader::{Command, HeaptrackReader};
+
+/// The size of the live heap, sampled at every "c" line.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LiveSizes {
+    /// `(timestamp, live bytes)`, where live bytes is the heap size right when the timestamp
+    /// was reached.
+    pub samples: Vec<(u64, i64)>,
+    /// live bytes at the end of the profile
+    pub end: i64,
+}
+
+impl LiveSizes {
+    /// Live bytes at the sample with the given index, or at the end of the profile.
+    fn at(&self, index: usize) -> i64 {
+        self.samples
+            .get(index)
+            .map_or(self.end, |(_, bytes)| *bytes)
+    }
+
+    /// Find the skip timestamp from which the next `window_ms` milliseconds have the largest net
+    /// increase of the live heap. Candidates are the timestamps in the profile, on ties the
+    /// earliest one wins.
+    pub fn hotspot(&self, window_ms: u64) -> u64 {
+        let mut best = (0, i64::MIN);
+        // index of the first sample after the window
+        let mut end = 0;
+
+        for (start, (timestamp, _)) in self.samples.iter().enumerate() {
+            while end < self.samples.len()
+                && self.samples[end].0 <= timestamp.saturating_add(window_ms)
+            {
+                end += 1;
+            }
+
+            // trimming starts at the first timestamp after the skip timestamp
+            let mut first = start + 1;
+            while first < self.samples.len() && self.samples[first].0 <= *timestamp {
+                first += 1;
+            }
+
+            let growth = self.at(end) - self.at(first);
+            if growth > best.1 {
+                best = (*timestamp, growth);
+            }
+        }
+
+        best.0
+    }
+}
+
+/// At most `points` of the samples, for --timeline-points. The time from the first to the last
+/// sample is split into `points` buckets of the same duration, and from each bucket the sample
+/// with the most live bytes is kept, the earliest one on ties. The largest sample is the largest
+/// in its bucket, so the peak of the whole profile is always kept. Buckets without samples are
+/// left out. If timestamps went backwards, the buckets span from the smallest to the largest one.
+pub fn downsample(samples: &[(u64, i64)], points: usize) -> Vec<(u64, i64)> {
+    let timestamps = samples.iter().map(|(timestamp, _)| *timestamp);
+    let (Some(first), Some(last)) = (timestamps.clone().min(), timestamps.max()) else {
+        return Vec::new();
+    };
+    let duration = (last - first) as u128 + 1;
+    let mut buckets: Vec<Option<(u64, i64)>> = vec![None; points];
+    for (timestamp, bytes) in samples {
+        let bucket = ((timestamp - first) as u128 * points as u128 / duration) as usize;
+        match &mut buckets[bucket] {
+            Some((_, max)) if *max >= *bytes => {}
+            max => *max = Some((*timestamp, *bytes)),
+        }
+    }
+    buckets.into_iter().flatten().collect()
+}
+
+/// "timestamp_ms,live_bytes", and one line per sample.
+pub fn write_csv(samples: &[(u64, i64)], mut output: impl Write) -> io::Result<()> {
+    writeln!(output, "timestamp_ms,live_bytes")?;
+    for (timestamp, bytes) in samples {
+        writeln!(output, "{},{}", timestamp, bytes)?;
+    }
+    Ok(())
+}
+
+pub fn live_sizes(input: impl BufRead) -> io::Result<LiveSizes> {
+    let mut allocation_sizes = Vec::new();
+    let mut sizes = LiveSizes::default();
+    let size = |sizes: &[i64], index: u64| sizes.get(index as usize).copied().unwrap_or(0);
+
+    for command in HeaptrackReader::new(input).timed() {
+        match command? {
+            (timestamp, Command::Timestamp(_)) => sizes.samples.push((timestamp, sizes.end)),
+            (_, Command::AllocationInfo { size, .. }) => allocation_sizes.push(size as i64),
+            (_, Command::Alloc(index)) => sizes.end += size(&allocation_sizes, index),
+            (_, Command::Free(index)) => sizes.end -= size(&allocation_sizes, index),
+            _ => {}
+        }
+    }
+
+    Ok(sizes)
+}
+
+#[test]
+fn test_live_sizes() {
+    let sizes = live_sizes(
+        &b"\
+v 10400 3
+a 10 1
++ 0
+c 1
+a 20 1
++ 1
++ 0
+c 2
+- 0
+"[..],
+    )
+    .unwrap();
+
+    assert_eq!(
+        sizes,
+        LiveSizes {
+            samples: vec![(1, 0x10), (2, 0x40)],
+            end: 0x30,
+        }
+    );
+}
+
+#[test]
+fn test_hotspot() {
+    let sizes = LiveSizes {
+        samples: vec![
+            (0, 0),
+            (1000, 100),
+            (2000, 100),
+            (3000, 500),
+            (4000, 600),
+            (5000, 200),
+            (6000, 600),
+        ],
+        end: 600,
+    };
+
+    // skipping until 1000 keeps the samples at 2000 and 3000, growth is 500
+    assert_eq!(sizes.hotspot(2000), 1000);
+    // 2000 to 3000 and 5000 to 6000 both grew by 400, the earlier one wins
+    assert_eq!(sizes.hotspot(1000), 1000);
+    // window longer than the profile
+    assert_eq!(sizes.hotspot(100_000), 0);
+    assert_eq!(sizes.hotspot(u64::MAX), 0);
+}
+
+#[test]
+fn test_downsample() {
+    let samples: Vec<_> = (0..100).map(|i| (i * 10, (i as i64 * 37) % 101)).collect();
+    let peak = *samples.iter().max_by_key(|(_, bytes)| *bytes).unwrap();
+    for points in [1, 3, 10, 99, 100, 1000] {
+        let downsampled = downsample(&samples, points);
+        assert!(downsampled.len() <= points);
+        assert!(downsampled.contains(&peak), "{}", points);
+        assert!(downsampled.windows(2).all(|x| x[0].0 < x[1].0));
+    }
+    assert_eq!(downsample(&samples, 100), samples);
+    assert_eq!(downsample(&[], 10), vec![]);
+
+    // the largest of each half, the earliest one on ties
+    let samples = [(0, 5), (1, 7), (2, 7), (3, 1), (4, 3)];
+    assert_eq!(downsample(&samples, 2), vec![(1, 7), (4, 3)]);
+
+    // timestamps going backwards
+    let backwards = [(5, 1), (2, 3), (9, 2)];
+    assert_eq!(downsample(&backwards, 2), vec![(2, 3), (9, 2)]);
+
+    let mut csv = Vec::new();
+    write_csv(&samples[..2], &mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "timestamp_ms,live_bytes\n0,5\n1,7\n"
+    );
+}
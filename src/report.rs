@@ -0,0 +1,97 @@
+//! A record of everything a trim did, for --trim-report.
+//!
+//! The report is one JSON object:
+//!
+//! ```text
+//! {
+//!   "skip": {"mode": "time", "skip_timestamp_ms": 1000, "stop_timestamp_ms": null,
+//!            "stopped_early": false},
+//!   "time": {"preserve_time": false, "offset_ms": 0, "merge_timestamps_ms": null,
+//!            "merged_timestamps": 0, "idle_ms": 0},
+//!   "rebase_mode": "dense",
+//!   "stages": [{"name": "trimming", "events": 10, "dropped": 4}, ...],
+//!   "export": "heaptrack",
+//!   "bytes": {"input": 2000, "trimmed": 500, "exported": 500},
+//!   "stats": {"input_bytes": 2000, ...}
+//! }
+//! ```
+//!
+//! "mode" is how the start of the kept part was found: "time", "index" for --start-from-index,
+//! "markers" for --between-markers, or "none" when nothing is skipped. "skip_timestamp_ms" is
+//! where the kept part starts in the input, which for "index" and "markers" is only known after
+//! trimming, and "stopped_early" is whether the stop timestamp or end marker cut off the rest of
+//! the input. "merged_timestamps" counts the "c" lines dropped by --merge-timestamps, "idle_ms"
+//! is how much time --min-alloc-rate cut out of the timeline. "stages" are the numbers of
+//! --filter-stats, in the same order, and "stats" are those of --summary-only --json.
+//!
+//! "bytes" are the sizes at every step of the output: "input" is what was read, "trimmed" the
+//! heaptrack profile written by the trim, the same as "output_bytes" in "stats", and "exported"
+//! what --export made of it, or the same as "trimmed" without it. Everything after
+//! heaptrack-trim is not included, what a compressor behind --pipe-to makes of the output is
+//! unknown.
+
+use std::io::{self, Write};
+
+use crate::filter;
+use crate::stats::TrimStats;
+
+/// Collected by the trim into `TrimStats::report`, and written once the output is complete, with
+/// the size of the output after --export.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrimReport {
+    pub skip_mode: &'static str,
+    pub skip_timestamp_ms: u64,
+    pub stop_timestamp_ms: Option<u64>,
+    pub is_truncated: bool,
+    pub preserve_time: bool,
+    pub time_offset_ms: i64,
+    pub merge_timestamps_ms: Option<u64>,
+    pub merged_timestamps: u64,
+    pub idle_ms: u64,
+    pub rebase_mode: &'static str,
+    /// `FilterChain::stage_stats`
+    pub stages: Vec<(&'static str, u64, u64)>,
+    pub export: &'static str,
+}
+
+/// `null` for None.
+fn json_option(value: Option<u64>) -> String {
+    value.map_or("null".to_owned(), |x| x.to_string())
+}
+
+impl TrimReport {
+    /// The report, with a trailing newline.
+    pub fn write_json(
+        &self,
+        stats: &TrimStats,
+        exported_bytes: u64,
+        mut output: impl Write,
+    ) -> io::Result<()> {
+        write!(
+            output,
+            "{{\"skip\":{{\"mode\":\"{}\",\"skip_timestamp_ms\":{},\"stop_timestamp_ms\":{},\
+             \"stopped_early\":{}}},\"time\":{{\"preserve_time\":{},\"offset_ms\":{},\
+             \"merge_timestamps_ms\":{},\"merged_timestamps\":{},\"idle_ms\":{}}},\
+             \"rebase_mode\":\"{}\",\"stages\":",
+            self.skip_mode,
+            self.skip_timestamp_ms,
+            json_option(self.stop_timestamp_ms),
+            self.is_truncated,
+            self.preserve_time,
+            self.time_offset_ms,
+            json_option(self.merge_timestamps_ms),
+            self.merged_timestamps,
+            self.idle_ms,
+            self.rebase_mode
+        )?;
+        filter::write_stages_json(&self.stages, &mut output)?;
+        write!(
+            output,
+            ",\"export\":\"{}\",\"bytes\":{{\"input\":{},\"trimmed\":{},\"exported\":{}}},\
+             \"stats\":",
+            self.export, stats.input_bytes, stats.output_bytes, exported_bytes
+        )?;
+        stats.write_json(&mut output)?;
+        output.write_all(b"}\n")
+    }
+}
@@ -0,0 +1,118 @@
+//! Keeping allocations by how much memory the process used when they were made, for --rss-band.
+//!
+//! heaptrack records the resident set size in "R" lines, in bytes, about as often as timestamps.
+//! An allocation is kept if the most recent "R" line before it is within the band, which means
+//! nothing is kept before the first one. Frees are matched to allocations like in `lifetime`,
+//! and follow their allocation.
+
+use crate::filter::EventFilter;
+use crate::lifetime::Pairing;
+
+pub struct RssBand {
+    low: u64,
+    high: u64,
+    rss: Option<u64>,
+    pairing: Pairing,
+    /// per allocation, numbered like in `Pairing`
+    kept: Vec<bool>,
+}
+
+impl RssBand {
+    pub fn new(low: u64, high: u64) -> Self {
+        RssBand {
+            low,
+            high,
+            rss: None,
+            pairing: Pairing::default(),
+            kept: Vec::new(),
+        }
+    }
+
+    pub fn set_rss(&mut self, rss: u64) {
+        self.rss = Some(rss);
+    }
+
+    /// Whether to write a "+" or "-" line. Has to be called for every one of them, in order.
+    pub fn keep(&mut self, instruction: u8, allocation_index: u64) -> bool {
+        if instruction == b'+' {
+            self.pairing.alloc(allocation_index);
+            let keep = self
+                .rss
+                .is_some_and(|rss| (self.low..=self.high).contains(&rss));
+            self.kept.push(keep);
+            keep
+        } else {
+            // frees that do not match any allocation are none of our business
+            self.pairing
+                .free(allocation_index)
+                .is_none_or(|x| self.kept[x as usize])
+        }
+    }
+}
+
+impl EventFilter for RssBand {
+    fn keep(&mut self, instruction: u8, allocation_index: u64) -> bool {
+        RssBand::keep(self, instruction, allocation_index)
+    }
+
+    fn rss(&mut self, rss: u64) {
+        self.set_rss(rss);
+    }
+}
+
+/// Parse `LOW:HIGH` in bytes.
+pub fn parse_rss_band(value: &str) -> Result<(u64, u64), String> {
+    let invalid = || {
+        format!(
+            "expected LOW:HIGH in bytes with LOW <= HIGH, got {:?}",
+            value
+        )
+    };
+    let (low, high) = value.split_once(':').ok_or_else(invalid)?;
+    let low: u64 = low.parse().map_err(|_| invalid())?;
+    let high: u64 = high.parse().map_err(|_| invalid())?;
+    if low > high {
+        return Err(invalid());
+    }
+    Ok((low, high))
+}
+
+/// Parse "1/N" or just "N", for --sample-rss.
+pub fn parse_rss_sampling(value: &str) -> Result<u64, String> {
+    match value.strip_prefix("1/").unwrap_or(value).parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("expected 1/N with N at least 1, got {:?}", value)),
+    }
+}
+
+#[test]
+fn test_parse_rss_sampling() {
+    assert_eq!(parse_rss_sampling("1/10"), Ok(10));
+    assert_eq!(parse_rss_sampling("10"), Ok(10));
+    assert_eq!(parse_rss_sampling("1/1"), Ok(1));
+    assert!(parse_rss_sampling("1/0").is_err());
+    assert!(parse_rss_sampling("2/10").is_err());
+}
+
+#[test]
+fn test_rss_band() {
+    let mut band = RssBand::new(100, 200);
+    // no RSS sample yet
+    assert!(!band.keep(b'+', 0));
+    band.set_rss(150);
+    assert!(band.keep(b'+', 0));
+    band.set_rss(250);
+    assert!(!band.keep(b'+', 0));
+    assert!(!band.keep(b'+', 1));
+    band.set_rss(100);
+    // frees follow their allocation, not the current RSS
+    assert!(!band.keep(b'-', 0));
+    assert!(band.keep(b'-', 0));
+    assert!(!band.keep(b'-', 0));
+    assert!(!band.keep(b'-', 1));
+    assert!(band.keep(b'-', 2));
+
+    assert_eq!(parse_rss_band("100:200"), Ok((100, 200)));
+    assert!(parse_rss_band("200:100").is_err());
+    assert!(parse_rss_band("100").is_err());
+}
@@ -0,0 +1,161 @@
+//! Keeping only the largest allocations, for --min-size-percentile.
+//!
+//! The first pass finds the size at the given percentile of all "+" lines, and allocations at
+//! least that large are kept. Sizes are counted in the same log-scale histogram as lifetimes in
+//! `lifetime`, and the cutoff is the smallest size in the bucket of the percentile. That is up to
+//! 12.5% below the exact percentile, so a few allocations slightly smaller than it are kept too.
+//! Sizes of 4GiB and more share the last bucket.
+//!
+//! Both passes only keep a byte per "a" line, the "+" lines themselves are only counted, so the
+//! memory needed depends on the number of allocation infos, not on the number of allocations.
+
+use std::io::{self, BufRead};
+
+use crate::filter::EventFilter;
+use crate::lifetime;
+use crate::pages;
+use crate::reader::{Command, HeaptrackReader};
+
+/// The first pass: the size in bytes at the `percentile` of all allocation sizes, rounded down
+/// to the start of its bucket. With `page_size`, sizes are rounded up to multiples of it first.
+pub fn percentile_filter(
+    input: impl BufRead,
+    percentile: f64,
+    page_size: Option<u64>,
+) -> io::Result<u64> {
+    // bucket per allocation info
+    let mut allocation_buckets = Vec::new();
+    let mut histogram = [0; lifetime::BUCKETS];
+
+    for command in HeaptrackReader::new(input) {
+        match command? {
+            Command::AllocationInfo { size, .. } => {
+                let size = page_size.map_or(size, |x| pages::round_up(size, x));
+                allocation_buckets.push(lifetime::bucket(size));
+            }
+            Command::Alloc(allocation_index) => {
+                let bucket = allocation_buckets
+                    .get(allocation_index as usize)
+                    .copied()
+                    .unwrap_or(0);
+                histogram[bucket as usize] += 1;
+            }
+            _ => {}
+        }
+    }
+
+    let cutoff = lifetime::percentile_bucket(&histogram, percentile);
+    Ok(lifetime::bucket_start(cutoff as usize))
+}
+
+/// Keeps the events of allocation infos of at least `min_size` bytes. Since `min_size` is the
+/// start of a bucket, these are the ones in its bucket or a larger one.
+pub struct MinSize {
+    min_size: u64,
+    /// by allocation index
+    matches: Vec<bool>,
+}
+
+impl MinSize {
+    pub fn new(min_size: u64) -> Self {
+        MinSize {
+            min_size,
+            matches: Vec::new(),
+        }
+    }
+}
+
+impl EventFilter for MinSize {
+    fn keep(&mut self, _instruction: u8, allocation_index: u64) -> bool {
+        self.matches
+            .get(allocation_index as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    fn allocation_info(&mut self, size: u64, _trace: u64) {
+        self.matches.push(size >= self.min_size);
+    }
+}
+
+pub fn parse_percentile(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(percentile) if (0.0..=100.0).contains(&percentile) => Ok(percentile),
+        _ => Err(format!(
+            "invalid percentile {:?}, expected a number between 0 and 100",
+            value
+        )),
+    }
+}
+
+#[test]
+fn test_percentile_filter() {
+    // one allocation each of 100, 200, ..., 1000 bytes, and frees of the smallest and largest
+    let profile = b"\
+v 10400 3
+a 64 1
+a c8 1
+a 12c 1
+a 190 1
+a 1f4 1
+a 258 1
+a 2bc 1
+a 320 1
+a 384 1
+a 3e8 1
+c 1
++ 0
++ 1
++ 2
++ 3
++ 4
++ 5
++ 6
++ 7
++ 8
++ 9
+- 0
+- 9
+";
+    let keep = |percentile| {
+        let cutoff = percentile_filter(&profile[..], percentile, None).unwrap();
+        let mut filter = MinSize::new(cutoff);
+        for size in (1..=10).map(|x| x * 100) {
+            filter.allocation_info(size, 1);
+        }
+        let mut events: Vec<_> = (0..10).map(|index| (b'+', index)).collect();
+        events.extend([(b'-', 0), (b'-', 9)]);
+        let kept: String = events
+            .into_iter()
+            .map(|(instruction, index)| {
+                if filter.keep(instruction, index) {
+                    'y'
+                } else {
+                    'n'
+                }
+            })
+            .collect();
+        (kept, cutoff)
+    };
+
+    // the median is 500 bytes, in the bucket from 480 to 511 bytes
+    assert_eq!(keep(50.0), ("nnnnyyyyyyny".to_owned(), 480));
+    // 1000 bytes, in the bucket from 960 to 1023 bytes
+    assert_eq!(keep(100.0), ("nnnnnnnnnyny".to_owned(), 960));
+    assert_eq!(keep(0.0), ("yyyyyyyyyyyy".to_owned(), 96));
+
+    // rounded up to 4KiB pages, every size is the same
+    assert_eq!(
+        percentile_filter(&profile[..], 100.0, Some(4096)).unwrap(),
+        4096
+    );
+}
+
+#[test]
+fn test_parse_percentile() {
+    assert_eq!(parse_percentile("90"), Ok(90.0));
+    assert_eq!(parse_percentile("99.9"), Ok(99.9));
+    assert!(parse_percentile("101").is_err());
+    assert!(parse_percentile("-1").is_err());
+    assert!(parse_percentile("x").is_err());
+}
@@ -0,0 +1,376 @@
+//! Splitting a profile into several smaller ones that can be opened independently.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use crate::stats::{write_json_string, CountingWriter, TrimStats};
+use crate::{malformed_line_error, parse_hex, write_hex};
+
+/// One of the files written.
+#[derive(Debug)]
+pub struct Chunk {
+    pub path: PathBuf,
+    /// the timestamp in the input at which the chunk starts
+    pub start_ms: u64,
+    /// the byte offset in the input at which the chunk starts
+    pub input_offset: u64,
+    /// `profile_duration_ms` is the timestamp in the input at which the chunk ends
+    pub stats: TrimStats,
+}
+
+/// Write the chunks as a JSON array of objects with the fields of `Chunk`, for example:
+///
+/// ```text
+/// [{"path":"profile.0","start_ms":1000,"input_offset":4096,"stats":{"input_bytes":...}}]
+/// ```
+pub fn write_manifest(chunks: &[Chunk], mut output: impl Write) -> io::Result<()> {
+    output.write_all(b"[")?;
+    for (i, chunk) in chunks.iter().enumerate() {
+        if i > 0 {
+            output.write_all(b",")?;
+        }
+        output.write_all(b"{\"path\":")?;
+        write_json_string(&mut output, &chunk.path.to_string_lossy())?;
+        write!(
+            output,
+            ",\"start_ms\":{},\"input_offset\":{},\"stats\":",
+            chunk.start_ms, chunk.input_offset
+        )?;
+        chunk.stats.write_json(&mut output)?;
+        output.write_all(b"}")?;
+    }
+    output.write_all(b"]\n")
+}
+
+/// The allocation infos of one chunk. Every chunk starts numbering them from 0 again.
+#[derive(Default)]
+struct ChunkAllocations {
+    /// original allocation index to index in this chunk, and how many allocations of it were not
+    /// freed yet
+    indices: HashMap<u64, (u64, u64)>,
+}
+
+struct Splitter<'a> {
+    prefix: &'a Path,
+    buf_size: usize,
+    preserve_time: bool,
+    /// the lines that define something, like strings and traces, they are copied into every
+    /// chunk
+    header: Vec<u8>,
+    /// the "a" lines of the input, by allocation index
+    allocation_infos: Vec<Vec<u8>>,
+    chunks: Vec<Chunk>,
+    output: Option<CountingWriter<BufWriter<File>>>,
+    allocations: ChunkAllocations,
+    chunk_start_ms: u64,
+}
+
+impl Splitter<'_> {
+    fn chunk_path(&self, chunk: u64) -> PathBuf {
+        let mut path = self.prefix.as_os_str().to_owned();
+        path.push(format!(".{}", chunk));
+        PathBuf::from(path)
+    }
+
+    fn write_timestamp(&mut self, timestamp: u64) -> io::Result<()> {
+        let output = self.output.as_mut().unwrap();
+        output.write_all(b"c ")?;
+        if self.preserve_time {
+            write_hex(&mut *output, timestamp)?;
+        } else {
+            // saturating, since timestamps can go backwards
+            write_hex(&mut *output, timestamp.saturating_sub(self.chunk_start_ms))?;
+        }
+        output.write_all(b"\n")
+    }
+
+    fn finish_chunk(&mut self) -> io::Result<()> {
+        if let Some(mut output) = self.output.take() {
+            output.flush()?;
+            self.chunks.last_mut().unwrap().stats.output_bytes = output.bytes;
+        }
+        Ok(())
+    }
+
+    fn start_chunk(&mut self, timestamp: u64, input_offset: u64) -> io::Result<()> {
+        self.finish_chunk()?;
+
+        let path = self.chunk_path(self.chunks.len() as u64);
+        let mut output = CountingWriter {
+            inner: BufWriter::with_capacity(self.buf_size, File::create(&path)?),
+            bytes: 0,
+        };
+        output.write_all(&self.header)?;
+        self.output = Some(output);
+        self.chunks.push(Chunk {
+            path,
+            start_ms: timestamp,
+            input_offset,
+            stats: TrimStats {
+                profile_duration_ms: timestamp,
+                ..TrimStats::default()
+            },
+        });
+        self.allocations = ChunkAllocations::default();
+        self.chunk_start_ms = timestamp;
+        self.write_timestamp(timestamp)
+    }
+
+    fn write_event(&mut self, instruction: u8, allocation_index: u64) -> io::Result<()> {
+        let output = self.output.as_mut().unwrap();
+        let next_index = self.allocations.indices.len() as u64;
+        let (index, outstanding) = match self.allocations.indices.get_mut(&allocation_index) {
+            Some(x) => x,
+            // a free of an allocation from a previous chunk
+            None if instruction == b'-' => return Ok(()),
+            None => {
+                let info = self
+                    .allocation_infos
+                    .get(allocation_index as usize)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("allocation index {:x} is not defined", allocation_index),
+                        )
+                    })?;
+                output.write_all(info)?;
+                self.allocations
+                    .indices
+                    .entry(allocation_index)
+                    .or_insert((next_index, 0))
+            }
+        };
+
+        if instruction == b'+' {
+            *outstanding += 1;
+        } else if *outstanding == 0 {
+            return Ok(());
+        } else {
+            *outstanding -= 1;
+        }
+
+        let stats = &mut self.chunks.last_mut().unwrap().stats;
+        stats.output_events += 1;
+        stats.largest_written_allocation_index = stats.largest_written_allocation_index.max(*index);
+        output.write_all(&[instruction, b' '])?;
+        write_hex(&mut *output, *index)?;
+        output.write_all(b"\n")
+    }
+}
+
+/// Write the profile to `<prefix>.0`, `<prefix>.1`, ..., starting a new file every
+/// `allocations_per_chunk` "+" lines. Returns the files written.
+pub fn split_every_allocations(
+    mut input: impl BufRead,
+    skip_timestamp: u64,
+    allocations_per_chunk: u64,
+    prefix: &Path,
+    buf_size: usize,
+    preserve_time: bool,
+) -> io::Result<Vec<Chunk>> {
+    let mut splitter = Splitter {
+        prefix,
+        buf_size,
+        preserve_time,
+        header: Vec::new(),
+        allocation_infos: Vec::new(),
+        chunks: Vec::new(),
+        output: None,
+        allocations: ChunkAllocations::default(),
+        chunk_start_ms: 0,
+    };
+
+    let mut is_skipping = skip_timestamp > 0;
+    let mut timestamp = 0;
+    let mut allocations_in_chunk = 0;
+    let mut input_offset = 0;
+    let mut lineno = 0;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read_bytes = input.read_until(b'\n', &mut line)? as u64;
+        if read_bytes == 0 {
+            break;
+        }
+        let line_offset = input_offset;
+        input_offset += read_bytes;
+        lineno += 1;
+
+        let arg = || {
+            line.trim_ascii_end()
+                .split(|x| *x == b' ')
+                .nth(1)
+                .and_then(|x| parse_hex(x).ok())
+                .ok_or_else(|| malformed_line_error(lineno, slice::from_ref(&line)))
+        };
+
+        match line[0] {
+            b'c' => {
+                timestamp = arg()?;
+                if splitter.output.is_some() {
+                    splitter.write_timestamp(timestamp)?;
+                } else if !is_skipping || timestamp > skip_timestamp {
+                    is_skipping = false;
+                    splitter.start_chunk(timestamp, line_offset)?;
+                }
+            }
+            b'a' => splitter.allocation_infos.push(line.clone()),
+            instruction @ (b'+' | b'-') => {
+                if is_skipping {
+                    continue;
+                }
+                // without skipping, events can come before the first timestamp
+                if splitter.output.is_none() {
+                    splitter.start_chunk(timestamp, line_offset)?;
+                }
+                if instruction == b'+' {
+                    if allocations_in_chunk == allocations_per_chunk {
+                        splitter.start_chunk(timestamp, line_offset)?;
+                        allocations_in_chunk = 0;
+                    }
+                    allocations_in_chunk += 1;
+                }
+                splitter.chunks.last_mut().unwrap().stats.input_events += 1;
+                splitter.write_event(instruction, arg()?)?;
+            }
+            b'v' | b'X' | b'I' | b'A' | b'S' | b's' | b'i' | b't' => {
+                splitter.header.extend_from_slice(&line);
+                if let Some(output) = &mut splitter.output {
+                    output.write_all(&line)?;
+                }
+            }
+            // "R" lines, comments and anything else only belong to the chunk they are in
+            _ => {
+                if !is_skipping && splitter.output.is_none() {
+                    splitter.start_chunk(timestamp, line_offset)?;
+                }
+                if let Some(output) = &mut splitter.output {
+                    output.write_all(&line)?;
+                }
+            }
+        }
+
+        if let Some(chunk) = splitter.chunks.last_mut() {
+            chunk.stats.input_bytes += read_bytes;
+            chunk.stats.input_lines += 1;
+            chunk.stats.profile_duration_ms = timestamp;
+        }
+    }
+
+    splitter.finish_chunk()?;
+    Ok(splitter.chunks)
+}
+
+#[test]
+fn test_split_every_allocations() {
+    let dir = std::env::temp_dir().join(format!("heaptrack-trim-split-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let prefix = dir.join("profile");
+
+    let chunks = split_every_allocations(
+        &b"\
+v 10400 3
+s foo
+c 1
+R 64
+a 10 1
++ 0
+a 20 1
++ 1
+c 3
+- 0
++ 0
+s bar
++ 1
+# marker
+c 5
+- 1
+- 0
+- 1
+"[..],
+        0,
+        2,
+        &prefix,
+        1024,
+        false,
+    )
+    .unwrap();
+    assert_eq!(chunks.len(), 2);
+
+    let read = |chunk| std::fs::read_to_string(dir.join(format!("profile.{}", chunk))).unwrap();
+    assert_eq!(
+        read(0),
+        "\
+v 10400 3
+s foo
+c 0
+R 64
+a 10 1
++ 0
+a 20 1
++ 1
+c 2
+- 0
+"
+    );
+    // the second chunk gets its own "a" lines and drops the free of the allocation from the
+    // first chunk, and only the definitions of the first chunk are repeated, not the "R" line
+    assert_eq!(
+        read(1),
+        "\
+v 10400 3
+s foo
+c 0
+a 10 1
++ 0
+s bar
+a 20 1
++ 1
+# marker
+c 2
+- 1
+- 0
+"
+    );
+
+    let mut manifest = Vec::new();
+    write_manifest(&chunks, &mut manifest).unwrap();
+    let path = |chunk| dir.join(format!("profile.{}", chunk)).display().to_string();
+    assert_eq!(
+        String::from_utf8(manifest).unwrap(),
+        format!(
+            "[{{\"path\":{:?},\"start_ms\":1,\"input_offset\":16,\"stats\":{{\"input_bytes\":39,\
+             \"input_lines\":8,\"output_bytes\":55,\"input_events\":3,\"output_events\":3,\
+             \"profile_duration_ms\":3,\"allocation_index_correction\":0,\
+             \"largest_written_allocation_index\":1}}}},\
+             {{\"path\":{:?},\"start_ms\":3,\"input_offset\":55,\"stats\":{{\"input_bytes\":39,\
+             \"input_lines\":8,\"output_bytes\":69,\"input_events\":5,\"output_events\":4,\
+             \"profile_duration_ms\":5,\"allocation_index_correction\":0,\
+             \"largest_written_allocation_index\":1}}}}]\n",
+            path(0),
+            path(1)
+        )
+    );
+
+    // without skipping, the first chunk starts with the first event, even before a timestamp
+    for (input, expected) in [
+        (
+            "v 10400 3\nc 0\na 8 1\n+ 0\n+ 0\nc 1\n- 0\n",
+            "v 10400 3\nc 0\na 8 1\n+ 0\n+ 0\nc 1\n- 0\n",
+        ),
+        (
+            "v 10400 3\na 8 1\n+ 0\nc 1\n- 0\n",
+            "v 10400 3\nc 0\na 8 1\n+ 0\nc 1\n- 0\n",
+        ),
+    ] {
+        let chunks = split_every_allocations(input.as_bytes(), 0, 5, &prefix, 1024, false).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(read(0), expected);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,134 @@
+//! Identifiers for allocation infos that are the same in every profile, for --stable-ids.
+//!
+//! An allocation info is identified by its size and backtrace, and a backtrace by the module,
+//! function, file and line of every frame, but not the instruction pointer, which changes with
+//! every build and with address space layout randomization. Two allocation infos that only differ
+//! in the instruction pointers of their frames get the same id.
+
+use crate::parse_hex;
+
+/// FNV-1a, since std's hashers are not guaranteed to stay the same between Rust versions.
+pub fn hash(state: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(state, |state, byte| {
+        (state ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub const INITIAL: u64 = 0xcbf29ce484222325;
+
+fn hash_u64(state: u64, value: u64) -> u64 {
+    hash(state, &value.to_le_bytes())
+}
+
+#[derive(Default)]
+pub struct StableIds {
+    strings: Vec<u64>,
+    instruction_pointers: Vec<u64>,
+    traces: Vec<u64>,
+    allocation_infos: Vec<u64>,
+}
+
+/// Look up a 1-based index, where 0 and undefined indices hash to 0.
+fn get(table: &[u64], index: u64) -> u64 {
+    (index as usize)
+        .checked_sub(1)
+        .and_then(|x| table.get(x))
+        .copied()
+        .unwrap_or(0)
+}
+
+impl StableIds {
+    /// Remember the definition in `line`, if it is an "s", "i", "t" or "a" line.
+    pub fn add(&mut self, line: &[u8]) {
+        let line = line.trim_ascii_end();
+        let args = || {
+            line.split(|x| *x == b' ')
+                .skip(1)
+                .map(|x| parse_hex(x).unwrap_or(0))
+        };
+
+        match line.first() {
+            Some(b's') => self
+                .strings
+                .push(hash(INITIAL, line.get(2..).unwrap_or_default())),
+            Some(b'i') => {
+                // "i <ip> <module> [<function> <file> <line>]...", all but ip and line are strings
+                let id = args().enumerate().skip(1).fold(INITIAL, |id, (i, arg)| {
+                    if i % 3 == 1 && i > 1 {
+                        hash_u64(id, arg)
+                    } else {
+                        hash_u64(id, get(&self.strings, arg))
+                    }
+                });
+                self.instruction_pointers.push(id);
+            }
+            Some(b't') => {
+                let mut args = args();
+                let ip = get(&self.instruction_pointers, args.next().unwrap_or(0));
+                let parent = get(&self.traces, args.next().unwrap_or(0));
+                self.traces.push(hash_u64(hash_u64(INITIAL, ip), parent));
+            }
+            Some(b'a') => {
+                let mut args = args();
+                let size = args.next().unwrap_or(0);
+                let trace = get(&self.traces, args.next().unwrap_or(0));
+                self.allocation_infos
+                    .push(hash_u64(hash_u64(INITIAL, size), trace));
+            }
+            _ => {}
+        }
+    }
+
+    /// The id of a 0-based allocation index, as used in "+" and "-" lines.
+    pub fn id(&self, allocation_index: u64) -> u64 {
+        get(&self.allocation_infos, allocation_index + 1)
+    }
+
+    /// The ids of all allocation infos so far, by allocation index.
+    pub fn allocation_info_ids(&self) -> &[u64] {
+        &self.allocation_infos
+    }
+}
+
+#[test]
+fn test_stable_ids() {
+    let ids = |profile: &[u8]| {
+        let mut ids = StableIds::default();
+        for line in profile.split_inclusive(|x| *x == b'\n') {
+            ids.add(line);
+        }
+        ids
+    };
+
+    let first = ids(b"\
+s a.out
+s main
+s foo
+i 10 1 2 0 0
+i 20 1 3 0 7
+t 1 0
+t 2 1
+a 8 2
+a 10 2
+a 8 1
+");
+    // the same program, built differently and with the strings in a different order
+    let second = ids(b"\
+s a.out
+s foo
+s bar
+s main
+i 35 1 2 0 7
+i 45 1 4 0 0
+t 2 0
+t 1 1
+a 10 2
+a 8 2
+");
+
+    assert_eq!(first.id(0), second.id(1));
+    assert_eq!(first.id(1), second.id(0));
+    assert_ne!(first.id(0), first.id(1));
+    // same size, but only main() is on the stack
+    assert_ne!(first.id(0), first.id(2));
+}
@@ -0,0 +1,193 @@
+//! Numbers about what a trim did.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::report::TrimReport;
+use crate::stable_id;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrimStats {
+    pub input_bytes: u64,
+    pub input_lines: u64,
+    pub output_bytes: u64,
+    /// "+" and "-" lines in the input
+    pub input_events: u64,
+    /// "+" and "-" lines in the output
+    pub output_events: u64,
+    /// the last timestamp of the input, in milliseconds
+    pub profile_duration_ms: u64,
+    /// how many allocation indices were referenced while skipping. Every index written is the
+    /// original one minus this.
+    pub allocation_index_correction: u64,
+    /// the largest rebased allocation index written. heaptrack-gui needs every index up to this
+    /// one to have an "a" line in the output.
+    pub largest_written_allocation_index: u64,
+    /// with --cache-key, a hash of every byte of the input
+    pub cache_key: Option<u64>,
+    /// with --trim-report, the rest of the report, which is not part of the statistics
+    pub report: Option<TrimReport>,
+}
+
+impl TrimStats {
+    /// A single JSON object, without a trailing newline. "cache_key" is only there with
+    /// --cache-key.
+    pub fn write_json(&self, mut output: impl Write) -> io::Result<()> {
+        write!(
+            output,
+            "{{\"input_bytes\":{},\"input_lines\":{},\"output_bytes\":{},\"input_events\":{},\
+             \"output_events\":{},\"profile_duration_ms\":{},\"allocation_index_correction\":{},\
+             \"largest_written_allocation_index\":{}",
+            self.input_bytes,
+            self.input_lines,
+            self.output_bytes,
+            self.input_events,
+            self.output_events,
+            self.profile_duration_ms,
+            self.allocation_index_correction,
+            self.largest_written_allocation_index
+        )?;
+        if let Some(cache_key) = self.cache_key {
+            write!(output, ",\"cache_key\":\"{:016x}\"", cache_key)?;
+        }
+        output.write_all(b"}")
+    }
+}
+
+impl fmt::Display for TrimStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |part: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                part as f64 * 100.0 / total as f64
+            }
+        };
+
+        writeln!(
+            f,
+            "input:  {} bytes, {} lines, {} allocations and frees, {}ms long",
+            self.input_bytes, self.input_lines, self.input_events, self.profile_duration_ms
+        )?;
+        write!(
+            f,
+            "output: {} bytes ({:.1}%), {} allocations and frees ({:.1}%)",
+            self.output_bytes,
+            percent(self.output_bytes, self.input_bytes),
+            self.output_events,
+            percent(self.output_events, self.input_events)
+        )?;
+        if let Some(cache_key) = self.cache_key {
+            write!(f, "\ncache key: {:016x}", cache_key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `value` as a quoted JSON string.
+pub fn write_json_string(mut output: impl Write, value: &str) -> io::Result<()> {
+    output.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => write!(output, "\\{}", c)?,
+            c if c.is_control() => write!(output, "\\u{:04x}", c as u32)?,
+            c => write!(output, "{}", c)?,
+        }
+    }
+    output.write_all(b"\"")
+}
+
+/// Counts the bytes written through it.
+pub struct CountingWriter<W> {
+    pub inner: W,
+    pub bytes: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Counts and hashes the bytes written through it, to compare them with another copy.
+pub struct HashingWriter<W> {
+    pub inner: W,
+    pub bytes: u64,
+    pub hash: u64,
+}
+
+impl<W> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            bytes: 0,
+            hash: stable_id::INITIAL,
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        self.hash = stable_id::hash(self.hash, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_format() {
+    let stats = TrimStats {
+        input_bytes: 2000,
+        input_lines: 300,
+        output_bytes: 500,
+        input_events: 200,
+        output_events: 40,
+        profile_duration_ms: 12345,
+        allocation_index_correction: 7,
+        largest_written_allocation_index: 3,
+        cache_key: None,
+        report: None,
+    };
+
+    assert_eq!(
+        stats.to_string(),
+        "\
+input:  2000 bytes, 300 lines, 200 allocations and frees, 12345ms long
+output: 500 bytes (25.0%), 40 allocations and frees (20.0%)"
+    );
+
+    let mut json = Vec::new();
+    stats.write_json(&mut json).unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        "{\"input_bytes\":2000,\"input_lines\":300,\"output_bytes\":500,\"input_events\":200,\
+         \"output_events\":40,\"profile_duration_ms\":12345,\"allocation_index_correction\":7,\
+         \"largest_written_allocation_index\":3}"
+    );
+}
+
+#[test]
+fn test_format_cache_key() {
+    let stats = TrimStats {
+        cache_key: Some(0xabc),
+        ..TrimStats::default()
+    };
+    assert!(stats.to_string().ends_with("\ncache key: 0000000000000abc"));
+    let mut json = Vec::new();
+    stats.write_json(&mut json).unwrap();
+    assert!(String::from_utf8(json)
+        .unwrap()
+        .ends_with(",\"cache_key\":\"0000000000000abc\"}"));
+}
@@ -0,0 +1,185 @@
+//! Writing the strings of a profile in sorted order, for --sort-strings.
+//!
+//! heaptrack defines strings in "s" lines in the order the program first used them, which differs
+//! between runs of the same program. Sorting them by their bytes and writing all of them at the
+//! first "s" line makes the string table of two profiles of the same program comparable. This
+//! needs a first pass over the whole input to collect the strings, and keeps all of them in
+//! memory.
+//!
+//! "i" lines are the only ones that reference strings, their references are rewritten to the new
+//! numbers. Like heaptrack's, those are numbered from 1, 0 means none.
+
+use std::io::{self, BufRead, Write};
+
+use crate::{parse_hex, write_hex};
+
+pub struct StringOrder {
+    /// the text of every "s" line, sorted
+    sorted: Vec<Vec<u8>>,
+    /// the new number of every string, by its number in the input minus 1
+    new_numbers: Vec<u64>,
+}
+
+fn without_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// The first pass: collect and sort all strings. Equal strings keep their order.
+pub fn string_order(mut input: impl BufRead) -> io::Result<StringOrder> {
+    let mut strings = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if line[0] == b's' {
+            strings.push(without_newline(&line).get(2..).unwrap_or_default().to_vec());
+        }
+    }
+
+    // (text, index in the input)
+    let mut strings: Vec<_> = strings
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| (x, i))
+        .collect();
+    strings.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut new_numbers = vec![0; strings.len()];
+    for (new, (_, old)) in strings.iter().enumerate() {
+        new_numbers[*old] = new as u64 + 1;
+    }
+    let sorted = strings.into_iter().map(|(x, _)| x).collect();
+    Ok(StringOrder {
+        sorted,
+        new_numbers,
+    })
+}
+
+/// Rewrites a trimmed profile with the strings in the order of a `StringOrder`.
+pub struct SortStrings<'a, W> {
+    inner: W,
+    order: &'a StringOrder,
+    /// the current line, until it is complete
+    line: Vec<u8>,
+    is_written: bool,
+}
+
+impl<'a, W: Write> SortStrings<'a, W> {
+    pub fn new(inner: W, order: &'a StringOrder) -> Self {
+        SortStrings {
+            inner,
+            order,
+            line: Vec::new(),
+            is_written: false,
+        }
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        let line = &self.line;
+        let newline = &line[without_newline(line).len()..];
+        match line[0] {
+            b's' if self.is_written => {}
+            b's' => {
+                self.is_written = true;
+                for string in &self.order.sorted {
+                    self.inner.write_all(b"s ")?;
+                    self.inner.write_all(string)?;
+                    self.inner.write_all(newline)?;
+                }
+            }
+            b'i' => {
+                // "i <ip> <module> [<function> <file> <line>]...", all but the line are strings
+                let mut args = without_newline(line).split(|x| *x == b' ');
+                self.inner.write_all(args.next().unwrap_or_default())?;
+                for (position, arg) in args.enumerate() {
+                    self.inner.write_all(b" ")?;
+                    let is_string = position > 0 && (position == 1 || position % 3 != 1);
+                    let new_number = parse_hex(arg)
+                        .ok()
+                        .filter(|_| is_string)
+                        .and_then(|x| self.order.new_numbers.get((x as usize).wrapping_sub(1)));
+                    match new_number {
+                        Some(new_number) => write_hex(&mut self.inner, *new_number)?,
+                        // undefined strings and 0 stay as they are
+                        None => self.inner.write_all(arg)?,
+                    }
+                }
+                self.inner.write_all(newline)?;
+            }
+            _ => self.inner.write_all(line)?,
+        }
+        self.line.clear();
+        Ok(())
+    }
+
+    /// Write the last line if it did not end with a newline.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.line.is_empty() {
+            self.write_line()?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SortStrings<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.split_inclusive(|x| *x == b'\n') {
+            self.line.extend_from_slice(chunk);
+            if self.line.ends_with(b"\n") {
+                self.write_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_sort_strings() {
+    let profile = b"\
+v 10400 3
+s main
+s libc.so
+i 10 2 1 3 0
+s foo.c
+s libc.so
+t 1 0
+i 20 4 0 0 0 1 5 1
+c 1
+";
+    let order = string_order(&profile[..]).unwrap();
+    let mut output = SortStrings::new(Vec::new(), &order);
+    output.write_all(profile).unwrap();
+    let output = String::from_utf8(output.finish().unwrap()).unwrap();
+    // both libc.so stay, the first one first
+    assert_eq!(
+        output,
+        "\
+v 10400 3
+s foo.c
+s libc.so
+s libc.so
+s main
+i 10 2 4 1 0
+t 1 0
+i 20 3 0 0 0 4 5 1
+c 1
+"
+    );
+
+    // the new numbers have the same text as the old ones
+    let text = |output: &str, number: usize| {
+        let strings: Vec<_> = output.lines().filter(|x| x.starts_with("s ")).collect();
+        strings[number - 1].to_owned()
+    };
+    let input = String::from_utf8(profile.to_vec()).unwrap();
+    assert_eq!(text(&input, 1), text(&output, 4));
+    assert_eq!(text(&input, 3), text(&output, 1));
+    assert_eq!(text(&input, 4), text(&output, 3));
+}
@@ -0,0 +1,112 @@
+//! Helpers for string contents of profiles, which are usually but not necessarily UTF-8.
+
+use std::io::{self, BufRead};
+use std::str;
+
+/// Some editors put this in front of UTF-8 text, heaptrack never does.
+pub const BYTE_ORDER_MARK: &[u8] = b"\xef\xbb\xbf";
+
+/// Consume a byte order mark at the current position of `input`, if there is one.
+///
+/// The buffer of `input` can hold less than the whole mark, for example after a short read from
+/// a pipe, so it is matched piece by piece. What was consumed cannot be put back, so input that
+/// starts like a byte order mark but does not continue like one is an error, it is not a profile
+/// anyway.
+pub fn skip_byte_order_mark(mut input: impl BufRead) -> io::Result<()> {
+    let mut matched = 0;
+    while matched < BYTE_ORDER_MARK.len() {
+        let buf = input.fill_buf()?;
+        let rest = &BYTE_ORDER_MARK[matched..];
+        let len = buf.len().min(rest.len());
+        if len == 0 || buf[..len] != rest[..len] {
+            if matched == 0 {
+                return Ok(());
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the input starts with an incomplete byte order mark",
+            ));
+        }
+        input.consume(len);
+        matched += len;
+    }
+    Ok(())
+}
+
+/// Cut `input` to at most `max_len` bytes without splitting a UTF-8 character, so that the result
+/// is valid UTF-8 if the input was.
+///
+/// Input that is not UTF-8 is cut without splitting anything that looks like a character.
+pub fn truncate_utf8(input: &[u8], max_len: usize) -> &[u8] {
+    if input.len() <= max_len {
+        return input;
+    }
+
+    let head = &input[..max_len];
+    match str::from_utf8(head) {
+        Ok(_) => head,
+        // the cut is in the middle of a character
+        Err(e) if e.error_len().is_none() => &head[..e.valid_up_to()],
+        Err(_) => {
+            // back off over the continuation bytes of the character at the cut, there are at
+            // most 3 of them
+            let mut end = max_len;
+            while end > 0 && max_len - end < 3 && input[end] & 0xc0 == 0x80 {
+                end -= 1;
+            }
+            if input[end] & 0xc0 == 0x80 {
+                head
+            } else {
+                &input[..end]
+            }
+        }
+    }
+}
+
+#[test]
+fn test_skip_byte_order_mark() {
+    let mut input = &b"\xef\xbb\xbfv 10400 3\n"[..];
+    skip_byte_order_mark(&mut input).unwrap();
+    assert_eq!(input, b"v 10400 3\n");
+    skip_byte_order_mark(&mut input).unwrap();
+    assert_eq!(input, b"v 10400 3\n");
+
+    // a buffer too small for the whole mark
+    let mut input = io::BufReader::with_capacity(1, &b"\xef\xbb\xbfv 10400 3\n"[..]);
+    skip_byte_order_mark(&mut input).unwrap();
+    assert_eq!(input.fill_buf().unwrap(), b"v");
+    let mut input = io::BufReader::with_capacity(1, &b"v 10400 3\n"[..]);
+    skip_byte_order_mark(&mut input).unwrap();
+    assert_eq!(input.fill_buf().unwrap(), b"v");
+    let input = io::BufReader::with_capacity(1, &b"\xef\xbbv 10400 3\n"[..]);
+    assert!(skip_byte_order_mark(input).is_err());
+    assert!(skip_byte_order_mark(&b"\xef\xbb"[..]).is_err());
+
+    let crate::format::Version { file_format, .. } =
+        crate::format::read_version(&b"\xef\xbb\xbfv 10400 3\n"[..]).unwrap();
+    assert_eq!(file_format, 3);
+}
+
+#[test]
+fn test_truncate_utf8() {
+    let symbol = "Grüße::<€>".as_bytes();
+    assert_eq!(truncate_utf8(symbol, 100), symbol);
+    assert_eq!(truncate_utf8(symbol, 2), b"Gr");
+    // "ü" is 2 bytes, "€" is 3
+    assert_eq!(truncate_utf8(symbol, 3), b"Gr");
+    assert_eq!(truncate_utf8(symbol, 4), "Grü".as_bytes());
+    assert_eq!(truncate_utf8(symbol, 10), "Grüße::<".as_bytes());
+    assert_eq!(truncate_utf8(symbol, 11), "Grüße::<".as_bytes());
+    assert_eq!(truncate_utf8(symbol, 12), "Grüße::<".as_bytes());
+    assert_eq!(truncate_utf8(symbol, 13), "Grüße::<€".as_bytes());
+    assert_eq!(truncate_utf8("🦀".as_bytes(), 3), b"");
+
+    // invalid UTF-8 before the cut is kept, the character at the cut is still not split
+    let invalid = b"\xff ab\xc3\xbc";
+    assert_eq!(truncate_utf8(invalid, 5), b"\xff ab");
+    assert_eq!(truncate_utf8(invalid, 3), b"\xff a");
+    assert_eq!(
+        truncate_utf8(b"\xff\x80\x80\x80\x80", 4),
+        b"\xff\x80\x80\x80"
+    );
+}
@@ -0,0 +1,97 @@
+//! Keeping only the allocations of the traces that allocate the most, for --max-traces.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use crate::lifetime::AllocationFilter;
+use crate::pages;
+use crate::reader::{Command, HeaptrackReader};
+
+/// The traces that allocated the most bytes over the whole profile, at most `max_traces` of them,
+/// the most first. On ties, the trace defined first wins.
+fn top_traces(bytes_by_trace: &HashMap<u64, u64>, max_traces: usize) -> Vec<u64> {
+    let mut traces: Vec<_> = bytes_by_trace.iter().map(|(t, b)| (*t, *b)).collect();
+    traces.sort_by_key(|(trace, bytes)| (u64::MAX - bytes, *trace));
+    traces.truncate(max_traces);
+    traces.into_iter().map(|(trace, _)| trace).collect()
+}
+
+/// The first pass: sum up the bytes allocated by every trace, and keep the allocations of the
+/// `max_traces` traces with the most. Frees follow their allocation. With `page_size`, sizes are
+/// rounded up to whole pages first.
+pub fn top_traces_filter(
+    input: impl BufRead,
+    max_traces: usize,
+    page_size: Option<u64>,
+) -> io::Result<AllocationFilter> {
+    // (size, trace) by allocation index
+    let mut allocation_infos = Vec::new();
+    let mut bytes_by_trace = HashMap::<u64, u64>::new();
+    // trace of every allocation
+    let mut allocated_by = Vec::new();
+
+    for command in HeaptrackReader::new(input) {
+        match command? {
+            Command::AllocationInfo { size, trace } => {
+                let size = page_size.map_or(size, |x| pages::round_up(size, x));
+                allocation_infos.push((size, trace));
+            }
+            Command::Alloc(allocation_index) => {
+                let info = allocation_infos.get(allocation_index as usize).copied();
+                let (size, trace) = info.unwrap_or((0, 0));
+                *bytes_by_trace.entry(trace).or_default() += size;
+                allocated_by.push(trace);
+            }
+            _ => {}
+        }
+    }
+
+    let top = top_traces(&bytes_by_trace, max_traces);
+    Ok(AllocationFilter::new(
+        allocated_by.into_iter().map(|x| top.contains(&x)).collect(),
+    ))
+}
+
+#[test]
+fn test_top_traces() {
+    let bytes_by_trace = HashMap::from([(1, 100), (2, 300), (3, 100), (4, 200)]);
+    assert_eq!(top_traces(&bytes_by_trace, 0), Vec::<u64>::new());
+    assert_eq!(top_traces(&bytes_by_trace, 1), vec![2]);
+    assert_eq!(top_traces(&bytes_by_trace, 3), vec![2, 4, 1]);
+    assert_eq!(top_traces(&bytes_by_trace, 10), vec![2, 4, 1, 3]);
+}
+
+#[test]
+fn test_top_traces_filter() {
+    use crate::lifetime::Pairing;
+
+    // trace 1 allocates 0x30 bytes in 3 small allocations, trace 2 0x20 in one, trace 3 0x10
+    let profile = b"\
+a 10 1
+a 20 2
+a 10 3
++ 0
++ 1
++ 0
+- 0
++ 2
++ 0
+- 1
+";
+    let filter = top_traces_filter(&profile[..], 2, None).unwrap();
+    let mut pairing = Pairing::default();
+    let events = [
+        (b'+', 0),
+        (b'+', 1),
+        (b'+', 0),
+        (b'-', 0),
+        (b'+', 2),
+        (b'+', 0),
+        (b'-', 1),
+    ];
+    let kept: Vec<_> = events
+        .into_iter()
+        .map(|(instruction, index)| filter.keep(&mut pairing, instruction, index))
+        .collect();
+    assert_eq!(kept, [true, true, true, true, false, true, true]);
+}
@@ -0,0 +1,213 @@
+//! A read-only terminal view of a profile, for --tui. Only built with the `tui` feature.
+//!
+//! This does not use a terminal library: the screen is redrawn with ANSI escape codes, and
+//! commands are read line by line from the terminal, since stdin is the profile. The live heap is
+//! replayed once, the allocation sizes are counted again for every window that is shown, so the
+//! profile has to be a file that can be read several times, not a pipe.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::reader::{Command, HeaptrackReader};
+use crate::replay::{self, LiveSizes};
+use crate::rewind;
+
+/// Columns of the sparkline, and how many allocation sizes to list.
+const SPARKLINE_WIDTH: usize = 64;
+const TOP_SIZES: usize = 10;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The part of the profile on screen, in milliseconds since its start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub start: u64,
+    pub duration: u64,
+}
+
+impl Window {
+    fn end(self) -> u64 {
+        self.start.saturating_add(self.duration)
+    }
+
+    /// Apply a command typed by the user, staying within a profile of `profile_duration`.
+    /// Returns None to quit.
+    fn command(self, command: &str, profile_duration: u64) -> Option<Window> {
+        let step = (self.duration / 2).max(1);
+        let mut window = self;
+        match command.trim() {
+            "h" => window.start = window.start.saturating_sub(step),
+            "l" => window.start = window.start.saturating_add(step),
+            "+" => window.duration = step,
+            "-" => window.duration = window.duration.saturating_mul(2),
+            "q" => return None,
+            _ => {}
+        }
+        window.duration = window.duration.clamp(1, profile_duration.max(1));
+        window.start = window
+            .start
+            .min(profile_duration.saturating_sub(window.duration));
+        Some(window)
+    }
+}
+
+/// The live heap as a line of bars, the peak of every column.
+fn sparkline(samples: &[(u64, i64)]) -> String {
+    let columns = replay::downsample(samples, SPARKLINE_WIDTH);
+    let max = columns.iter().map(|(_, bytes)| *bytes).max().unwrap_or(0);
+    columns
+        .iter()
+        .map(|(_, bytes)| {
+            if max <= 0 {
+                return BARS[0];
+            }
+            let bar = (*bytes).max(0) as i128 * (BARS.len() - 1) as i128 / max as i128;
+            BARS[bar as usize]
+        })
+        .collect()
+}
+
+/// The allocation sizes with the most "+" lines in the window, as `(size, allocations)`.
+fn top_sizes(input: impl BufRead, window: Window) -> io::Result<Vec<(u64, u64)>> {
+    let mut sizes = Vec::new();
+    let mut counts = HashMap::<u64, u64>::new();
+    for command in HeaptrackReader::new(input).timed() {
+        match command? {
+            (_, Command::AllocationInfo { size, .. }) => sizes.push(size),
+            (timestamp, Command::Alloc(index))
+                if (window.start..=window.end()).contains(&timestamp) =>
+            {
+                let size = sizes.get(index as usize).copied().unwrap_or(0);
+                *counts.entry(size).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by_key(|(size, allocations)| (u64::MAX - allocations, *size));
+    counts.truncate(TOP_SIZES);
+    Ok(counts)
+}
+
+/// Draw one screen.
+pub fn render(
+    mut output: impl Write,
+    sizes: &LiveSizes,
+    window: Window,
+    top: &[(u64, u64)],
+) -> io::Result<()> {
+    let samples: Vec<_> = sizes
+        .samples
+        .iter()
+        .copied()
+        .filter(|(timestamp, _)| (window.start..=window.end()).contains(timestamp))
+        .collect();
+    let profile_duration = sizes.samples.last().map_or(0, |(timestamp, _)| *timestamp);
+    let peak = samples.iter().map(|(_, bytes)| *bytes).max().unwrap_or(0);
+
+    // clear the screen and move to the top left
+    output.write_all(b"\x1b[2J\x1b[H")?;
+    writeln!(
+        output,
+        "profile timestamps {} to {} of {} ms",
+        window.start,
+        window.end(),
+        profile_duration
+    )?;
+    writeln!(output)?;
+    writeln!(output, "live heap, peak {} bytes:", peak)?;
+    writeln!(output, "{}", sparkline(&samples))?;
+    writeln!(output)?;
+    writeln!(output, "{:>12} {:>12}", "size", "allocations")?;
+    for (size, allocations) in top {
+        writeln!(output, "{:>12} {:>12}", size, allocations)?;
+    }
+    writeln!(output)?;
+    writeln!(
+        output,
+        "h: earlier, l: later, +: zoom in, -: zoom out, q: quit, then enter"
+    )?;
+    output.flush()
+}
+
+/// Show the whole profile first, then follow the commands in `commands` until it ends or says
+/// to quit.
+pub fn run(
+    input: &mut BufReader<File>,
+    commands: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let sizes = replay::live_sizes(&mut *input)?;
+    let profile_duration = sizes.samples.last().map_or(0, |(timestamp, _)| *timestamp);
+    let mut window = Window {
+        start: 0,
+        duration: profile_duration.max(1),
+    };
+    let mut commands = commands.lines();
+    loop {
+        rewind(input)?;
+        let top = top_sizes(&mut *input, window)?;
+        render(&mut output, &sizes, window, &top)?;
+        let Some(command) = commands.next().transpose()? else {
+            return Ok(());
+        };
+        match window.command(&command, profile_duration) {
+            Some(next) => window = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_render() {
+    let profile = b"\
+v 10400 3
+c 1
+a 10 1
+a 20 1
++ 0
++ 0
+c 3e8
++ 1
+c 7d0
+- 0
+- 0
+c bb8
+";
+    let sizes = replay::live_sizes(&profile[..]).unwrap();
+    let window = Window {
+        start: 0,
+        duration: 3000,
+    };
+    let top = top_sizes(&profile[..], window).unwrap();
+    assert_eq!(top, vec![(0x10, 2), (0x20, 1)]);
+
+    let mut screen = Vec::new();
+    render(&mut screen, &sizes, window, &top).unwrap();
+    let screen = String::from_utf8(screen).unwrap();
+    assert!(screen.contains("profile timestamps 0 to 3000 of 3000 ms\n"));
+    assert!(screen.contains("peak 64 bytes"));
+    assert!(screen.contains("▁▄█▄\n"), "{}", screen);
+
+    // scrubbing stays within the profile
+    let window = window.command("+", 3000).unwrap();
+    assert_eq!(
+        window,
+        Window {
+            start: 0,
+            duration: 1500
+        }
+    );
+    let window = window.command("l", 3000).unwrap();
+    let window = window.command("l", 3000).unwrap();
+    let window = window.command("l", 3000).unwrap();
+    assert_eq!(
+        window,
+        Window {
+            start: 1500,
+            duration: 1500
+        }
+    );
+    assert_eq!(window.command("q", 3000), None);
+}
@@ -0,0 +1,240 @@
+//! Writing the output to a URL, for --output-url. Only built with the `upload` feature.
+//!
+//! This does not use an HTTP library: `http://` URLs get a PUT request with the output as a
+//! chunked body, so it is streamed without knowing its size in advance, and the upload only
+//! succeeds if the server answers with a 2xx status. If the environment variable
+//! `HEAPTRACK_TRIM_AUTHORIZATION` is set, it is sent as the `Authorization` header, credentials
+//! in the URL itself are not supported, so they do not show up in the process list.
+//!
+//! Without TLS, that header goes over the network in cleartext, readable by anyone on the way to
+//! the server. Unless `quiet`, sending it to a host that is not loopback prints a warning.
+//!
+//! There is no TLS or S3 client, so `https://` and `s3://` URLs are rejected. Those uploads
+//! can go through --pipe-to instead, for example `--pipe-to "aws s3 cp - s3://bucket/key"`,
+//! which also handles the credentials. `file://` URLs and anything without a scheme are paths
+//! to write to, `-` is stdout.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::TcpStream;
+
+const AUTHORIZATION_VARIABLE: &str = "HEAPTRACK_TRIM_AUTHORIZATION";
+
+pub enum Output {
+    Stdout(BufWriter<io::Stdout>),
+    File(BufWriter<File>),
+    Http(BufWriter<HttpUpload>),
+}
+
+/// Open the destination of `url`, with a write buffer of `buf_size` bytes, which is also the size
+/// of the chunks of an upload.
+pub fn open(url: &str, buf_size: usize, quiet: bool) -> io::Result<Output> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_owned());
+    if let Some(rest) = url.strip_prefix("http://") {
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() || authority.contains('@') {
+            return Err(invalid(
+                "expected http://host[:port]/path, without credentials",
+            ));
+        }
+        let authorization = env::var(AUTHORIZATION_VARIABLE).ok();
+        let upload = HttpUpload::new(authority, path, authorization.as_deref(), quiet)?;
+        Ok(Output::Http(BufWriter::with_capacity(buf_size, upload)))
+    } else if url.starts_with("https://") || url.starts_with("s3://") {
+        Err(invalid(
+            "https:// and s3:// are not supported, upload with --pipe-to, for example \
+             --pipe-to \"curl -T - URL\" or --pipe-to \"aws s3 cp - URL\"",
+        ))
+    } else if url == "-" {
+        Ok(Output::Stdout(BufWriter::with_capacity(
+            buf_size,
+            io::stdout(),
+        )))
+    } else {
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        let file = File::create(path)?;
+        Ok(Output::File(BufWriter::with_capacity(buf_size, file)))
+    }
+}
+
+impl Output {
+    /// Flush everything, and for uploads, wait for the response of the server.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut output) => output.flush(),
+            Output::File(mut output) => output.flush(),
+            Output::Http(output) => output.into_inner().map_err(|e| e.into_error())?.finish(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(output) => output.write(buf),
+            Output::File(output) => output.write(buf),
+            Output::Http(output) => output.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(output) => output.flush(),
+            Output::File(output) => output.flush(),
+            Output::Http(output) => output.flush(),
+        }
+    }
+}
+
+/// A PUT request. Every write is sent as one chunk.
+pub struct HttpUpload {
+    stream: TcpStream,
+}
+
+impl HttpUpload {
+    /// Connect to `authority`, `host` or `host:port`, and send the request header.
+    fn new(
+        authority: &str,
+        path: &str,
+        authorization: Option<&str>,
+        quiet: bool,
+    ) -> io::Result<Self> {
+        let address = if authority.contains(':') {
+            authority.to_owned()
+        } else {
+            format!("{}:80", authority)
+        };
+        let mut stream = TcpStream::connect(address)?;
+        if authorization.is_some() && !quiet && !stream.peer_addr()?.ip().is_loopback() {
+            eprintln!(
+                "WARNING: {} is sent to {} in cleartext, anyone on the network in between can \
+                 read it",
+                AUTHORIZATION_VARIABLE, authority
+            );
+        }
+        let mut header = format!(
+            "PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/octet-stream\r\n\
+             Transfer-Encoding: chunked\r\nConnection: close\r\n",
+            path, authority
+        );
+        if let Some(authorization) = authorization {
+            header.push_str(&format!("Authorization: {}\r\n", authorization));
+        }
+        header.push_str("\r\n");
+        stream.write_all(header.as_bytes())?;
+        Ok(HttpUpload { stream })
+    }
+
+    /// End the body and check the status of the response.
+    fn finish(mut self) -> io::Result<()> {
+        self.stream.write_all(b"0\r\n\r\n")?;
+        self.stream.flush()?;
+        let mut status_line = String::new();
+        BufReader::new(&self.stream).read_line(&mut status_line)?;
+        let status_line = status_line.trim_end();
+        match status_line.split(' ').nth(1) {
+            Some(status) if status.starts_with('2') && status.len() == 3 => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "upload failed, the server responded {:?}",
+                status_line
+            ))),
+        }
+    }
+}
+
+impl Write for HttpUpload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // an empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.stream, "{:x}\r\n", buf.len())?;
+        self.stream.write_all(buf)?;
+        self.stream.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Accept one request and answer it with `status_line`, returning the request line with the
+/// headers, and the decoded body.
+#[cfg(test)]
+fn mock_server(status_line: &'static str) -> (String, std::thread::JoinHandle<(String, Vec<u8>)>) {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut header = String::new();
+        while !header.ends_with("\r\n\r\n") {
+            reader.read_line(&mut header).unwrap();
+        }
+        let mut body = Vec::new();
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).unwrap();
+            let size = usize::from_str_radix(size.trim_end(), 16).unwrap();
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).unwrap();
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+        (&stream).write_all(status_line.as_bytes()).unwrap();
+        (header, body)
+    });
+    (address, server)
+}
+
+#[test]
+fn test_http_upload() {
+    let (address, server) = mock_server("HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n");
+    // small chunks, to send several
+    let mut output = open(&format!("http://{}/profiles/trimmed", address), 4, false).unwrap();
+    output.write_all(b"v 10400 3\n").unwrap();
+    output.write_all(b"c 1\n").unwrap();
+    output.finish().unwrap();
+
+    let (header, body) = server.join().unwrap();
+    assert!(header.starts_with("PUT /profiles/trimmed HTTP/1.1\r\n"));
+    assert!(header.contains(&format!("\r\nHost: {}\r\n", address)));
+    assert!(header.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert_eq!(body, b"v 10400 3\nc 1\n");
+}
+
+#[test]
+fn test_http_upload_rejected() {
+    let (address, server) = mock_server("HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+    let mut output = open(&format!("http://{}", address), 1024, false).unwrap();
+    output.write_all(b"v 10400 3\n").unwrap();
+    let error = output.finish().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "upload failed, the server responded \"HTTP/1.1 403 Forbidden\""
+    );
+    let (header, _) = server.join().unwrap();
+    assert!(header.starts_with("PUT / HTTP/1.1\r\n"));
+}
+
+#[test]
+fn test_unsupported_urls() {
+    for url in [
+        "s3://bucket/key",
+        "https://example.com/x",
+        "http://user:pw@localhost/x",
+    ] {
+        let error = open(url, 1024, false).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", url);
+    }
+}
@@ -0,0 +1,640 @@
+//! Structural checks of a profile, without trimming anything.
+//!
+//! With --validate-monotonic-rss, the "R" lines are checked too. heaptrack writes one after every
+//! "c" line, with the resident set size in hex, so each one should be parseable and have a newer
+//! timestamp than the one before it. Two samples without a "c" line between them, or one before
+//! the first "c" line, cannot be placed on the timeline of heaptrack-gui's graph.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::parse_hex;
+use crate::warnings::{Warning, Warnings};
+
+/// Counts the strings, instruction pointers, traces and allocation infos defined so far, to find
+/// references to ones that are not defined (yet). heaptrack-gui requires every definition to
+/// come before its first use. Lines with missing or unparseable arguments are malformed rather
+/// than undefined references.
+#[derive(Default)]
+pub struct Definitions {
+    strings: u64,
+    instruction_pointers: u64,
+    traces: u64,
+    allocation_infos: u64,
+}
+
+impl Definitions {
+    pub fn check(&mut self, line: &[u8]) -> Result<(), Warning> {
+        let mut args = line
+            .trim_ascii_end()
+            .split(|x| *x == b' ')
+            .skip(1)
+            .map(parse_hex);
+        let mut is_malformed = false;
+        let mut arg = || match args.next() {
+            Some(Ok(value)) => Some(value),
+            _ => {
+                is_malformed = true;
+                None
+            }
+        };
+        // strings, instruction pointers and traces are numbered from 1, 0 means none
+        let defined = |index: Option<u64>, count: u64| index.is_some_and(|x| x <= count);
+
+        // definitions still count if they have broken references, to not report every line after
+        let ok = match line[0] {
+            b's' => {
+                self.strings += 1;
+                true
+            }
+            b'i' => {
+                let _ip = arg();
+                // "i <ip> <module> [<function> <file> <line>]...", all but the line are strings
+                let mut ok = true;
+                for (position, value) in args.by_ref().enumerate() {
+                    let Ok(value) = value else {
+                        is_malformed = true;
+                        continue;
+                    };
+                    let is_line = position > 0 && position % 3 == 0;
+                    ok &= is_line || defined(Some(value), self.strings);
+                }
+                self.instruction_pointers += 1;
+                ok
+            }
+            b't' => {
+                let ok = defined(arg(), self.instruction_pointers) && defined(arg(), self.traces);
+                self.traces += 1;
+                ok
+            }
+            b'a' => {
+                let _size = arg();
+                let ok = defined(arg(), self.traces);
+                self.allocation_infos += 1;
+                ok
+            }
+            b'+' | b'-' => {
+                // allocation infos are numbered from 0
+                if arg().is_some_and(|x| x >= self.allocation_infos) {
+                    return Err(Warning::UndefinedAllocationInfo);
+                }
+                true
+            }
+            _ => true,
+        };
+
+        if is_malformed {
+            return Err(Warning::MalformedLine);
+        }
+        if !ok {
+            return Err(Warning::UndefinedReference);
+        }
+        Ok(())
+    }
+}
+
+/// Check the profile for corruption, reporting everything found to `warnings`. `check_rss` also
+/// checks the "R" lines.
+pub fn verify(mut input: impl BufRead, check_rss: bool, warnings: &mut Warnings) -> io::Result<()> {
+    // heaptrack writes one "a" line per distinct size and trace, so seeing the same one twice
+    // means the allocations using the second index are attributed to the wrong index.
+    let mut allocation_infos = HashSet::<Vec<u8>>::new();
+    let mut definitions = Definitions::default();
+    let mut timestamp = None;
+    let mut is_version_seen = false;
+    // of the last "R" line
+    let mut rss_timestamp = None;
+    let mut lineno = 0u64;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        lineno += 1;
+
+        if let Err(warning) = definitions.check(&line) {
+            warnings.warn(warning, lineno, &line);
+        }
+
+        let trimmed = line.trim_ascii_end();
+        match line[0] {
+            b'a' if !allocation_infos.insert(trimmed[1..].to_vec()) => {
+                warnings.warn(Warning::DuplicateAllocationInfo, lineno, &line);
+            }
+            b'c' => match trimmed.split(|x| *x == b' ').nth(1).map(parse_hex) {
+                Some(Ok(new_timestamp)) if Some(new_timestamp) >= timestamp => {
+                    timestamp = Some(new_timestamp)
+                }
+                _ => warnings.warn(Warning::BackwardsTimestamp, lineno, &line),
+            },
+            b'v' => {
+                if is_version_seen {
+                    warnings.warn(Warning::IndexReset, lineno, &line);
+                }
+                is_version_seen = true;
+            }
+            b'R' if check_rss => {
+                if !matches!(
+                    trimmed.split(|x| *x == b' ').nth(1).map(parse_hex),
+                    Some(Ok(_))
+                ) {
+                    warnings.warn(Warning::MalformedRss, lineno, &line);
+                }
+                if timestamp.is_none() || rss_timestamp == timestamp {
+                    warnings.warn(Warning::UntimedRss, lineno, &line);
+                }
+                rss_timestamp = timestamp;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that every line written through it only references definitions that were written
+/// before it, failing the write otherwise.
+pub struct CheckOrdering<W> {
+    inner: W,
+    definitions: Definitions,
+    line: Vec<u8>,
+    lineno: u64,
+}
+
+impl<W: Write> CheckOrdering<W> {
+    pub fn new(inner: W) -> Self {
+        CheckOrdering {
+            inner,
+            definitions: Definitions::default(),
+            line: Vec::new(),
+            lineno: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for CheckOrdering<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.split_inclusive(|x| *x == b'\n') {
+            self.line.extend_from_slice(chunk);
+            if !self.line.ends_with(b"\n") {
+                continue;
+            }
+
+            self.lineno += 1;
+            if let Err(warning) = self.definitions.check(&self.line) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "line {} of the output: {}: {:?}",
+                        self.lineno,
+                        warning,
+                        String::from_utf8_lossy(self.line.trim_ascii_end())
+                    ),
+                ));
+            }
+            self.line.clear();
+        }
+
+        self.inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reference that `HeaderCompleteness` found no definition for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DanglingReference {
+    /// the line of the output with the reference
+    pub lineno: u64,
+    /// "string", "instruction pointer", "trace" or "allocation info"
+    pub kind: &'static str,
+    pub index: u64,
+}
+
+impl fmt::Display for DanglingReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {} of the output references undefined {} {:x}",
+            self.lineno, self.kind, self.index
+        )
+    }
+}
+
+/// Collects what the lines written through it define and reference, to find the strings,
+/// instruction pointers, traces and allocation infos that the allocations in the output need,
+/// but that are not defined anywhere in it. Unlike `CheckOrdering`, definitions may come after
+/// their use, everything is reported instead of only the first problem, and definitions that no
+/// allocation needs are not checked at all.
+pub struct HeaderCompleteness<W> {
+    inner: W,
+    line: Vec<u8>,
+    lineno: u64,
+    strings: u64,
+    /// per instruction pointer, its line and the strings it references
+    instruction_pointers: Vec<(u64, Vec<u64>)>,
+    /// per trace, its line, instruction pointer and parent trace
+    traces: Vec<(u64, u64, u64)>,
+    /// per allocation info, its line and trace
+    allocation_infos: Vec<(u64, u64)>,
+    /// allocation infos referenced by "+" and "-" lines, with the first line referencing them
+    used_allocation_infos: BTreeMap<u64, u64>,
+}
+
+impl<W: Write> HeaderCompleteness<W> {
+    pub fn new(inner: W) -> Self {
+        HeaderCompleteness {
+            inner,
+            line: Vec::new(),
+            lineno: 0,
+            strings: 0,
+            instruction_pointers: Vec::new(),
+            traces: Vec::new(),
+            allocation_infos: Vec::new(),
+            used_allocation_infos: BTreeMap::new(),
+        }
+    }
+
+    fn add_line(&mut self) {
+        self.lineno += 1;
+        let mut args = self
+            .line
+            .trim_ascii_end()
+            .split(|x| *x == b' ')
+            .skip(1)
+            .map(|x| parse_hex(x).unwrap_or(u64::MAX));
+        let mut arg = || args.next().unwrap_or(u64::MAX);
+        match self.line[0] {
+            b's' => self.strings += 1,
+            b'i' => {
+                let _ip = arg();
+                // like in `Definitions::check`, all but the line numbers are strings
+                let strings = args
+                    .enumerate()
+                    .filter(|(position, _)| *position == 0 || position % 3 != 0)
+                    .map(|(_, string)| string)
+                    .collect();
+                self.instruction_pointers.push((self.lineno, strings));
+            }
+            b't' => {
+                let ip = arg();
+                let parent = arg();
+                self.traces.push((self.lineno, ip, parent));
+            }
+            b'a' => {
+                let _size = arg();
+                let trace = arg();
+                self.allocation_infos.push((self.lineno, trace));
+            }
+            b'+' | b'-' => {
+                let index = arg();
+                self.used_allocation_infos
+                    .entry(index)
+                    .or_insert(self.lineno);
+            }
+            _ => {}
+        }
+    }
+
+    /// All references needed by the allocations in the output that are not defined, ordered by
+    /// the allocation info that needs them.
+    pub fn dangling_references(&self) -> Vec<DanglingReference> {
+        let mut dangling = Vec::new();
+        let mut checked_traces = HashSet::new();
+        let mut checked_instruction_pointers = HashSet::new();
+
+        for (&index, &lineno) in &self.used_allocation_infos {
+            let Some(&(lineno, mut trace)) = self.allocation_infos.get(index as usize) else {
+                dangling.push(DanglingReference {
+                    lineno,
+                    kind: "allocation info",
+                    index,
+                });
+                continue;
+            };
+
+            // walk up to the root, strings, instruction pointers and traces are numbered from 1
+            let mut referenced_in = lineno;
+            while trace != 0 && checked_traces.insert(trace) {
+                let Some(&(trace_lineno, ip, parent)) = self.traces.get(trace as usize - 1) else {
+                    dangling.push(DanglingReference {
+                        lineno: referenced_in,
+                        kind: "trace",
+                        index: trace,
+                    });
+                    break;
+                };
+
+                if checked_instruction_pointers.insert(ip) {
+                    match self.instruction_pointers.get((ip as usize).wrapping_sub(1)) {
+                        Some((ip_lineno, strings)) => {
+                            for &string in strings {
+                                if string == 0 || string <= self.strings {
+                                    continue;
+                                }
+                                dangling.push(DanglingReference {
+                                    lineno: *ip_lineno,
+                                    kind: "string",
+                                    index: string,
+                                });
+                            }
+                        }
+                        None => dangling.push(DanglingReference {
+                            lineno: trace_lineno,
+                            kind: "instruction pointer",
+                            index: ip,
+                        }),
+                    }
+                }
+
+                referenced_in = trace_lineno;
+                trace = parent;
+            }
+        }
+
+        dangling
+    }
+
+    /// Fail if there are any dangling references, listing all of them.
+    pub fn finish(self) -> io::Result<W> {
+        let dangling = self.dangling_references();
+        if dangling.is_empty() {
+            return Ok(self.inner);
+        }
+        let mut message = format!("{} dangling references in the output:", dangling.len());
+        for reference in &dangling {
+            message.push_str(&format!("\n  {}", reference));
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+}
+
+impl<W: Write> Write for HeaderCompleteness<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.split_inclusive(|x| *x == b'\n') {
+            self.line.extend_from_slice(chunk);
+            if !self.line.ends_with(b"\n") {
+                continue;
+            }
+            self.add_line();
+            self.line.clear();
+        }
+
+        self.inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_duplicate_allocation_info() {
+    use crate::warnings::Verbosity;
+
+    let mut warnings = Warnings::new(Verbosity::Normal);
+    verify(
+        &b"\
+v 10400 3
+s main
+i 1234 1
+t 1 0
+t 1 1
+a 10 1
++ 0
+a 20 1
++ 1
+a 10 1
++ 2
+a 10 2
++ 3
+"[..],
+        false,
+        &mut warnings,
+    )
+    .unwrap();
+
+    let mut summary = Vec::new();
+    warnings.write_summary(&mut summary).unwrap();
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "warnings:\n  1x duplicate allocation info, first in line 10: \"a 10 1\"\n"
+    );
+}
+
+#[test]
+fn test_valid_profile() {
+    use crate::warnings::Verbosity;
+
+    let mut warnings = Warnings::new(Verbosity::Normal);
+    verify(
+        &b"v 10400 3\ns main\ni 1234 1\nt 1 0\nc 1\na 10 1\n+ 0\nc 2\n- 0\n"[..],
+        false,
+        &mut warnings,
+    )
+    .unwrap();
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn test_forward_references() {
+    use crate::warnings::Verbosity;
+
+    let mut warnings = Warnings::new(Verbosity::Normal);
+    verify(
+        &b"\
+v 10400 3
+s main
+i 1234 1 1 1 2
+t 1 0
+t 2 0
+a 10 3
++ 1
+"[..],
+        false,
+        &mut warnings,
+    )
+    .unwrap();
+
+    let mut summary = Vec::new();
+    warnings.write_summary(&mut summary).unwrap();
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "\
+warnings:
+  1x reference to undefined allocation info, first in line 7: \"+ 1\"
+  2x reference to undefined string, instruction pointer or trace, first in line 5: \"t 2 0\"
+"
+    );
+}
+
+#[test]
+fn test_malformed_references() {
+    use crate::warnings::Verbosity;
+
+    // unparseable and missing arguments, as opposed to ones that are not defined
+    let mut warnings = Warnings::new(Verbosity::Normal);
+    verify(
+        &b"\
+v 10400 3
+s main
+i 1234 1
+t 1 0
+a 10
++ zz
+-
+i 1234 zz
+"[..],
+        true,
+        &mut warnings,
+    )
+    .unwrap();
+
+    let mut summary = Vec::new();
+    warnings.write_summary(&mut summary).unwrap();
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "warnings:\n  4x malformed line, first in line 5: \"a 10\"\n"
+    );
+}
+
+#[test]
+fn test_check_ordering() {
+    let mut output = CheckOrdering::new(Vec::new());
+    // writes do not have to be whole lines
+    output.write_all(b"s main\ni 1234 1\nt 1").unwrap();
+    output.write_all(b" 0\na 10 1\n+ 0\n").unwrap();
+
+    // as if a filter dropped the string that the instruction pointer refers to
+    let mut output = CheckOrdering::new(Vec::new());
+    let error = output.write_all(b"s main\ni 1234 2\n").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "line 2 of the output: reference to undefined string, instruction pointer or trace: \
+         \"i 1234 2\""
+    );
+}
+
+#[test]
+fn test_header_completeness() {
+    let check = |profile: &[u8]| {
+        let mut output = HeaderCompleteness::new(Vec::new());
+        output.write_all(profile).unwrap();
+        output.dangling_references()
+    };
+
+    // definitions may come after their use, and unused ones may be broken
+    assert_eq!(
+        check(b"s main\na 10 1\nt 1 0\ni 1234 1\n+ 0\ni 99 5\nt 9 9\n"),
+        vec![]
+    );
+
+    // as if a garbage collection of strings had dropped one that an allocation still needs
+    let gc_bug = b"\
+s main
+i 1234 1 2 1 7
+t 1 0
+t 1 1
+a 10 2
++ 0
++ 1
+";
+    assert_eq!(
+        check(gc_bug),
+        vec![
+            DanglingReference {
+                lineno: 2,
+                kind: "string",
+                index: 2
+            },
+            DanglingReference {
+                lineno: 7,
+                kind: "allocation info",
+                index: 1
+            },
+        ]
+    );
+
+    let mut output = HeaderCompleteness::new(Vec::new());
+    output.write_all(b"a 10 3\n+ 0\n").unwrap();
+    assert_eq!(
+        output.finish().unwrap_err().to_string(),
+        "1 dangling references in the output:\n  line 1 of the output references undefined \
+         trace 3"
+    );
+}
+
+#[test]
+fn test_index_reset() {
+    use crate::warnings::Verbosity;
+
+    // two profiles concatenated, the "+ 0" of the second one means its own first allocation info
+    let mut warnings = Warnings::new(Verbosity::Normal);
+    verify(
+        &b"v 10400 3\nc 1\na 8 0\n+ 0\nc 2\nv 10400 3\nc 1\na 10 0\n+ 0\n"[..],
+        false,
+        &mut warnings,
+    )
+    .unwrap();
+    let mut summary = Vec::new();
+    warnings.write_summary(&mut summary).unwrap();
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "\
+warnings:
+  1x timestamp went backwards, first in line 7: \"c 1\"
+  1x second profile header, allocation indices restart at 0, first in line 6: \"v 10400 3\"
+"
+    );
+}
+
+#[test]
+fn test_rss() {
+    use crate::warnings::Verbosity;
+
+    let profile = b"\
+v 10400 3
+R 1000
+c 1
+R 2000
+c 2
+R -5
+c 3
+R 3000
+R 3000
+c 2
+R 4000
+c 4
+R 5000
+";
+    let mut warnings = Warnings::new(Verbosity::Normal);
+    verify(&profile[..], true, &mut warnings).unwrap();
+    let mut summary = Vec::new();
+    warnings.write_summary(&mut summary).unwrap();
+    // the backwards timestamp leaves the sample after it without a timestamp of its own
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "\
+warnings:
+  1x timestamp went backwards, first in line 10: \"c 2\"
+  1x unparseable resident set size, first in line 6: \"R -5\"
+  3x resident set size without a new timestamp, first in line 2: \"R 1000\"
+"
+    );
+
+    let mut warnings = Warnings::new(Verbosity::Normal);
+    verify(&profile[..], false, &mut warnings).unwrap();
+    let mut summary = Vec::new();
+    warnings.write_summary(&mut summary).unwrap();
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "warnings:\n  1x timestamp went backwards, first in line 10: \"c 2\"\n"
+    );
+}
@@ -0,0 +1,174 @@
+//! Collecting problems with the input that are not bad enough to stop trimming.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::clock::Throttle;
+use crate::text::truncate_utf8;
+
+/// Lines quoted in warnings are cut to this many bytes, some symbols are kilobytes long.
+const MAX_QUOTED_LINE: usize = 200;
+
+/// `line` without the trailing newline, for quoting it in a message.
+pub fn quote_line(line: &[u8]) -> String {
+    let line = line.trim_ascii_end();
+    let truncated = truncate_utf8(line, MAX_QUOTED_LINE);
+    let mut quoted = String::from_utf8_lossy(truncated).into_owned();
+    if truncated.len() < line.len() {
+        quoted.push_str("...");
+    }
+    quoted
+}
+
+/// How much to print to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    /// only errors
+    Quiet,
+    /// progress messages and a summary of warnings at the end
+    #[default]
+    Normal,
+    /// additionally, every warning as it happens
+    Verbose,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Warning {
+    UnknownCommand,
+    VersionMismatch,
+    BackwardsTimestamp,
+    /// a "v" line that is not the first one, as in profiles that were concatenated or rotated
+    /// into one file. heaptrack numbers allocation infos by the "a" lines since the start of the
+    /// file, so after this, "+" and "-" lines mean different allocation infos than the ones
+    /// they get attributed to.
+    IndexReset,
+    MalformedRss,
+    UntimedRss,
+    UndefinedAllocationInfo,
+    DuplicateAllocationInfo,
+    UndefinedReference,
+    MalformedLine,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Warning::UnknownCommand => "unknown command",
+            Warning::VersionMismatch => "unsupported file format version",
+            Warning::BackwardsTimestamp => "timestamp went backwards",
+            Warning::IndexReset => "second profile header, allocation indices restart at 0",
+            Warning::MalformedRss => "unparseable resident set size",
+            Warning::UntimedRss => "resident set size without a new timestamp",
+            Warning::UndefinedAllocationInfo => "reference to undefined allocation info",
+            Warning::DuplicateAllocationInfo => "duplicate allocation info",
+            Warning::UndefinedReference => {
+                "reference to undefined string, instruction pointer or trace"
+            }
+            Warning::MalformedLine => "malformed line",
+        })
+    }
+}
+
+struct Occurrences {
+    count: u64,
+    first_lineno: u64,
+    first_line: String,
+}
+
+pub struct Warnings {
+    verbosity: Verbosity,
+    occurrences: BTreeMap<Warning, Occurrences>,
+    /// limits how often --verbose prints warnings
+    throttle: Option<Throttle>,
+    suppressed: u64,
+}
+
+impl Warnings {
+    pub fn new(verbosity: Verbosity) -> Self {
+        Warnings {
+            verbosity,
+            occurrences: BTreeMap::new(),
+            throttle: None,
+            suppressed: 0,
+        }
+    }
+
+    pub fn throttled(self, throttle: Throttle) -> Self {
+        Warnings {
+            throttle: Some(throttle),
+            ..self
+        }
+    }
+
+    /// `lineno` is 1-based, `line` may include the trailing newline.
+    pub fn warn(&mut self, warning: Warning, lineno: u64, line: &[u8]) {
+        let line = || quote_line(line);
+        if self.verbosity == Verbosity::Verbose {
+            if self.throttle.as_mut().is_none_or(|x| x.ready()) {
+                eprintln!("warning: {} in line {}: {:?}", warning, lineno, line());
+                if self.suppressed > 0 {
+                    eprintln!("({} more warnings since the last one)", self.suppressed);
+                    self.suppressed = 0;
+                }
+            } else {
+                self.suppressed += 1;
+            }
+        }
+
+        self.occurrences
+            .entry(warning)
+            .and_modify(|x| x.count += 1)
+            .or_insert_with(|| Occurrences {
+                count: 1,
+                first_lineno: lineno,
+                first_line: line(),
+            });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.occurrences.is_empty()
+    }
+
+    /// One line per kind of warning, with the first line it occurred on.
+    pub fn write_summary(&self, mut output: impl Write) -> io::Result<()> {
+        if self.verbosity == Verbosity::Quiet || self.occurrences.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(output, "warnings:")?;
+        for (warning, occurrences) in &self.occurrences {
+            writeln!(
+                output,
+                "  {}x {}, first in line {}: {:?}",
+                occurrences.count, warning, occurrences.first_lineno, occurrences.first_line
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_summary() {
+    let mut warnings = Warnings::new(Verbosity::Normal);
+    warnings.warn(Warning::BackwardsTimestamp, 10, b"c 5\n");
+    warnings.warn(Warning::UnknownCommand, 3, b"Q foo\n");
+    warnings.warn(Warning::BackwardsTimestamp, 20, b"c 6\n");
+
+    let mut summary = Vec::new();
+    warnings.write_summary(&mut summary).unwrap();
+    assert_eq!(
+        String::from_utf8(summary).unwrap(),
+        "\
+warnings:
+  1x unknown command, first in line 3: \"Q foo\"
+  2x timestamp went backwards, first in line 10: \"c 5\"
+"
+    );
+
+    let mut quiet = Warnings::new(Verbosity::Quiet);
+    quiet.warn(Warning::UnknownCommand, 3, b"Q foo\n");
+    let mut summary = Vec::new();
+    quiet.write_summary(&mut summary).unwrap();
+    assert!(summary.is_empty());
+}
@@ -0,0 +1,105 @@
+//! Reading a profile while heaptrack is still writing it, for --watch.
+//!
+//! A file has no way to tell that its writer is done, so the input counts as complete once it has
+//! not grown for a while.
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+/// How long to wait before checking whether the input has grown.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads `inner` like `tail -f` does, only reporting the end of the input once it has not grown
+/// for `idle_timeout_ms`.
+pub struct Follow<R, F> {
+    inner: R,
+    clock: Box<dyn Clock>,
+    idle_timeout_ms: u64,
+    last_data_ms: u64,
+    /// called before waiting for more data, so that everything so far can be flushed
+    on_idle: F,
+    poll_interval: Duration,
+}
+
+impl<R: Read, F: FnMut() -> io::Result<()>> Follow<R, F> {
+    pub fn new(inner: R, clock: Box<dyn Clock>, idle_timeout_ms: u64, on_idle: F) -> Self {
+        Follow {
+            inner,
+            last_data_ms: clock.now_ms(),
+            clock,
+            idle_timeout_ms,
+            on_idle,
+            poll_interval: POLL_INTERVAL,
+        }
+    }
+}
+
+impl<R: Read, F: FnMut() -> io::Result<()>> Read for Follow<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.inner.read(buf)?;
+            let now = self.clock.now_ms();
+            if read > 0 || buf.is_empty() {
+                self.last_data_ms = now;
+                return Ok(read);
+            }
+            if now.saturating_sub(self.last_data_ms) >= self.idle_timeout_ms {
+                return Ok(0);
+            }
+            (self.on_idle)()?;
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+/// Lets the output be written by the trim and flushed by `Follow` at the same time.
+pub struct Shared<'a, W>(pub &'a RefCell<W>);
+
+impl<W: Write> Write for Shared<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+#[test]
+fn test_follow() {
+    use crate::clock::MockClock;
+    use std::collections::VecDeque;
+    use std::io::{BufRead, BufReader};
+
+    struct File<'a>(&'a RefCell<VecDeque<u8>>);
+    impl Read for File<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.borrow_mut().read(buf)
+        }
+    }
+
+    // a file that heaptrack appends to whenever we wait for it, starting in the middle of a line
+    let file = RefCell::new(VecDeque::from(b"v 10400 3\nc 1\n+ ".to_vec()));
+    let mut appends = VecDeque::from([&b"0\n"[..], b"", b"c 2\n- 0\n"]);
+    let clock = MockClock::default();
+    let mut idle_calls = 0;
+
+    let mut follow = Follow::new(File(&file), Box::new(clock.clone()), 1000, || {
+        idle_calls += 1;
+        clock.advance(600);
+        if let Some(data) = appends.pop_front() {
+            file.borrow_mut().extend(data);
+        }
+        Ok(())
+    });
+    follow.poll_interval = Duration::ZERO;
+
+    let lines: Vec<_> = BufReader::new(follow).lines().map(|x| x.unwrap()).collect();
+    assert_eq!(lines, ["v 10400 3", "c 1", "+ 0", "c 2", "- 0"]);
+    // the input was idle for 600ms twice, and for 1200ms at the end
+    assert_eq!(idle_calls, 5);
+}